@@ -3,9 +3,11 @@ use crate::background::{BackgroundBackend, PendingBackgroundResponses};
 use crate::clipboard::Clipboard;
 use crate::dialog::{CopySelect, Message};
 use crate::input_capture::InputCapture;
+use crate::privacy::Privacy;
 use crate::remotedesktop::RemoteDesktopBackend;
 use crate::screencast::ScreenCastBackend;
 use crate::screenshot::ScreenShotBackend;
+use crate::session_manager::SessionManager;
 use crate::settings::XDG_CONFIG_HOME_DIR;
 use crate::settings::{AccentColor, SETTING_CONFIG, SettingsBackend, SettingsConfig};
 use futures::{
@@ -61,7 +63,12 @@ async fn update_settings<'a>(
     dialog_sender: &mut Sender<Message>,
 ) {
     let mut config = SETTING_CONFIG.lock().await;
+    let privacy_mode = config.privacy_mode;
     *config = SettingsConfig::config_from_file();
+    // Only a change of the key counts, so a D-Bus toggle survives unrelated edits
+    if config.privacy_mode != privacy_mode {
+        crate::privacy::set_privacy_mode(config.privacy_mode);
+    }
     let prefers_dark = config.prefers_dark();
     let _ = SettingsBackend::setting_changed(
         signal_context,
@@ -139,11 +146,13 @@ pub async fn backend(
     receiver_cast: Receiver<CopySelect>,
     receiver_remote: Receiver<CopySelect>,
     receiver_background: UnboundedReceiver<CopySelect>,
+    receiver_stop_sharing: UnboundedReceiver<String>,
 ) -> anyhow::Result<()> {
     let mut settings_dialog_sender = sender.clone();
-    let toplevel_capture_support = libwayshot::WayshotConnection::new()
-        .map(|conn| conn.toplevel_capture_support())
-        .unwrap_or(false);
+    let sharing_dialog_sender = sender.clone();
+    let (toplevel_capture_support, cursor_metadata_support) = libwayshot::WayshotConnection::new()
+        .map(|conn| (conn.toplevel_capture_support(), conn.image_copy_support()))
+        .unwrap_or((false, false));
     let pending_background_responses: PendingBackgroundResponses =
         Arc::new(Mutex::new(Default::default()));
     let conn = connection::Builder::session()?
@@ -160,8 +169,10 @@ pub async fn backend(
             "/org/freedesktop/portal/desktop",
             ScreenCastBackend {
                 toplevel_capture_support,
+                cursor_metadata_support,
+                virtual_outputs: crate::virtual_output::backend(),
                 sender: sender.clone(),
-                receiver: receiver_cast,
+                receiver: Arc::new(Mutex::new(receiver_cast)),
             },
         )?
         .serve_at(
@@ -178,14 +189,27 @@ pub async fn backend(
         .serve_at("/org/freedesktop/portal/desktop", SettingsBackend)?
         .serve_at("/org/freedesktop/portal/desktop", InputCapture::default())?
         .serve_at("/org/freedesktop/portal/desktop", Clipboard)?
+        .serve_at("/org/freedesktop/portal/desktop", SessionManager)?
+        .serve_at("/org/freedesktop/portal/desktop", Privacy)?
         .build()
         .await?;
 
     set_connection(conn).await;
+    crate::privacy::set_privacy_mode(SETTING_CONFIG.lock().await.privacy_mode);
+    tokio::spawn(crate::privacy::watch_privacy_mode(get_connection().await));
+    tokio::spawn(crate::privacy::toggle_on_signal());
+    tokio::spawn(crate::logind::watch_session_lock());
     tokio::spawn(crate::background::route_background_dialog_responses(
         receiver_background,
         pending_background_responses,
     ));
+    tokio::spawn(crate::sharing::forward_sharing_sessions(
+        sharing_dialog_sender,
+    ));
+    tokio::spawn(crate::sharing::route_stop_sharing_requests(
+        receiver_stop_sharing,
+        get_connection().await,
+    ));
 
     let background_connection = get_connection().await;
     tokio::spawn(async move {
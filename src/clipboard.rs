@@ -2,6 +2,7 @@ mod wayland_backend;
 
 use std::{
     collections::HashMap,
+    io::Write,
     sync::{Arc, LazyLock},
     time::Duration,
 };
@@ -23,6 +24,9 @@ use wayland_backend::{ClipboardRequest, ClipboardThread, OwnerState, TransferEve
 const SIGNAL_QUEUE_DEPTH: usize = 64;
 const INIT_TIMEOUT: Duration = Duration::from_secs(5);
 const WORKER_REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+const SCREENSHOT_MIME_TYPE: &str = "image/png";
+// only names the worker in logs, screenshots have no session
+const SCREENSHOT_WORKER_PATH: &str = "/org/freedesktop/portal/desktop/screenshot";
 
 static CLIPBOARD_SESSION: LazyLock<Arc<Mutex<HashMap<OwnedObjectPath, ClipboardEntry>>>> =
     LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
@@ -353,3 +357,103 @@ async fn forward_clipboard_signals(
         }
     }
 }
+
+/// Put a screenshot on the regular clipboard. A worker of its own serves the bytes until
+/// another client takes the selection, so no remote desktop session is needed
+pub(crate) async fn offer_screenshot(png: Vec<u8>) -> anyhow::Result<()> {
+    let key = OwnedObjectPath::try_from(SCREENSHOT_WORKER_PATH)?;
+    let (transfer_tx, transfer_rx) = mpsc::channel(SIGNAL_QUEUE_DEPTH);
+    let (owner_tx, mut owner_rx) = watch::channel(None);
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let thread = ClipboardThread::spawn(transfer_tx, owner_tx, ready_tx, key)?;
+    tokio::time::timeout(INIT_TIMEOUT, ready_rx)
+        .await
+        .map_err(|_| anyhow::anyhow!("clipboard worker init timed out"))?
+        .map_err(|error| anyhow::anyhow!("clipboard worker died during init: {error}"))?
+        .map_err(|error| anyhow::anyhow!("clipboard init failed: {error}"))?;
+    // the selection from before is not ours, only the changes after ours count
+    owner_rx.borrow_and_update();
+    thread
+        .sender
+        .try_send(ClipboardRequest::SetSelection {
+            mime_types: vec![SCREENSHOT_MIME_TYPE.to_owned()],
+        })
+        .map_err(|error| anyhow::anyhow!("request selection failed: {error}"))?;
+    tokio::spawn(serve_screenshot(thread, png.into(), transfer_rx, owner_rx));
+    Ok(())
+}
+
+async fn serve_screenshot(
+    thread: ClipboardThread,
+    png: Arc<[u8]>,
+    mut transfer_rx: mpsc::Receiver<TransferEvent>,
+    mut owner_rx: watch::Receiver<Option<OwnerState>>,
+) {
+    let mut exit_rx = thread.exit_rx.clone();
+    loop {
+        tokio::select! {
+            _ = watch_closed(&mut exit_rx) => break,
+            changed = owner_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                let replaced = owner_rx
+                    .borrow_and_update()
+                    .as_ref()
+                    .is_some_and(|state| !state.session_is_owner);
+                if replaced {
+                    break;
+                }
+            }
+            maybe = transfer_rx.recv() => {
+                let Some(TransferEvent { serial, .. }) = maybe else {
+                    break;
+                };
+                // a paste may block on a slow reader, the others must not wait for it
+                tokio::spawn(write_screenshot(thread.sender.clone(), png.clone(), serial));
+            }
+        }
+    }
+    // dropping the thread stops the worker, which withdraws the offer if it still stands
+    tracing::debug!("screenshot is off the clipboard");
+}
+
+async fn write_screenshot(
+    sender: calloop::channel::SyncSender<ClipboardRequest>,
+    png: Arc<[u8]>,
+    serial: u32,
+) {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if sender
+        .try_send(ClipboardRequest::Write {
+            serial,
+            sender: reply_tx,
+        })
+        .is_err()
+    {
+        return;
+    }
+    let fd = match tokio::time::timeout(WORKER_REPLY_TIMEOUT, reply_rx).await {
+        Ok(Ok(Ok(fd))) => fd,
+        Ok(Ok(Err(error))) => {
+            tracing::warn!(serial, %error, "cannot write the screenshot to the clipboard");
+            return;
+        }
+        _ => {
+            tracing::warn!(serial, "clipboard worker did not reply in time");
+            return;
+        }
+    };
+    let written =
+        tokio::task::spawn_blocking(move || std::fs::File::from(fd).write_all(&png)).await;
+    let success = matches!(written, Ok(Ok(())));
+    if !success {
+        tracing::warn!(serial, "the screenshot did not reach the pasting client");
+    }
+    let (reply_tx, _reply_rx) = oneshot::channel();
+    let _ = sender.try_send(ClipboardRequest::WriteDone {
+        serial,
+        success,
+        sender: reply_tx,
+    });
+}
@@ -10,7 +10,7 @@ use iced::{
 };
 use iced_layershell::daemon;
 use iced_layershell::reexport::{
-    Anchor, KeyboardInteractivity, NewLayerShellSettings, OutputOption,
+    Anchor, KeyboardInteractivity, Layer, NewLayerShellSettings, OutputOption,
 };
 use iced_layershell::settings::{LayerShellSettings, StartMode};
 use iced_layershell::to_layer_message;
@@ -19,6 +19,12 @@ use libwayshot::output::OutputInfo;
 use libwayshot::region::TopLevel;
 
 use crate::settings::SettingsConfig;
+use crate::sharing::SharingSession;
+
+mod editor;
+
+pub use editor::ScreenshotImage;
+use editor::{Editor, EditorMessage};
 
 const BACKGROUND_PROMPT_QUEUE_CAPACITY: usize = 8;
 const BACKGROUND_PROMPT_TOMBSTONE_CAPACITY: usize = 64;
@@ -33,9 +39,18 @@ const PREVIEW_BUTTON_PADDING: u16 = 8;
 const PREVIEW_BUTTON_LINE_HEIGHT: f32 = 17.0;
 const FOOTER_HEIGHT: f32 = 81.0;
 const FOOTER_BOX_HEIGHT: f32 = 33.0;
+const INDICATOR_WIDTH: u32 = 320;
+const INDICATOR_HEADER_HEIGHT: u32 = 24;
+const INDICATOR_ROW_HEIGHT: u32 = 40;
+const INDICATOR_PADDING: u32 = 12;
+const INDICATOR_MARGIN: i32 = 12;
+const COUNTDOWN_SIZE: u32 = 96;
+const COUNTDOWN_MARGIN: i32 = 48;
+const SHOT_DELAYS: [(u64, &str); 4] = [(0, "No delay"), (3, "3 s"), (5, "5 s"), (10, "10 s")];
 
 const ACCENT: Color = Color::from_rgb8(56, 132, 228);
 const DARK_ACCENT: Color = Color::from_rgb8(21, 83, 158);
+const RECORDING: Color = Color::from_rgb8(224, 49, 49);
 
 const FONT_MEDIUM: Font = Font {
     weight: iced::font::Weight::Medium,
@@ -85,6 +100,7 @@ pub enum GuiMode {
         id_valid: bool,
     },
     BackgroundPrompt,
+    Editor,
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq)]
@@ -106,14 +122,25 @@ struct AreaSelectorGUI {
     sender_cast: Option<Sender<CopySelect>>,
     sender_remote: Option<Sender<CopySelect>>,
     sender_background: Option<UnboundedSender<CopySelect>>,
+    sender_stop_sharing: Option<UnboundedSender<String>>,
+    sharing: Vec<SharingSession>,
+    indicator_id: Option<iced::window::Id>,
     toplevels: Vec<TopLevelInfo>,
     screens: Vec<WlOutputInfo>,
     use_cursor: bool,
+    multiple: bool,
+    virtual_output: bool,
+    selected_sources: Vec<CopySelect>,
     prompt_text: Option<String>,
     active_background_handle: Option<String>,
     background_queue: VecDeque<BackgroundPromptRequest>,
     tombstoned_background_handles: VecDeque<String>,
     prefers_dark: bool,
+    editor: Option<Editor>,
+    shot_delay: u64,
+    copy_to_clipboard: bool,
+    countdown_id: Option<iced::window::Id>,
+    countdown: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -136,9 +163,50 @@ pub enum CopySelect {
     Screen { index: usize, show_cursor: bool },
     All,
     Slurp,
+    ActiveWindow,
+    VirtualOutput,
+    Multiple(Vec<CopySelect>),
     Cancel,
     Permission(PermissionResult),
     BackgroundPermission { handle: String, result: u32 },
+    Annotated(ScreenshotImage),
+    Shot(Box<CopySelect>, ShotOptions),
+    // the countdown overlay is gone, the screen can be captured without it
+    CountdownClosed,
+}
+
+/// Where the dialog of a request goes. A modal dialog is put on the output of its parent
+/// window when that is known, and keeps the keyboard until it is answered
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DialogParent {
+    pub modal: bool,
+    pub output: Option<String>,
+}
+
+impl DialogParent {
+    fn keyboard_interactivity(&self) -> KeyboardInteractivity {
+        if self.modal {
+            KeyboardInteractivity::Exclusive
+        } else {
+            KeyboardInteractivity::OnDemand
+        }
+    }
+
+    fn output_option(&self) -> OutputOption {
+        match &self.output {
+            Some(name) => OutputOption::OutputName(name.clone()),
+            None => OutputOption::Active,
+        }
+    }
+}
+
+/// What the screenshot chooser asks for besides the source, sent along in `CopySelect::Shot`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShotOptions {
+    /// Seconds to wait before the capture, so menus and tooltips can be opened
+    pub delay: u64,
+    pub show_cursor: bool,
+    pub clipboard: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -166,26 +234,33 @@ pub enum Message {
     ImageCopyOpen {
         top_levels: Vec<TopLevelInfo>,
         screens: Vec<WlOutputInfo>,
+        copy_to_clipboard: bool,
+        parent: DialogParent,
     },
     ScreenCastOpen {
         top_levels: Vec<TopLevelInfo>,
         screens: Vec<WlOutputInfo>,
         show_cursor: bool,
+        multiple: bool,
+        virtual_output: bool,
     },
     Selected {
         id: iced::window::Id,
         select: CopySelect,
     },
     ShowModeChange(ShowMode),
+    ToggleSource(CopySelect),
     ReadyShot(Sender<CopySelect>),
     ReadyCast(Sender<CopySelect>),
     ReadyRemote(Sender<CopySelect>),
     ReadyBackground(UnboundedSender<CopySelect>),
+    ReadyStopSharing(UnboundedSender<String>),
     ToggleCursor(bool),
     PermissionDialog {
         message: String,
         mode: PermissionMode,
         id_valid: bool,
+        parent: DialogParent,
     },
     BackgroundPrompt {
         handle: String,
@@ -196,6 +271,15 @@ pub enum Message {
         handle: String,
     },
     ColorSchemeChanged(bool),
+    SharingChanged(Vec<SharingSession>),
+    StopSharing(String),
+    AnnotateOpen(ScreenshotImage),
+    Editor(EditorMessage),
+    ShotDelay(u64),
+    ToggleClipboard(bool),
+    Countdown(u64),
+    CountdownTick,
+    WindowClosed(iced::window::Id),
 }
 
 fn dialog_style(outlined: bool) -> impl Fn(&iced::Theme) -> container::Style + Copy {
@@ -229,6 +313,27 @@ fn dialog_style(outlined: bool) -> impl Fn(&iced::Theme) -> container::Style + C
     }
 }
 
+fn indicator_style(theme: &iced::Theme) -> container::Style {
+    let mut style = dialog_style(false)(theme);
+    style.border = Border {
+        color: RECORDING,
+        width: 2.0,
+        radius: 12.0.into(),
+    };
+    style
+}
+
+fn recording_dot_style(_theme: &iced::Theme) -> container::Style {
+    container::Style {
+        background: Some(Background::Color(RECORDING)),
+        border: Border {
+            radius: 4.0.into(),
+            ..Border::default()
+        },
+        ..container::Style::default()
+    }
+}
+
 fn tab_bar_style(theme: &iced::Theme) -> container::Style {
     let mut style = container::rounded_box(theme);
     style.border.radius = 8.0.into();
@@ -262,17 +367,37 @@ fn bordered_button_style(theme: &iced::Theme, status: button::Status) -> button:
     style
 }
 
+fn source_button_style(
+    selected: bool,
+) -> impl Fn(&iced::Theme, button::Status) -> button::Style + Copy {
+    move |theme, status| {
+        let mut style = bordered_button_style(theme, status);
+        if selected {
+            style.border.color = theme.extended_palette().primary.base.color;
+            style.border.width = 2.0;
+        }
+        style
+    }
+}
+
 fn primary_button_style(theme: &iced::Theme, status: button::Status) -> button::Style {
     let mut style = button::primary(theme, status);
     style.border.radius = 8.0.into();
     style
 }
 
+fn countdown_tick() -> Task<Message> {
+    Task::perform(
+        tokio::time::sleep(std::time::Duration::from_secs(1)),
+        |_| Message::CountdownTick,
+    )
+}
+
 fn divider() -> Element<'static, Message> {
     rule::horizontal(1).style(rule::weak).into()
 }
 
-fn chooser_layer_settings() -> NewLayerShellSettings {
+fn chooser_layer_settings(parent: &DialogParent) -> NewLayerShellSettings {
     NewLayerShellSettings {
         size: Some((
             CHOOSER_WIDTH + CHOOSER_SHADOW_MARGIN * 2,
@@ -280,13 +405,13 @@ fn chooser_layer_settings() -> NewLayerShellSettings {
         )),
         exclusive_zone: None,
         anchor: Anchor::all(),
-        keyboard_interactivity: KeyboardInteractivity::OnDemand,
-        output_option: OutputOption::Active,
+        keyboard_interactivity: parent.keyboard_interactivity(),
+        output_option: parent.output_option(),
         ..Default::default()
     }
 }
 
-fn permission_layer_settings() -> NewLayerShellSettings {
+fn permission_layer_settings(parent: &DialogParent) -> NewLayerShellSettings {
     NewLayerShellSettings {
         size: Some((
             PERMISSION_DIALOG_WIDTH + PERMISSION_DIALOG_SHADOW_MARGIN * 2,
@@ -294,8 +419,58 @@ fn permission_layer_settings() -> NewLayerShellSettings {
         )),
         exclusive_zone: None,
         anchor: Anchor::Top | Anchor::Bottom,
-        keyboard_interactivity: KeyboardInteractivity::OnDemand,
+        keyboard_interactivity: parent.keyboard_interactivity(),
+        output_option: parent.output_option(),
+        ..Default::default()
+    }
+}
+
+/// The editor takes the whole output, above the panels, and the keyboard for its text
+fn editor_layer_settings() -> NewLayerShellSettings {
+    NewLayerShellSettings {
+        layer: Layer::Overlay,
+        exclusive_zone: None,
+        anchor: Anchor::all(),
+        keyboard_interactivity: KeyboardInteractivity::Exclusive,
+        output_option: OutputOption::Active,
+        ..Default::default()
+    }
+}
+
+fn indicator_size(sessions: usize) -> (u32, u32) {
+    (
+        INDICATOR_WIDTH,
+        INDICATOR_HEADER_HEIGHT + INDICATOR_ROW_HEIGHT * sessions as u32 + INDICATOR_PADDING * 3,
+    )
+}
+
+/// A small badge in the corner, it must not take the keyboard from the shared apps
+fn indicator_layer_settings(sessions: usize) -> NewLayerShellSettings {
+    NewLayerShellSettings {
+        size: Some(indicator_size(sessions)),
+        layer: Layer::Overlay,
+        exclusive_zone: None,
+        anchor: Anchor::Top | Anchor::Right,
+        margin: Some((INDICATOR_MARGIN, INDICATOR_MARGIN, 0, INDICATOR_MARGIN)),
+        keyboard_interactivity: KeyboardInteractivity::None,
+        output_option: OutputOption::Active,
+        namespace: Some(String::from("luminous-indicator")),
+        ..Default::default()
+    }
+}
+
+/// The seconds left before a delayed screenshot, it must not take the keyboard from the menus
+/// the user opens meanwhile
+fn countdown_layer_settings() -> NewLayerShellSettings {
+    NewLayerShellSettings {
+        size: Some((COUNTDOWN_SIZE, COUNTDOWN_SIZE)),
+        layer: Layer::Overlay,
+        exclusive_zone: None,
+        anchor: Anchor::Top,
+        margin: Some((COUNTDOWN_MARGIN, 0, 0, 0)),
+        keyboard_interactivity: KeyboardInteractivity::None,
         output_option: OutputOption::Active,
+        namespace: Some(String::from("luminous-countdown")),
         ..Default::default()
     }
 }
@@ -362,18 +537,16 @@ impl AreaSelectorGUI {
         .width(Length::Fill)
         .align_x(Alignment::Center);
 
+        let select = CopySelect::Window {
+            index,
+            show_cursor: self.use_cursor,
+        };
         button(button_context)
-            .on_press(Message::Selected {
-                id,
-                select: CopySelect::Window {
-                    index,
-                    show_cursor: self.use_cursor,
-                },
-            })
             .width(Length::Fill)
             .height(Length::Fixed(PREVIEW_BUTTON_HEIGHT))
             .padding(PREVIEW_BUTTON_PADDING)
-            .style(bordered_button_style)
+            .style(source_button_style(self.is_source_selected(&select)))
+            .on_press(self.select_message(id, select))
             .into()
     }
 
@@ -406,11 +579,11 @@ impl AreaSelectorGUI {
         .align_x(Alignment::Center);
 
         button(button_context)
-            .on_press(Message::Selected { id, select })
             .width(Length::Fill)
             .height(Length::Fixed(PREVIEW_BUTTON_HEIGHT))
             .padding(PREVIEW_BUTTON_PADDING)
-            .style(bordered_button_style)
+            .style(source_button_style(self.is_source_selected(&select)))
+            .on_press(self.select_message(id, select))
             .into()
     }
 
@@ -434,14 +607,52 @@ impl AreaSelectorGUI {
             .width(Length::Fill)
             .height(Length::Fill),
         )
-        .on_press(Message::Selected { id, select })
         .width(Length::Fill)
         .height(Length::Fixed(PREVIEW_BUTTON_HEIGHT))
         .padding(PREVIEW_BUTTON_PADDING)
-        .style(bordered_button_style)
+        .style(source_button_style(self.is_source_selected(&select)))
+        .on_press(self.select_message(id, select))
         .into()
     }
 
+    fn multi_select(&self) -> bool {
+        self.gui_mode == GuiMode::ScreenCast && self.multiple
+    }
+
+    fn is_source_selected(&self, select: &CopySelect) -> bool {
+        self.multi_select() && self.selected_sources.contains(select)
+    }
+
+    /// In multi-select mode a click only toggles the source, the share button confirms.
+    /// A new virtual output is always cast alone
+    fn select_message(&self, id: iced::window::Id, select: CopySelect) -> Message {
+        if self.multi_select() && select != CopySelect::VirtualOutput {
+            Message::ToggleSource(select)
+        } else {
+            Message::Selected { id, select }
+        }
+    }
+
+    /// Screenshot sources are sent with the options of the chooser footer
+    fn with_shot_options(&self, select: CopySelect) -> CopySelect {
+        match select {
+            CopySelect::Screen { .. }
+            | CopySelect::Window { .. }
+            | CopySelect::All
+            | CopySelect::Slurp
+                if self.gui_mode == GuiMode::ScreenShot =>
+            {
+                let options = ShotOptions {
+                    delay: self.shot_delay,
+                    show_cursor: self.use_cursor,
+                    clipboard: self.copy_to_clipboard,
+                };
+                CopySelect::Shot(Box::new(select), options)
+            }
+            select => select,
+        }
+    }
+
     fn tab_button(
         &self,
         label: &'static str,
@@ -489,6 +700,13 @@ impl AreaSelectorGUI {
                 },
             ),
         ]);
+        if self.gui_mode == GuiMode::ScreenCast {
+            button_list.push(self.tab_button(
+                "Area",
+                self.mode == ViewMode::Others,
+                Some(Message::ShowModeChange(ShowMode::Others)),
+            ));
+        }
 
         container(
             Row::from_vec(button_list)
@@ -514,14 +732,25 @@ impl AreaSelectorGUI {
             sender_cast: None,
             sender_remote: None,
             sender_background: None,
+            sender_stop_sharing: None,
+            sharing: Vec::new(),
+            indicator_id: None,
             toplevels: Vec::new(),
             screens: Vec::new(),
             use_cursor: false,
+            multiple: false,
+            virtual_output: false,
+            selected_sources: Vec::new(),
             prompt_text: None,
             active_background_handle: None,
             background_queue: VecDeque::new(),
             tombstoned_background_handles: VecDeque::new(),
             prefers_dark: SettingsConfig::config_from_file().prefers_dark(),
+            editor: None,
+            shot_delay: 0,
+            copy_to_clipboard: false,
+            countdown_id: None,
+            countdown: 0,
         }
     }
 
@@ -546,7 +775,7 @@ impl AreaSelectorGUI {
         let id = iced::window::Id::unique();
         self.window_id = Some(id);
         Task::done(Message::NewLayerShell {
-            settings: permission_layer_settings(),
+            settings: permission_layer_settings(&DialogParent::default()),
             id,
         })
     }
@@ -624,6 +853,31 @@ impl AreaSelectorGUI {
         Task::batch([close_task, next_prompt_task])
     }
 
+    fn update_indicator(&mut self) -> Task<Message> {
+        use iced_runtime::Action;
+        use iced_runtime::window::Action as WindowAction;
+
+        match (self.indicator_id, self.sharing.is_empty()) {
+            (None, true) => Task::none(),
+            (Some(id), true) => {
+                self.indicator_id = None;
+                iced_runtime::task::effect(Action::Window(WindowAction::Close(id)))
+            }
+            (Some(id), false) => Task::done(Message::SizeChange {
+                id,
+                size: indicator_size(self.sharing.len()),
+            }),
+            (None, false) => {
+                let id = iced::window::Id::unique();
+                self.indicator_id = Some(id);
+                Task::done(Message::NewLayerShell {
+                    settings: indicator_layer_settings(self.sharing.len()),
+                    id,
+                })
+            }
+        }
+    }
+
     fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::ShowModeChange(ShowMode::Screens) => {
@@ -638,6 +892,18 @@ impl AreaSelectorGUI {
                 self.mode = ViewMode::Others;
                 Task::none()
             }
+            Message::ToggleSource(select) => {
+                if let Some(index) = self
+                    .selected_sources
+                    .iter()
+                    .position(|selected| *selected == select)
+                {
+                    self.selected_sources.remove(index);
+                } else {
+                    self.selected_sources.push(select);
+                }
+                Task::none()
+            }
             Message::Selected { id, select } => {
                 if self.window_id != Some(id) {
                     return Task::none();
@@ -655,10 +921,11 @@ impl AreaSelectorGUI {
                         }
                         _ => return Task::none(),
                     },
-                    GuiMode::ScreenShot => {
+                    GuiMode::ScreenShot | GuiMode::Editor => {
                         if matches!(select, CopySelect::BackgroundPermission { .. }) {
                             return Task::none();
                         }
+                        let select = self.with_shot_options(select);
                         let _ = self.sender_shot.as_mut().unwrap().try_send(select);
                     }
                     GuiMode::PermissionPrompt { mode, .. } => {
@@ -683,12 +950,18 @@ impl AreaSelectorGUI {
                     self.prompt_text = None;
                     self.active_background_handle = None;
                 }
+                if self.gui_mode == GuiMode::Editor {
+                    self.gui_mode = GuiMode::ScreenShot;
+                    self.editor = None;
+                }
                 self.close_window_and_show_next_background_prompt(id)
             }
 
             Message::ImageCopyOpen {
                 top_levels: toplevels,
                 screens,
+                copy_to_clipboard,
+                parent,
             } => {
                 if self.window_show {
                     let _ = self
@@ -702,13 +975,14 @@ impl AreaSelectorGUI {
                     self.mode = ViewMode::Others;
                 }
                 self.gui_mode = GuiMode::ScreenShot;
+                self.copy_to_clipboard = copy_to_clipboard;
                 self.window_show = true;
                 self.toplevels = toplevels;
                 self.screens = screens;
                 let id = iced::window::Id::unique();
                 self.window_id = Some(id);
                 Task::done(Message::NewLayerShell {
-                    settings: chooser_layer_settings(),
+                    settings: chooser_layer_settings(&parent),
                     id,
                 })
             }
@@ -716,6 +990,8 @@ impl AreaSelectorGUI {
                 top_levels: toplevels,
                 screens,
                 show_cursor,
+                multiple,
+                virtual_output,
             } => {
                 if self.window_show {
                     let _ = self
@@ -729,6 +1005,9 @@ impl AreaSelectorGUI {
                     self.mode = ViewMode::Screens;
                 }
                 self.use_cursor = show_cursor;
+                self.multiple = multiple;
+                self.virtual_output = virtual_output;
+                self.selected_sources.clear();
                 self.gui_mode = GuiMode::ScreenCast;
                 self.window_show = true;
                 self.toplevels = toplevels;
@@ -736,7 +1015,7 @@ impl AreaSelectorGUI {
                 let id = iced::window::Id::unique();
                 self.window_id = Some(id);
                 Task::done(Message::NewLayerShell {
-                    settings: chooser_layer_settings(),
+                    settings: chooser_layer_settings(&DialogParent::default()),
                     id,
                 })
             }
@@ -756,14 +1035,75 @@ impl AreaSelectorGUI {
                 self.sender_background = Some(sender);
                 Task::none()
             }
+            Message::ReadyStopSharing(sender) => {
+                self.sender_stop_sharing = Some(sender);
+                Task::none()
+            }
             Message::ToggleCursor(cursor) => {
                 self.use_cursor = cursor;
+                for select in self.selected_sources.iter_mut() {
+                    if let CopySelect::Window { show_cursor, .. }
+                    | CopySelect::Screen { show_cursor, .. } = select
+                    {
+                        *show_cursor = cursor;
+                    }
+                }
+                Task::none()
+            }
+            Message::ShotDelay(delay) => {
+                self.shot_delay = delay;
+                Task::none()
+            }
+            Message::ToggleClipboard(clipboard) => {
+                self.copy_to_clipboard = clipboard;
+                Task::none()
+            }
+            Message::Countdown(seconds) => {
+                self.countdown = seconds;
+                // A running countdown keeps its ticks and only shows the new number
+                if self.countdown_id.is_some() || seconds == 0 {
+                    return Task::none();
+                }
+                let id = iced::window::Id::unique();
+                self.countdown_id = Some(id);
+                Task::batch([
+                    Task::done(Message::NewLayerShell {
+                        settings: countdown_layer_settings(),
+                        id,
+                    }),
+                    countdown_tick(),
+                ])
+            }
+            Message::CountdownTick => {
+                use iced_runtime::Action;
+                use iced_runtime::window::Action as WindowAction;
+
+                self.countdown = self.countdown.saturating_sub(1);
+                if self.countdown > 0 {
+                    return countdown_tick();
+                }
+                // The id is kept until the window is closed, which is when the shot is taken
+                match self.countdown_id {
+                    Some(id) => iced_runtime::task::effect(Action::Window(WindowAction::Close(id))),
+                    None => Task::none(),
+                }
+            }
+            Message::WindowClosed(id) => {
+                if self.countdown_id == Some(id) {
+                    self.countdown_id = None;
+                    let _ = self
+                        .sender_shot
+                        .as_mut()
+                        .unwrap()
+                        .try_send(CopySelect::CountdownClosed);
+                }
                 Task::none()
             }
             Message::PermissionDialog {
                 message,
                 mode,
                 id_valid,
+                parent,
             } => {
                 if self.window_show {
                     match mode {
@@ -791,7 +1131,7 @@ impl AreaSelectorGUI {
                 let id = iced::window::Id::unique();
                 self.window_id = Some(id);
                 Task::done(Message::NewLayerShell {
-                    settings: permission_layer_settings(),
+                    settings: permission_layer_settings(&parent),
                     id,
                 })
             }
@@ -849,6 +1189,50 @@ impl AreaSelectorGUI {
                 self.prefers_dark = prefers_dark;
                 Task::none()
             }
+            Message::SharingChanged(sessions) => {
+                self.sharing = sessions;
+                self.update_indicator()
+            }
+            Message::AnnotateOpen(image) => {
+                if self.window_show {
+                    let _ = self
+                        .sender_shot
+                        .as_mut()
+                        .unwrap()
+                        .try_send(CopySelect::Cancel);
+                    return Task::none();
+                }
+                self.gui_mode = GuiMode::Editor;
+                self.window_show = true;
+                self.editor = Some(Editor::new(image));
+                let id = iced::window::Id::unique();
+                self.window_id = Some(id);
+                Task::done(Message::NewLayerShell {
+                    settings: editor_layer_settings(),
+                    id,
+                })
+            }
+            Message::Editor(EditorMessage::Done) => {
+                let (Some(editor), Some(id)) = (&self.editor, self.window_id) else {
+                    return Task::none();
+                };
+                let select = CopySelect::Annotated(editor.render());
+                self.update(Message::Selected { id, select })
+            }
+            Message::Editor(message) => match self.editor.as_mut() {
+                Some(editor) => editor.update(message),
+                None => Task::none(),
+            },
+            Message::StopSharing(handle) => {
+                let Some(sender) = &self.sender_stop_sharing else {
+                    tracing::warn!("Cannot stop sharing for {handle}: channel is not ready");
+                    return Task::none();
+                };
+                if let Err(e) = sender.unbounded_send(handle) {
+                    tracing::warn!("Cannot stop sharing: receiver is gone: {e}");
+                }
+                Task::none()
+            }
             _ => unreachable!(),
         }
     }
@@ -1006,13 +1390,141 @@ impl AreaSelectorGUI {
         self.view_prompt(button_row.into())
     }
 
+    fn view_indicator(&self) -> Element<'_, Message> {
+        let header = row![
+            container(
+                Space::new()
+                    .width(Length::Fixed(8.0))
+                    .height(Length::Fixed(8.0))
+            )
+            .style(recording_dot_style),
+            text("Sharing in progress")
+                .size(14)
+                .line_height(Pixels(17.0))
+                .font(FONT_SEMIBOLD),
+        ]
+        .align_y(Alignment::Center)
+        .spacing(8)
+        .height(Length::Fixed(INDICATOR_HEADER_HEIGHT as f32));
+
+        let sessions = self.sharing.iter().map(|session| {
+            let app_name = if session.app_id.is_empty() {
+                "Unknown application"
+            } else {
+                &session.app_id
+            };
+            row![
+                column![
+                    text(app_name)
+                        .size(14)
+                        .line_height(Pixels(17.0))
+                        .font(FONT_MEDIUM),
+                    text(session.kind.description())
+                        .size(12)
+                        .line_height(Pixels(15.0)),
+                ]
+                .width(Length::Fill),
+                button(
+                    text("Stop sharing")
+                        .size(14)
+                        .line_height(Pixels(17.0))
+                        .font(FONT_MEDIUM),
+                )
+                .on_press(Message::StopSharing(session.handle.clone()))
+                .padding([6, 12])
+                .style(primary_button_style),
+            ]
+            .align_y(Alignment::Center)
+            .spacing(10)
+            .height(Length::Fixed(INDICATOR_ROW_HEIGHT as f32))
+            .into()
+        });
+
+        container(
+            column![header]
+                .extend(sessions)
+                .spacing(INDICATOR_PADDING as f32 / 2.0)
+                .width(Length::Fill),
+        )
+        .padding(INDICATOR_PADDING as f32)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .style(indicator_style)
+        .into()
+    }
+
+    fn view_countdown(&self) -> Element<'_, Message> {
+        container(
+            text(self.countdown.to_string())
+                .size(40)
+                .line_height(Pixels(48.0))
+                .font(FONT_SEMIBOLD),
+        )
+        .center_x(Length::Fill)
+        .center_y(Length::Fill)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .style(dialog_style(true))
+        .into()
+    }
+
+    /// The screenshot footer offers a delay, as tabs, and copying to the clipboard
+    fn shot_options(&self) -> Option<Element<'_, Message>> {
+        if self.gui_mode != GuiMode::ScreenShot {
+            return None;
+        }
+        let delays = SHOT_DELAYS.iter().map(|&(delay, label)| {
+            self.tab_button(
+                label,
+                self.shot_delay == delay,
+                Some(Message::ShotDelay(delay)),
+            )
+        });
+        let delay_bar = container(
+            Row::from_iter(delays)
+                .align_y(Alignment::Center)
+                .spacing(4)
+                .width(Length::Fill),
+        )
+        .padding(2)
+        .width(Length::Fixed(300.0))
+        .height(Length::Fixed(FOOTER_BOX_HEIGHT))
+        .style(tab_bar_style);
+        let clipboard_checkbox = checkbox(self.copy_to_clipboard)
+            .label("Copy to clipboard")
+            .on_toggle(Message::ToggleClipboard)
+            .size(16)
+            .spacing(8)
+            .text_size(14)
+            .font(FONT_MEDIUM);
+        Some(
+            row![clipboard_checkbox, delay_bar]
+                .align_y(Alignment::Center)
+                .spacing(10)
+                .into(),
+        )
+    }
+
     fn view(&self, id: iced::window::Id) -> Element<'_, Message> {
+        if self.indicator_id == Some(id) {
+            return self.view_indicator();
+        }
+        if self.countdown_id == Some(id) {
+            return self.view_countdown();
+        }
         if let GuiMode::PermissionPrompt { id_valid, .. } = self.gui_mode {
             return self.view_permission_prompt(id, id_valid);
         }
         if self.gui_mode == GuiMode::BackgroundPrompt {
             return self.view_background_prompt(id);
         }
+        if let Some(editor) = self
+            .editor
+            .as_ref()
+            .filter(|_| self.gui_mode == GuiMode::Editor)
+        {
+            return editor.view(id);
+        }
 
         let selector = self.selector();
 
@@ -1043,14 +1555,26 @@ impl AreaSelectorGUI {
             )
             .height(Length::Fill)
             .into(),
-            ViewMode::Others => grid(vec![
-                self.option_card(id, "Area Select", CopySelect::Slurp),
-                self.option_card(id, "All Screens", CopySelect::All),
-            ])
-            .columns(2)
-            .spacing(12)
-            .height(Length::Shrink)
-            .into(),
+            ViewMode::Others => {
+                let mut cards = vec![self.option_card(id, "Area Select", CopySelect::Slurp)];
+                cards.push(self.option_card(id, "All Screens", CopySelect::All));
+                // The window list is empty when windows cannot be cast
+                if self.gui_mode == GuiMode::ScreenCast && !self.toplevels.is_empty() {
+                    cards.push(self.option_card(id, "Active Window", CopySelect::ActiveWindow));
+                }
+                if self.gui_mode == GuiMode::ScreenCast && self.virtual_output {
+                    cards.push(self.option_card(
+                        id,
+                        "New Virtual Output",
+                        CopySelect::VirtualOutput,
+                    ));
+                }
+                grid(cards)
+                    .columns(2)
+                    .spacing(12)
+                    .height(Length::Shrink)
+                    .into()
+            }
         };
         let content = container(content)
             .padding(10)
@@ -1083,16 +1607,34 @@ impl AreaSelectorGUI {
         .padding([8, 16])
         .style(bordered_button_style);
 
+        let share_button = self.multi_select().then(|| {
+            button(
+                text("Share")
+                    .size(14)
+                    .line_height(Pixels(17.0))
+                    .font(FONT_MEDIUM),
+            )
+            .on_press_maybe(
+                (!self.selected_sources.is_empty()).then(|| Message::Selected {
+                    id,
+                    select: CopySelect::Multiple(self.selected_sources.clone()),
+                }),
+            )
+            .height(Length::Fixed(33.0))
+            .padding([8, 16])
+            .style(primary_button_style)
+        });
+
         let footer = container(
-            row![
-                cursor_checkbox,
-                Space::new().width(Length::Fill),
-                cancel_button
-            ]
-            .align_y(Alignment::Center)
-            .spacing(10)
-            .width(Length::Fill)
-            .height(Length::Fixed(FOOTER_BOX_HEIGHT)),
+            row![cursor_checkbox]
+                .push(self.shot_options())
+                .push(Space::new().width(Length::Fill))
+                .push(cancel_button)
+                .push(share_button)
+                .align_y(Alignment::Center)
+                .spacing(10)
+                .width(Length::Fill)
+                .height(Length::Fixed(FOOTER_BOX_HEIGHT)),
         )
         .padding([24, 0])
         .width(Length::Fill)
@@ -1141,7 +1683,7 @@ impl AreaSelectorGUI {
     }
 
     fn subscription(&self) -> iced::Subscription<Message> {
-        iced::Subscription::run(|| {
+        let backend = iced::Subscription::run(|| {
             iced::stream::channel(100, |mut output: Sender<Message>| async move {
                 use iced::futures::channel::mpsc::{channel, unbounded};
                 use iced::futures::sink::SinkExt;
@@ -1149,12 +1691,16 @@ impl AreaSelectorGUI {
                 let (sender_cast, receiver_cast) = channel(100);
                 let (sender_remote, receiver_remote) = channel(100);
                 let (sender_background, receiver_background) = unbounded();
+                let (sender_stop_sharing, receiver_stop_sharing) = unbounded();
                 let _ = output.send(Message::ReadyShot(sender_shot)).await;
                 let _ = output.send(Message::ReadyCast(sender_cast)).await;
                 let _ = output.send(Message::ReadyRemote(sender_remote)).await;
                 let _ = output
                     .send(Message::ReadyBackground(sender_background))
                     .await;
+                let _ = output
+                    .send(Message::ReadyStopSharing(sender_stop_sharing))
+                    .await;
 
                 let _ = crate::backend::backend(
                     output,
@@ -1162,10 +1708,15 @@ impl AreaSelectorGUI {
                     receiver_cast,
                     receiver_remote,
                     receiver_background,
+                    receiver_stop_sharing,
                 )
                 .await;
             })
-        })
+        });
+        iced::Subscription::batch([
+            backend,
+            iced::window::close_events().map(Message::WindowClosed),
+        ])
     }
     fn theme(&self, _id: iced::window::Id) -> Option<iced::Theme> {
         Some(dialog_theme(self.prefers_dark))
@@ -0,0 +1,764 @@
+use std::sync::{Arc, LazyLock};
+
+use ::image::RgbaImage;
+use ::image::imageops::FilterType;
+use ab_glyph::{Font, FontVec, PxScale, ScaleFont, point};
+use iced::widget::{
+    Space, button, column, container, image, mouse_area, responsive, row, text, text_input,
+};
+use iced::{Alignment, Background, Border, Color, Element, Length, Pixels, Point, Task};
+use tiny_skia::{
+    BlendMode, FillRule, LineCap, LineJoin, Paint, PathBuilder, PixmapMut, Rect, Stroke, Transform,
+};
+
+use super::{
+    FONT_MEDIUM, Message, bordered_button_style, dialog_style, primary_button_style, tab_bar_style,
+    tab_style,
+};
+
+// The preview is redrawn on every move of a drag, so it is kept small
+const PREVIEW_MAX_WIDTH: u32 = 1920;
+const PREVIEW_MAX_HEIGHT: u32 = 1200;
+const TOOLBAR_HEIGHT: f32 = 33.0;
+const HIGHLIGHT: [u8; 3] = [255, 230, 0];
+const COLORS: [[u8; 3]; 5] = [
+    [224, 49, 49],
+    [255, 200, 0],
+    [47, 158, 68],
+    [56, 132, 228],
+    [255, 255, 255],
+];
+
+static TEXT_INPUT_ID: LazyLock<iced::widget::Id> =
+    LazyLock::new(|| iced::widget::Id::new("luminous-editor-text"));
+
+// Looking through the system fonts takes a while, so it happens once and only for text
+static FONT: LazyLock<Option<FontVec>> = LazyLock::new(|| {
+    let mut database = fontdb::Database::new();
+    database.load_system_fonts();
+    let id = database.query(&fontdb::Query {
+        families: &[fontdb::Family::SansSerif],
+        ..Default::default()
+    })?;
+    database.with_face_data(id, |data, index| {
+        FontVec::try_from_vec_and_index(data.to_vec(), index).ok()
+    })?
+});
+
+/// A screenshot going to the editor or coming out of it
+#[derive(Clone)]
+pub struct ScreenshotImage(Arc<RgbaImage>);
+
+impl ScreenshotImage {
+    pub fn new(image: RgbaImage) -> Self {
+        Self(Arc::new(image))
+    }
+
+    pub fn into_image(self) -> RgbaImage {
+        Arc::unwrap_or_clone(self.0)
+    }
+}
+
+impl std::fmt::Debug for ScreenshotImage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ScreenshotImage({}x{})", self.0.width(), self.0.height())
+    }
+}
+
+// Only the same image is equal, the pixels are not compared
+impl PartialEq for ScreenshotImage {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Tool {
+    Crop,
+    #[default]
+    Arrow,
+    Rectangle,
+    Text,
+    Pixelate,
+    Highlight,
+}
+
+impl Tool {
+    const ALL: [Tool; 6] = [
+        Tool::Crop,
+        Tool::Arrow,
+        Tool::Rectangle,
+        Tool::Text,
+        Tool::Pixelate,
+        Tool::Highlight,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Tool::Crop => "Crop",
+            Tool::Arrow => "Arrow",
+            Tool::Rectangle => "Rectangle",
+            Tool::Text => "Text",
+            Tool::Pixelate => "Pixelate",
+            Tool::Highlight => "Highlight",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum EditorMessage {
+    Tool(Tool),
+    Color(usize),
+    // where the cursor is over the image, from 0 to 1 in both directions
+    Moved(Point),
+    Pressed,
+    Released,
+    TextChanged(String),
+    TextSubmitted,
+    Undo,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+enum Annotation {
+    Arrow {
+        from: Point,
+        to: Point,
+        color: [u8; 3],
+    },
+    Rectangle {
+        from: Point,
+        to: Point,
+        color: [u8; 3],
+    },
+    Text {
+        position: Point,
+        content: String,
+        color: [u8; 3],
+    },
+    Pixelate {
+        from: Point,
+        to: Point,
+    },
+    Highlight {
+        from: Point,
+        to: Point,
+    },
+}
+
+/// Crops and draws on a screenshot before it goes to the app. Everything is kept in pixels of
+/// the screenshot and only drawn into it once the user is done
+pub(super) struct Editor {
+    image: ScreenshotImage,
+    preview_base: RgbaImage,
+    preview: image::Handle,
+    tool: Tool,
+    color: usize,
+    annotations: Vec<Annotation>,
+    crop: Option<(Point, Point)>,
+    cursor: Point,
+    drag: Option<Point>,
+    text: Option<(Point, String)>,
+}
+
+impl std::fmt::Debug for Editor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Editor")
+            .field("image", &self.image)
+            .field("tool", &self.tool)
+            .field("annotations", &self.annotations)
+            .field("crop", &self.crop)
+            .finish()
+    }
+}
+
+impl Editor {
+    pub(super) fn new(image: ScreenshotImage) -> Self {
+        let (width, height) = image.0.dimensions();
+        let scale = (PREVIEW_MAX_WIDTH as f32 / width as f32)
+            .min(PREVIEW_MAX_HEIGHT as f32 / height as f32)
+            .min(1.0);
+        let preview_base = if scale < 1.0 {
+            ::image::imageops::resize(
+                image.0.as_ref(),
+                ((width as f32 * scale) as u32).max(1),
+                ((height as f32 * scale) as u32).max(1),
+                FilterType::Triangle,
+            )
+        } else {
+            image.0.as_ref().clone()
+        };
+        let mut editor = Self {
+            image,
+            preview: image::Handle::from_rgba(
+                preview_base.width(),
+                preview_base.height(),
+                preview_base.as_raw().clone(),
+            ),
+            preview_base,
+            tool: Tool::default(),
+            color: 0,
+            annotations: Vec::new(),
+            crop: None,
+            cursor: Point::ORIGIN,
+            drag: None,
+            text: None,
+        };
+        editor.redraw_preview();
+        editor
+    }
+
+    pub(super) fn update(&mut self, message: EditorMessage) -> Task<Message> {
+        match message {
+            EditorMessage::Tool(tool) => {
+                self.commit_text();
+                self.tool = tool;
+            }
+            EditorMessage::Color(color) => self.color = color,
+            EditorMessage::Moved(position) => {
+                let (width, height) = self.image.0.dimensions();
+                self.cursor = Point::new(
+                    position.x.clamp(0.0, 1.0) * width as f32,
+                    position.y.clamp(0.0, 1.0) * height as f32,
+                );
+                if self.drag.is_none() {
+                    return Task::none();
+                }
+            }
+            EditorMessage::Pressed if self.tool == Tool::Text => {
+                self.commit_text();
+                self.text = Some((self.cursor, String::new()));
+                return iced::widget::operation::focus(TEXT_INPUT_ID.clone());
+            }
+            EditorMessage::Pressed => self.drag = Some(self.cursor),
+            EditorMessage::Released => {
+                let Some(start) = self.drag.take() else {
+                    return Task::none();
+                };
+                self.finish_drag(start, self.cursor);
+            }
+            EditorMessage::TextChanged(content) => {
+                if let Some((_, text)) = self.text.as_mut() {
+                    *text = content;
+                }
+            }
+            EditorMessage::TextSubmitted => self.commit_text(),
+            EditorMessage::Undo => {
+                if self.text.take().is_none() && self.annotations.pop().is_none() {
+                    self.crop = None;
+                }
+            }
+            // The dialog renders the image and sends it on
+            EditorMessage::Done => return Task::none(),
+        }
+        self.redraw_preview();
+        Task::none()
+    }
+
+    fn finish_drag(&mut self, from: Point, to: Point) {
+        // A click is no shape
+        if (from.x - to.x).abs() < 2.0 && (from.y - to.y).abs() < 2.0 {
+            return;
+        }
+        let color = COLORS[self.color];
+        let annotation = match self.tool {
+            Tool::Crop => {
+                self.crop = Some((from, to));
+                return;
+            }
+            Tool::Arrow => Annotation::Arrow { from, to, color },
+            Tool::Rectangle => Annotation::Rectangle { from, to, color },
+            Tool::Pixelate => Annotation::Pixelate { from, to },
+            Tool::Highlight => Annotation::Highlight { from, to },
+            Tool::Text => return,
+        };
+        self.annotations.push(annotation);
+    }
+
+    fn commit_text(&mut self) {
+        if let Some((position, content)) = self.text.take()
+            && !content.trim().is_empty()
+        {
+            self.annotations.push(Annotation::Text {
+                position,
+                content,
+                color: COLORS[self.color],
+            });
+        }
+    }
+
+    /// The annotations in progress, they are drawn but not yet kept
+    fn pending(&self) -> Option<Annotation> {
+        if let Some((position, content)) = &self.text {
+            return Some(Annotation::Text {
+                position: *position,
+                content: content.clone(),
+                color: COLORS[self.color],
+            });
+        }
+        let from = self.drag?;
+        let to = self.cursor;
+        let color = COLORS[self.color];
+        match self.tool {
+            Tool::Arrow => Some(Annotation::Arrow { from, to, color }),
+            Tool::Rectangle => Some(Annotation::Rectangle { from, to, color }),
+            Tool::Pixelate => Some(Annotation::Pixelate { from, to }),
+            Tool::Highlight => Some(Annotation::Highlight { from, to }),
+            Tool::Crop | Tool::Text => None,
+        }
+    }
+
+    fn redraw_preview(&mut self) {
+        let mut preview = self.preview_base.clone();
+        let scale = preview.width() as f32 / self.image.0.width() as f32;
+        let line_width = line_width(&self.image.0);
+        let pending = self.pending();
+        draw(
+            &mut preview,
+            self.annotations.iter().chain(pending.as_ref()),
+            scale,
+            line_width,
+        );
+        let crop = match (self.tool, self.drag) {
+            (Tool::Crop, Some(start)) => Some((start, self.cursor)),
+            _ => self.crop,
+        };
+        if let Some((from, to)) = crop {
+            shade_outside(&mut preview, from, to, scale);
+        }
+        self.preview =
+            image::Handle::from_rgba(preview.width(), preview.height(), preview.into_raw());
+    }
+
+    /// The screenshot with every annotation drawn in and the crop applied
+    pub(super) fn render(&self) -> ScreenshotImage {
+        let mut image = self.image.0.as_ref().clone();
+        let line_width = line_width(&image);
+        let pending = self.pending();
+        draw(
+            &mut image,
+            self.annotations.iter().chain(pending.as_ref()),
+            1.0,
+            line_width,
+        );
+        if let Some((from, to)) = self.crop
+            && let Some((left, top, right, bottom)) = pixel_bounds(from, to, &image, 1.0)
+        {
+            image = ::image::imageops::crop_imm(&image, left, top, right - left, bottom - top)
+                .to_image();
+        }
+        ScreenshotImage::new(image)
+    }
+
+    pub(super) fn view(&self, id: iced::window::Id) -> Element<'_, Message> {
+        let tools = row(Tool::ALL.into_iter().map(|tool| {
+            button(text(tool.label()).size(14).font(FONT_MEDIUM))
+                .on_press(Message::Editor(EditorMessage::Tool(tool)))
+                .padding([8, 12])
+                .style(tab_style(self.tool == tool))
+                .into()
+        }))
+        .spacing(4);
+        let tools = container(tools).padding(4).style(tab_bar_style);
+
+        let colors = row(COLORS.iter().enumerate().map(|(index, color)| {
+            button(
+                Space::new()
+                    .width(Length::Fixed(16.0))
+                    .height(Length::Fixed(16.0)),
+            )
+            .on_press(Message::Editor(EditorMessage::Color(index)))
+            .padding(4)
+            .style(swatch_style(*color, self.color == index))
+            .into()
+        }))
+        .spacing(6)
+        .align_y(Alignment::Center);
+
+        let text_entry: Element<'_, Message> = match &self.text {
+            Some((_, content)) => text_input("Type, then press Enter", content)
+                .id(TEXT_INPUT_ID.clone())
+                .on_input(|content| Message::Editor(EditorMessage::TextChanged(content)))
+                .on_submit(Message::Editor(EditorMessage::TextSubmitted))
+                .width(Length::Fixed(240.0))
+                .into(),
+            None => Space::new().width(Length::Fixed(240.0)).into(),
+        };
+
+        let action = |label: &'static str, message: Message, primary: bool| {
+            button(
+                text(label)
+                    .size(14)
+                    .line_height(Pixels(17.0))
+                    .font(FONT_MEDIUM),
+            )
+            .on_press(message)
+            .height(Length::Fixed(TOOLBAR_HEIGHT))
+            .padding([8, 16])
+            .style(if primary {
+                primary_button_style
+            } else {
+                bordered_button_style
+            })
+        };
+
+        let toolbar = row![
+            tools,
+            colors,
+            text_entry,
+            Space::new().width(Length::Fill),
+            action("Undo", Message::Editor(EditorMessage::Undo), false),
+            action(
+                "Cancel",
+                Message::Selected {
+                    id,
+                    select: super::CopySelect::Cancel,
+                },
+                false
+            ),
+            action("Done", Message::Editor(EditorMessage::Done), true),
+        ]
+        .spacing(12)
+        .align_y(Alignment::Center)
+        .width(Length::Fill);
+
+        let handle = self.preview.clone();
+        let (preview_width, preview_height) = self.preview_base.dimensions();
+        let canvas = responsive(move |size| {
+            let scale =
+                (size.width / preview_width as f32).min(size.height / preview_height as f32);
+            let (width, height) = (preview_width as f32 * scale, preview_height as f32 * scale);
+            let picture = mouse_area(
+                image(handle.clone())
+                    .width(Length::Fixed(width))
+                    .height(Length::Fixed(height)),
+            )
+            .on_move(move |position| {
+                Message::Editor(EditorMessage::Moved(Point::new(
+                    position.x / width,
+                    position.y / height,
+                )))
+            })
+            .on_press(Message::Editor(EditorMessage::Pressed))
+            .on_release(Message::Editor(EditorMessage::Released))
+            // A drag which leaves the image ends at its edge
+            .on_exit(Message::Editor(EditorMessage::Released))
+            .interaction(iced::mouse::Interaction::Crosshair);
+            container(picture)
+                .center_x(Length::Fill)
+                .center_y(Length::Fill)
+                .into()
+        });
+
+        container(
+            column![toolbar, canvas]
+                .spacing(16)
+                .width(Length::Fill)
+                .height(Length::Fill),
+        )
+        .padding(24)
+        .width(Length::Fill)
+        .height(Length::Fill)
+        .style(dialog_style(false))
+        .into()
+    }
+}
+
+fn swatch_style(
+    color: [u8; 3],
+    selected: bool,
+) -> impl Fn(&iced::Theme, button::Status) -> button::Style + Copy {
+    move |theme, _status| {
+        let palette = theme.extended_palette();
+        button::Style {
+            background: Some(Background::Color(Color::from_rgb8(
+                color[0], color[1], color[2],
+            ))),
+            border: Border {
+                color: if selected {
+                    palette.primary.strong.color
+                } else {
+                    palette.background.strong.color
+                },
+                width: if selected { 3.0 } else { 1.0 },
+                radius: 12.0.into(),
+            },
+            ..button::Style::default()
+        }
+    }
+}
+
+/// Lines stay visible on large screenshots
+fn line_width(image: &RgbaImage) -> f32 {
+    (image.width().max(image.height()) as f32 / 400.0).max(3.0)
+}
+
+/// The pixels a rectangle between two points covers, clamped to the image
+fn pixel_bounds(
+    from: Point,
+    to: Point,
+    image: &RgbaImage,
+    scale: f32,
+) -> Option<(u32, u32, u32, u32)> {
+    let clamp = |value: f32, limit: u32| ((value * scale).round().max(0.0) as u32).min(limit);
+    let left = clamp(from.x.min(to.x), image.width());
+    let right = clamp(from.x.max(to.x), image.width());
+    let top = clamp(from.y.min(to.y), image.height());
+    let bottom = clamp(from.y.max(to.y), image.height());
+    (right > left && bottom > top).then_some((left, top, right, bottom))
+}
+
+fn paint(color: [u8; 3]) -> Paint<'static> {
+    let mut paint = Paint {
+        anti_alias: true,
+        ..Default::default()
+    };
+    paint.set_color_rgba8(color[0], color[1], color[2], u8::MAX);
+    paint
+}
+
+/// Draw the annotations into the image, whose pixels are `scale` times the ones the
+/// annotations are in. The image is opaque, so tiny-skia can work on it as it is
+fn draw<'a>(
+    image: &mut RgbaImage,
+    annotations: impl Iterator<Item = &'a Annotation>,
+    scale: f32,
+    line_width: f32,
+) {
+    let line_width = line_width * scale;
+    let scaled = |point: Point| (point.x * scale, point.y * scale);
+    for annotation in annotations {
+        match annotation {
+            Annotation::Pixelate { from, to } => {
+                if let Some(bounds) = pixel_bounds(*from, *to, image, scale) {
+                    pixelate(image, bounds, (line_width * 3.0).max(2.0) as u32);
+                }
+            }
+            Annotation::Text {
+                position,
+                content,
+                color,
+            } => draw_text(image, scaled(*position), content, line_width * 6.0, *color),
+            Annotation::Arrow { from, to, color } => {
+                let (from, to) = (scaled(*from), scaled(*to));
+                let (width, height) = image.dimensions();
+                let Some(mut pixmap) = PixmapMut::from_bytes(image, width, height) else {
+                    continue;
+                };
+                let angle = (to.1 - from.1).atan2(to.0 - from.0);
+                let head = line_width * 4.0;
+                let wing = |offset: f32| {
+                    (
+                        to.0 - head * (angle + offset).cos(),
+                        to.1 - head * (angle + offset).sin(),
+                    )
+                };
+                let (left, right) = (wing(0.45), wing(-0.45));
+                // The shaft ends inside the head, so its round cap does not poke out
+                let (end_x, end_y) = (
+                    to.0 - head * 0.8 * angle.cos(),
+                    to.1 - head * 0.8 * angle.sin(),
+                );
+                let mut shaft = PathBuilder::new();
+                shaft.move_to(from.0, from.1);
+                shaft.line_to(end_x, end_y);
+                let mut tip = PathBuilder::new();
+                tip.move_to(to.0, to.1);
+                tip.line_to(left.0, left.1);
+                tip.line_to(right.0, right.1);
+                tip.close();
+                let stroke = Stroke {
+                    width: line_width,
+                    line_cap: LineCap::Round,
+                    ..Default::default()
+                };
+                if let Some(shaft) = shaft.finish() {
+                    pixmap.stroke_path(
+                        &shaft,
+                        &paint(*color),
+                        &stroke,
+                        Transform::identity(),
+                        None,
+                    );
+                }
+                if let Some(tip) = tip.finish() {
+                    pixmap.fill_path(
+                        &tip,
+                        &paint(*color),
+                        FillRule::Winding,
+                        Transform::identity(),
+                        None,
+                    );
+                }
+            }
+            Annotation::Rectangle { from, to, color } => {
+                let (from, to) = (scaled(*from), scaled(*to));
+                let (width, height) = image.dimensions();
+                let (Some(mut pixmap), Some(rect)) = (
+                    PixmapMut::from_bytes(image, width, height),
+                    Rect::from_ltrb(
+                        from.0.min(to.0),
+                        from.1.min(to.1),
+                        from.0.max(to.0),
+                        from.1.max(to.1),
+                    ),
+                ) else {
+                    continue;
+                };
+                let stroke = Stroke {
+                    width: line_width,
+                    line_join: LineJoin::Round,
+                    ..Default::default()
+                };
+                pixmap.stroke_path(
+                    &PathBuilder::from_rect(rect),
+                    &paint(*color),
+                    &stroke,
+                    Transform::identity(),
+                    None,
+                );
+            }
+            // Multiplied like a marker, dark text stays readable under it
+            Annotation::Highlight { from, to } => {
+                let (from, to) = (scaled(*from), scaled(*to));
+                let (width, height) = image.dimensions();
+                let (Some(mut pixmap), Some(rect)) = (
+                    PixmapMut::from_bytes(image, width, height),
+                    Rect::from_ltrb(
+                        from.0.min(to.0),
+                        from.1.min(to.1),
+                        from.0.max(to.0),
+                        from.1.max(to.1),
+                    ),
+                ) else {
+                    continue;
+                };
+                let mut paint = paint(HIGHLIGHT);
+                paint.blend_mode = BlendMode::Multiply;
+                pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+            }
+        }
+    }
+}
+
+fn pixelate(image: &mut RgbaImage, (left, top, right, bottom): (u32, u32, u32, u32), block: u32) {
+    for block_top in (top..bottom).step_by(block as usize) {
+        for block_left in (left..right).step_by(block as usize) {
+            let block_right = (block_left + block).min(right);
+            let block_bottom = (block_top + block).min(bottom);
+            let mut sum = [0u64; 3];
+            for y in block_top..block_bottom {
+                for x in block_left..block_right {
+                    let pixel = image.get_pixel(x, y);
+                    for (total, value) in sum.iter_mut().zip(pixel.0) {
+                        *total += value as u64;
+                    }
+                }
+            }
+            let count = ((block_right - block_left) * (block_bottom - block_top)) as u64;
+            let average = sum.map(|total| (total / count) as u8);
+            for y in block_top..block_bottom {
+                for x in block_left..block_right {
+                    image.put_pixel(
+                        x,
+                        y,
+                        ::image::Rgba([average[0], average[1], average[2], u8::MAX]),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// One line of text whose top left corner is at `position`, without a font it is not drawn
+fn draw_text(
+    image: &mut RgbaImage,
+    position: (f32, f32),
+    content: &str,
+    size: f32,
+    color: [u8; 3],
+) {
+    let Some(font) = FONT.as_ref() else {
+        tracing::warn!("No font is found for the text of the screenshot");
+        return;
+    };
+    let scaled = font.as_scaled(PxScale::from(size));
+    let baseline = position.1 + scaled.ascent();
+    let mut x = position.0;
+    let mut previous = None;
+    for character in content.chars() {
+        let id = scaled.glyph_id(character);
+        if let Some(previous) = previous {
+            x += scaled.kern(previous, id);
+        }
+        previous = Some(id);
+        let glyph = id.with_scale_and_position(size, point(x, baseline));
+        x += scaled.h_advance(id);
+        let Some(outlined) = font.outline_glyph(glyph) else {
+            continue;
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|glyph_x, glyph_y, coverage| {
+            let x = bounds.min.x as i32 + glyph_x as i32;
+            let y = bounds.min.y as i32 + glyph_y as i32;
+            if x < 0 || y < 0 || x >= image.width() as i32 || y >= image.height() as i32 {
+                return;
+            }
+            let pixel = image.get_pixel_mut(x as u32, y as u32);
+            for (channel, value) in pixel.0.iter_mut().zip(color) {
+                *channel = (*channel as f32 * (1.0 - coverage) + value as f32 * coverage) as u8;
+            }
+        });
+    }
+}
+
+/// Darken what the crop cuts away
+fn shade_outside(image: &mut RgbaImage, from: Point, to: Point, scale: f32) {
+    let bounds = pixel_bounds(from, to, image, scale);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let inside = bounds.is_some_and(|(left, top, right, bottom)| {
+            (left..right).contains(&x) && (top..bottom).contains(&y)
+        });
+        if !inside {
+            for channel in &mut pixel.0[..3] {
+                *channel /= 2;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_bounds_orders_and_scales_the_corners() {
+        let image = RgbaImage::new(100, 50);
+        assert_eq!(
+            pixel_bounds(Point::new(10.0, 20.0), Point::new(5.0, 5.0), &image, 2.0),
+            Some((10, 10, 20, 40))
+        );
+    }
+
+    #[test]
+    fn pixel_bounds_clamps_to_the_image() {
+        let image = RgbaImage::new(100, 50);
+        assert_eq!(
+            pixel_bounds(Point::new(-5.0, -5.0), Point::new(80.0, 40.0), &image, 2.0),
+            Some((0, 0, 100, 50))
+        );
+    }
+
+    #[test]
+    fn pixel_bounds_of_an_empty_area_is_none() {
+        let image = RgbaImage::new(100, 50);
+        assert_eq!(
+            pixel_bounds(Point::new(10.0, 10.0), Point::new(10.0, 30.0), &image, 1.0),
+            None
+        );
+        assert_eq!(
+            pixel_bounds(Point::new(60.0, 30.0), Point::new(70.0, 40.0), &image, 2.0),
+            None
+        );
+    }
+}
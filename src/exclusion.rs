@@ -0,0 +1,315 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use libwayshot::region::{Position, Region};
+use serde::Deserialize;
+use tokio::sync::watch;
+
+use crate::pipewirethread::logical_to_buffer_rect;
+use crate::settings::SETTING_CONFIG;
+use crate::sway::{SWAY_GET_TREE, SwayIpc};
+
+/// Tells where the windows of some apps are, through the IPC of a compositor
+pub trait WindowGeometry: Send + Sync {
+    /// The logical regions of the visible windows with one of the app ids
+    fn window_regions(&self, app_ids: &[String]) -> anyhow::Result<Vec<Region>>;
+}
+
+/// The IPC of the running compositor, if we know how to ask it for window geometry
+pub fn backend() -> Option<Arc<dyn WindowGeometry>> {
+    if let Some(ipc) = SwayIpc::from_env() {
+        return Some(Arc::new(ipc));
+    }
+    None
+}
+
+// A cast asks the compositor where the excluded windows are at most this often, a window
+// which moves may show at its new place until the next answer
+const REGIONS_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+const NO_GEOMETRY: &str = "The compositor does not tell where the excluded windows are";
+
+/// The regions of the excluded windows, kept up to date by a thread of its own so a frame
+/// never waits for the compositor. The thread stops once this is dropped
+#[derive(Debug)]
+pub struct WindowRegions {
+    regions: watch::Receiver<Result<Vec<Region>, String>>,
+}
+
+impl WindowRegions {
+    /// The last answer of the compositor, an error when the windows cannot be found
+    pub fn current(&self) -> Result<Vec<Region>, String> {
+        self.regions.borrow().clone()
+    }
+}
+
+/// The apps whose windows never show up in screenshots or casts
+#[derive(Clone, Default)]
+pub struct Exclusion {
+    app_ids: Vec<String>,
+    geometry: Option<Arc<dyn WindowGeometry>>,
+}
+
+impl std::fmt::Debug for Exclusion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Exclusion")
+            .field("app_ids", &self.app_ids)
+            .field("geometry", &self.geometry.is_some())
+            .finish()
+    }
+}
+
+impl Exclusion {
+    pub async fn from_config() -> Self {
+        let app_ids = SETTING_CONFIG.lock().await.excluded_apps.clone();
+        Self {
+            geometry: if app_ids.is_empty() { None } else { backend() },
+            app_ids,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.app_ids.is_empty()
+    }
+
+    pub fn excludes(&self, app_id: &str) -> bool {
+        self.app_ids.iter().any(|excluded| excluded == app_id)
+    }
+
+    /// The logical regions to black out. Without a way to find the windows this fails, so
+    /// the caller can hide everything instead of leaking them
+    pub fn regions(&self) -> anyhow::Result<Vec<Region>> {
+        if self.app_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let Some(geometry) = &self.geometry else {
+            anyhow::bail!(NO_GEOMETRY);
+        };
+        geometry.window_regions(&self.app_ids)
+    }
+
+    /// [`Self::regions`] for async code, the IPC blocks so it is asked from a blocking task
+    pub async fn window_regions(&self) -> anyhow::Result<Vec<Region>> {
+        let exclusion = self.clone();
+        tokio::task::spawn_blocking(move || exclusion.regions()).await?
+    }
+
+    /// Follow the excluded windows for a cast, until they are known the regions are an error
+    pub fn watch(&self) -> WindowRegions {
+        if self.app_ids.is_empty() {
+            return WindowRegions {
+                regions: watch::channel(Ok(Vec::new())).1,
+            };
+        }
+        let Some(geometry) = self.geometry.clone() else {
+            tracing::warn!("{NO_GEOMETRY}, the cast is blacked out while apps are excluded");
+            return WindowRegions {
+                regions: watch::channel(Err(NO_GEOMETRY.to_owned())).1,
+            };
+        };
+        let (sender, regions) =
+            watch::channel(Err("The excluded windows are not known yet".to_owned()));
+        let app_ids = self.app_ids.clone();
+        std::thread::spawn(move || {
+            while !sender.is_closed() {
+                let regions = geometry.window_regions(&app_ids).map_err(|e| e.to_string());
+                // An error is logged once, not every time the compositor is asked
+                if let Err(e) = &regions
+                    && sender.borrow().as_ref().err() != Some(e)
+                {
+                    tracing::error!("Cannot find the excluded windows: {e}");
+                }
+                let _ = sender.send(regions);
+                std::thread::sleep(REGIONS_REFRESH_INTERVAL);
+            }
+        });
+        WindowRegions { regions }
+    }
+
+    /// Black out the excluded windows in packed rgba pixels, a screenshot of the logical
+    /// region `captured`. libwayshot hands out images of another version of the image crate,
+    /// so this works on the bytes
+    pub fn apply(
+        &self,
+        pixels: &mut [u8],
+        width: u32,
+        height: u32,
+        captured: Region,
+    ) -> anyhow::Result<()> {
+        black_out(pixels, width, height, captured, &self.regions()?);
+        Ok(())
+    }
+}
+
+/// Black out the logical `regions` in packed rgba pixels, a screenshot of the logical region
+/// `captured`
+pub fn black_out(pixels: &mut [u8], width: u32, height: u32, captured: Region, regions: &[Region]) {
+    let size = libwayshot::Size { width, height };
+    for &region in regions {
+        let Some(region) = logical_to_buffer_rect(captured, size, region) else {
+            continue;
+        };
+        let left = region.position.x as usize;
+        let right = left + region.size.width as usize;
+        let top = region.position.y as usize;
+        for y in top..top + region.size.height as usize {
+            let row = y * width as usize * 4;
+            for pixel in pixels[row + left * 4..row + right * 4].chunks_exact_mut(4) {
+                pixel.copy_from_slice(&[0, 0, 0, u8::MAX]);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SwayRect {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct SwayWindowProperties {
+    #[serde(default)]
+    class: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SwayNode {
+    rect: SwayRect,
+    #[serde(default)]
+    app_id: Option<String>,
+    // set for xwayland windows, their class is what the toplevel protocols report as app id
+    #[serde(default)]
+    window_properties: Option<SwayWindowProperties>,
+    #[serde(default)]
+    visible: Option<bool>,
+    #[serde(default)]
+    nodes: Vec<SwayNode>,
+    #[serde(default)]
+    floating_nodes: Vec<SwayNode>,
+}
+
+impl SwayNode {
+    fn app_id(&self) -> Option<&str> {
+        self.app_id.as_deref().or_else(|| {
+            self.window_properties
+                .as_ref()
+                .and_then(|properties| properties.class.as_deref())
+        })
+    }
+
+    fn collect_regions(&self, app_ids: &[String], regions: &mut Vec<Region>) {
+        // A window without the visible flag counts as visible, better black than leaked
+        if self
+            .app_id()
+            .is_some_and(|app_id| app_ids.iter().any(|excluded| excluded == app_id))
+            && self.visible.unwrap_or(true)
+        {
+            regions.push(Region {
+                position: Position {
+                    x: self.rect.x,
+                    y: self.rect.y,
+                },
+                size: libwayshot::Size {
+                    width: self.rect.width,
+                    height: self.rect.height,
+                },
+            });
+        }
+        for node in self.nodes.iter().chain(&self.floating_nodes) {
+            node.collect_regions(app_ids, regions);
+        }
+    }
+}
+
+impl WindowGeometry for SwayIpc {
+    fn window_regions(&self, app_ids: &[String]) -> anyhow::Result<Vec<Region>> {
+        let tree: SwayNode = self.request(SWAY_GET_TREE, "")?;
+        let mut regions = Vec::new();
+        tree.collect_regions(app_ids, &mut regions);
+        Ok(regions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: i32, y: i32, width: u32, height: u32) -> Region {
+        Region {
+            position: Position { x, y },
+            size: libwayshot::Size { width, height },
+        }
+    }
+
+    const TREE: &str = r#"{
+        "rect": { "x": 0, "y": 0, "width": 3840, "height": 1080 },
+        "nodes": [{
+            "rect": { "x": 0, "y": 0, "width": 1920, "height": 1080 },
+            "nodes": [
+                {
+                    "rect": { "x": 0, "y": 0, "width": 960, "height": 1080 },
+                    "app_id": "org.keepassxc.KeePassXC",
+                    "visible": true
+                },
+                {
+                    "rect": { "x": 960, "y": 0, "width": 960, "height": 1080 },
+                    "app_id": "foot",
+                    "visible": true
+                },
+                {
+                    "rect": { "x": 0, "y": 0, "width": 1920, "height": 1080 },
+                    "app_id": "org.keepassxc.KeePassXC",
+                    "visible": false
+                }
+            ],
+            "floating_nodes": [
+                {
+                    "rect": { "x": 100, "y": 200, "width": 300, "height": 400 },
+                    "window_properties": { "class": "Signal" }
+                },
+                {
+                    "rect": { "x": 500, "y": 500, "width": 10, "height": 10 },
+                    "app_id": null
+                }
+            ]
+        }]
+    }"#;
+
+    #[test]
+    fn collect_regions_finds_the_visible_excluded_windows() {
+        let tree: SwayNode = serde_json::from_str(TREE).unwrap();
+        let mut regions = Vec::new();
+        tree.collect_regions(
+            &["org.keepassxc.KeePassXC".to_owned(), "Signal".to_owned()],
+            &mut regions,
+        );
+        assert_eq!(
+            regions,
+            [region(0, 0, 960, 1080), region(100, 200, 300, 400)]
+        );
+    }
+
+    #[test]
+    fn black_out_the_captured_region_covers_everything() {
+        let captured = region(10, 20, 2, 2);
+        // a scale 2 output, 4x4 pixels
+        let mut pixels = vec![u8::MAX; 4 * 4 * 4];
+        black_out(&mut pixels, 4, 4, captured, &[captured]);
+        assert!(
+            pixels
+                .chunks_exact(4)
+                .all(|pixel| pixel == [0, 0, 0, u8::MAX])
+        );
+    }
+
+    #[test]
+    fn collect_regions_without_excluded_apps() {
+        let tree: SwayNode = serde_json::from_str(TREE).unwrap();
+        let mut regions = Vec::new();
+        tree.collect_regions(&[], &mut regions);
+        assert!(regions.is_empty());
+    }
+}
@@ -5,13 +5,14 @@ use std::{
 
 use crate::{
     PortalResponse,
+    privacy::privacy_mode,
     remotedesktop::{
         CursorPosition, EIS_SERVER, EisServerMsg, REMOTE_SESSIONS, RemoteControl, RemoteInfo,
         RemoteSessionData, RestoreData, Zone, append_remote_session, disable_eis_listener,
         enable_eis_listener, get_monitor_info_from_socket,
     },
     request::RequestInterface,
-    session::{DeviceType, Session, SessionType, append_session},
+    session::{DeviceType, Session, SessionType, append_session, mark_session_started},
 };
 use enumflags2::BitFlags;
 use reis::eis;
@@ -154,6 +155,9 @@ impl InputCapture {
         options: CreateSessionOptions,
         #[zbus(object_server)] server: &zbus::ObjectServer,
     ) -> zbus::fdo::Result<PortalResponse<CreateSessionRet>> {
+        if privacy_mode() {
+            return Ok(PortalResponse::Cancelled);
+        }
         if (options.capabilities | self.capabilities()) != self.capabilities() {
             return Err(zbus::Error::Failure("Unsupported capability".to_owned()).into());
         }
@@ -177,7 +181,8 @@ impl InputCapture {
                 },
             )
             .await?;
-        let current_session = Session::new(session_handle.clone(), SessionType::InputCapture);
+        let current_session =
+            Session::new(session_handle.clone(), SessionType::InputCapture, app_id);
         append_session(current_session.clone()).await;
         server.at(session_handle.clone(), current_session).await?;
 
@@ -185,6 +190,8 @@ impl InputCapture {
 
         append_remote_session(RemoteSessionData::new(
             session_handle.to_string(),
+            app_id.to_owned(),
+            None,
             None,
             remote_control,
             vec![Zone {
@@ -194,10 +201,11 @@ impl InputCapture {
                 height: height as u32,
             }],
             RestoreData::new(crate::remotedesktop::LuminousData {
-                display: output_name,
+                display: output_name.clone(),
             }),
         ))
         .await;
+        mark_session_started(&session_handle.clone().into(), vec![output_name]).await;
         Ok(PortalResponse::Success(CreateSessionRet {
             capabilities,
             session_id: session_handle.to_string(),
@@ -0,0 +1,72 @@
+use std::sync::LazyLock;
+
+use futures::StreamExt;
+use tokio::sync::watch;
+use zbus::{Result, zvariant};
+
+static SESSION_LOCKED: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+
+#[zbus::proxy(
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1/user/self",
+    interface = "org.freedesktop.login1.User"
+)]
+pub trait User {
+    /// The graphical session of the user
+    #[zbus(property)]
+    fn display(&self) -> Result<(String, zvariant::OwnedObjectPath)>;
+}
+
+#[zbus::proxy(
+    default_service = "org.freedesktop.login1",
+    interface = "org.freedesktop.login1.Session"
+)]
+pub trait Session {
+    #[zbus(property)]
+    fn locked_hint(&self) -> Result<bool>;
+}
+
+/// Whether the screen locker of the session says it is locked. Captures would only show the
+/// lock screen, or whatever leaks past it
+pub fn session_locked() -> bool {
+    *SESSION_LOCKED.borrow()
+}
+
+fn set_session_locked(locked: bool) {
+    SESSION_LOCKED.send_if_modified(|current| {
+        let changed = *current != locked;
+        *current = locked;
+        changed
+    });
+}
+
+/// Follow the LockedHint of the graphical session. Only lockers, or the scripts starting
+/// them, which set it through logind are noticed: swaylock, hyprlock and gtklock do not, and
+/// Wayland does not tell other clients about an ext-session-lock. The portal runs outside of
+/// the session, so it is found through the user
+pub async fn watch_session_lock() {
+    if let Err(e) = watch_locked_hint().await {
+        tracing::info!("Cannot watch the lock state of the session: {e}");
+    }
+}
+
+async fn watch_locked_hint() -> Result<()> {
+    let connection = zbus::Connection::system().await?;
+    let (_, path) = UserProxy::new(&connection).await?.display().await?;
+    let session = SessionProxy::builder(&connection)
+        .path(path)?
+        .build()
+        .await?;
+    let mut changes = session.receive_locked_hint_changed().await;
+    set_session_locked(session.locked_hint().await?);
+    tracing::info!("Following the LockedHint of {}", session.inner().path());
+    while let Some(change) = changes.next().await {
+        let locked = change.get().await?;
+        tracing::info!(
+            "The session is {}",
+            if locked { "locked" } else { "unlocked" }
+        );
+        set_session_locked(locked);
+    }
+    Ok(())
+}
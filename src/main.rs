@@ -3,15 +3,22 @@ mod backend;
 mod background;
 mod clipboard;
 mod dialog;
+mod exclusion;
 mod input_capture;
+mod logind;
+mod privacy;
 mod remotedesktop;
 mod request;
 mod screencast;
 mod screenshot;
 mod session;
+mod session_manager;
 mod settings;
+mod sharing;
+mod sway;
 mod systemd;
 mod utils;
+mod virtual_output;
 
 use std::collections::HashMap;
 use zbus::zvariant;
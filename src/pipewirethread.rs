@@ -1,7 +1,9 @@
 use libwayshot::reexport::FailureReason;
+use libwayshot::region::{Position, Region};
 use libwayshot::{
     WayshotConnection, WayshotTarget,
     reexport::{ExtForeignToplevelHandleV1, WlOutput},
+    screencast::WayshotScreenCast,
 };
 use pipewire::spa::pod::Pod;
 use pipewire::spa::sys as libspa_sys;
@@ -9,60 +11,360 @@ use pipewire::{
     spa::{
         self,
         param::video::{VideoFormat, VideoInfoRaw},
-        pod::{self, serialize::PodSerializer},
+        pod::{self, deserialize::PodDeserializer, serialize::PodSerializer},
     },
     stream::StreamState,
 };
 use std::ffi::c_void;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 use std::{io, os::fd::IntoRawFd, slice};
 use wayland_client::WEnum;
-use wayland_client::protocol::wl_shm::Format;
+use wayland_client::protocol::{wl_buffer::WlBuffer, wl_shm::Format};
 
 use tokio::sync::oneshot;
 
+use crate::exclusion::{Exclusion, WindowRegions};
+use crate::logind::session_locked;
+use crate::session::CursorMode;
+use crate::settings::SETTING_CONFIG;
 use crate::utils::HEADLESS_START;
 
+mod blackout;
+mod capture;
+mod composite;
+mod cursor;
+mod dmabuf;
+mod focus;
+mod placeholder;
+mod scale;
+mod toplevels;
+
+pub(crate) use blackout::logical_to_buffer_rect;
+use blackout::{black_out, output_frame_region};
+use capture::{FrameResult, FrameSession};
+use composite::Composite;
+pub(crate) use composite::logical_extent;
+use cursor::{CursorSnapshot, CursorTracker};
+use dmabuf::{DRM_FORMAT_MOD_INVALID, DmabufAllocator, DmabufBuffer, fourcc_to_wl_shm};
+pub(crate) use focus::FocusTracker;
+use placeholder::{HiddenWindow, gray_pixel, hidden_window_card};
+use scale::{Scale, averages, fit_size};
+use toplevels::find_toplevel;
+
+// Largest cursor image which fits into the cursor metadata
+const CURSOR_META_MAX_SIZE: u32 = 256;
+// Damage beyond this many rectangles is merged into their bounding box
+const DAMAGE_META_MAX_REGIONS: usize = 16;
+// Placeholder frames keep the stream alive, they need no more than a few per second
+const PLACEHOLDER_FRAME_INTERVAL: Duration = Duration::from_millis(250);
+// The stream takes a new source on its next cycle, a paused stream never gets to it
+const RETARGET_TIMEOUT: Duration = Duration::from_secs(5);
+// Windows a stream switched to which are remembered, to tell windows with the same title apart
+const SHOWN_WINDOWS_MAX: usize = 16;
+
 pub struct ScreencastThread {
     node_id: u32,
+    size: libwayshot::Size,
     thread_stop_tx: pipewire::channel::Sender<()>,
+    target_lost_rx: Option<oneshot::Receiver<()>>,
+    retarget_tx: mpsc::Sender<Retarget>,
+}
+
+/// A source to switch a running stream to. Wayland objects cannot move between
+/// connections, so the stream looks it up again on its own one
+#[derive(Debug, Clone)]
+pub enum CastSource {
+    Output(String),
+    Toplevel { app_id: String, title: String },
+}
+
+/// The window with the keyboard focus now, a stream following the focus starts with it
+pub fn active_window(connection: &WayshotConnection) -> anyhow::Result<Option<CastSource>> {
+    Ok(FocusTracker::new(connection)?.focused())
+}
+
+/// The screencast settings a stream starts with
+#[derive(Debug, Clone)]
+struct StreamConfig {
+    max_framerate: u32,
+    hidden_window: HiddenWindow,
+    max_size: Option<libwayshot::Size>,
+    exclusion: Exclusion,
+}
+
+#[derive(Debug)]
+struct Retarget {
+    source: CastSource,
+    reply: oneshot::Sender<anyhow::Result<libwayshot::Size>>,
 }
 
 #[derive(Debug, Clone)]
 pub enum CastTarget {
     TopLevel(ExtForeignToplevelHandleV1),
     Screen(WlOutput),
+    /// A part of an output, `region` is logical and relative to the output
+    Area {
+        output: WlOutput,
+        region: Region,
+        output_size: libwayshot::Size,
+    },
+    /// Every output in one frame, laid out like in the compositor space
+    AllOutputs,
 }
 
 impl CastTarget {
     fn wayshot_target(&self) -> WayshotTarget {
-        match self {
-            CastTarget::Screen(screen) => WayshotTarget::Screen(screen.clone()),
-            CastTarget::TopLevel(toplevel) => WayshotTarget::Toplevel(toplevel.clone()),
-        }
+        self.into()
     }
 }
 
 impl From<&CastTarget> for WayshotTarget {
     fn from(value: &CastTarget) -> Self {
         match value {
-            CastTarget::Screen(screen) => Self::Screen(screen.clone()),
+            CastTarget::Screen(screen) | CastTarget::Area { output: screen, .. } => {
+                Self::Screen(screen.clone())
+            }
             CastTarget::TopLevel(toplevel) => Self::Toplevel(toplevel.clone()),
+            CastTarget::AllOutputs => unreachable!("all outputs are captured one by one"),
+        }
+    }
+}
+
+/// Cuts an area out of the full output frame
+#[derive(Debug, Clone, Copy)]
+struct Crop {
+    region: Region,
+    output_size: libwayshot::Size,
+    source_size: libwayshot::Size,
+}
+
+impl Crop {
+    /// The area in buffer pixels, so it follows the output scale
+    fn buffer_region(&self) -> Region {
+        let source = self.source_size;
+        // Nothing to cut out of an empty frame, and the clamps below need a non empty range
+        if source.width == 0 || source.height == 0 {
+            return Region {
+                position: Position { x: 0, y: 0 },
+                size: source,
+            };
+        }
+        let scale_x = source.width as f64 / self.output_size.width.max(1) as f64;
+        let scale_y = source.height as f64 / self.output_size.height.max(1) as f64;
+        let x = ((self.region.position.x as f64 * scale_x).round() as u32)
+            .min(source.width.saturating_sub(1));
+        let y = ((self.region.position.y as f64 * scale_y).round() as u32)
+            .min(source.height.saturating_sub(1));
+        let width =
+            ((self.region.size.width as f64 * scale_x).round() as u32).clamp(1, source.width - x);
+        let height =
+            ((self.region.size.height as f64 * scale_y).round() as u32).clamp(1, source.height - y);
+        Region {
+            position: Position {
+                x: x as i32,
+                y: y as i32,
+            },
+            size: libwayshot::Size { width, height },
+        }
+    }
+
+    fn cut(&self, source: &File) -> io::Result<Vec<u8>> {
+        let region = self.buffer_region();
+        if region.size.width == 0 || region.size.height == 0 {
+            return Ok(Vec::new());
+        }
+        let source_stride = self.source_size.width as usize * 4;
+        let row_len = region.size.width as usize * 4;
+        let mut rows = vec![0; region.size.height as usize * source_stride];
+        source.read_exact_at(
+            &mut rows,
+            (region.position.y as usize * source_stride) as u64,
+        )?;
+        let x_offset = region.position.x as usize * 4;
+        Ok(rows
+            .chunks_exact(source_stride)
+            .flat_map(|row| &row[x_offset..x_offset + row_len])
+            .copied()
+            .collect())
+    }
+
+    /// Damage of the full frame, clipped to the area and moved into it
+    fn crop_damage(&self, damage: &[Region]) -> Vec<Region> {
+        let area = self.buffer_region();
+        damage
+            .iter()
+            .filter_map(|region| {
+                let left = region.position.x.max(area.position.x);
+                let top = region.position.y.max(area.position.y);
+                let right = (region.position.x + region.size.width as i32)
+                    .min(area.position.x + area.size.width as i32);
+                let bottom = (region.position.y + region.size.height as i32)
+                    .min(area.position.y + area.size.height as i32);
+                (right > left && bottom > top).then(|| Region {
+                    position: Position {
+                        x: left - area.position.x,
+                        y: top - area.position.y,
+                    },
+                    size: libwayshot::Size {
+                        width: (right - left) as u32,
+                        height: (bottom - top) as u32,
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+/// For an area or a scaled stream, the frame is copied into `source`, then cropped and
+/// scaled into the pipewire buffer
+struct CropBuffers {
+    source: File,
+    target: File,
+}
+
+/// What the compositor copies the frame into
+#[derive(Debug)]
+enum CaptureBuffer {
+    Screencast(WayshotScreenCast),
+    /// Allocated by us in the layout negotiated with the client, only with a [`FrameSession`]
+    Dmabuf(DmabufBuffer),
+    /// Drawn on the cpu from the captures of all outputs
+    Composite,
+}
+
+impl CaptureBuffer {
+    fn wl_buffer(&self) -> &WlBuffer {
+        match self {
+            CaptureBuffer::Screencast(cast) => cast.buffer(),
+            CaptureBuffer::Dmabuf(dmabuf) => &dmabuf.buffer,
+            CaptureBuffer::Composite => unreachable!("a composite frame has no wl_buffer"),
+        }
+    }
+}
+
+/// A format the client may take as dmabuf, with the modifiers the compositor can write
+#[derive(Debug, Clone)]
+struct DmabufOffer {
+    format: VideoFormat,
+    fourcc: u32,
+    modifiers: Vec<u64>,
+}
+
+/// The modifier we fixated for an offer, and how many planes its buffers have
+#[derive(Debug, Clone, Copy)]
+struct DmabufChoice {
+    format: VideoFormat,
+    fourcc: u32,
+    modifier: u64,
+    planes: u32,
+}
+
+/// The modifier property of the format the client picked
+#[derive(Debug)]
+enum ModifierProperty {
+    None,
+    Fixed(u64),
+    /// Left open with DONT_FIXATE, the producer picks one of them
+    Choice(Vec<u64>),
+}
+
+/// The modifier property of an EnumFormat param
+#[derive(Debug, Clone, Copy)]
+enum ModifierParam<'a> {
+    None,
+    Fixed(u64),
+    Choice(&'a [u64]),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BufferType {
+    /// Whatever the legacy gbm path of libwayshot can handle
+    Any,
+    MemFd,
+    DmaBuf {
+        planes: u32,
+    },
+}
+
+struct BufferData {
+    cast: CaptureBuffer,
+    crop: Option<CropBuffers>,
+    // the memory of a shm buffer, placeholder frames are written into it
+    memory: Option<File>,
+    // what the chunk holds with video data, it is zero for a buffer with only metadata
+    chunk_size: u32,
+}
+
+impl BufferData {
+    /// Whether we can draw a frame into it, the dmabufs of libwayshot are not ours to map
+    fn writable(&self) -> bool {
+        self.memory.is_some() || matches!(self.cast, CaptureBuffer::Dmabuf(_))
+    }
+
+    /// Write a frame of the stream size, a shm buffer directly and a dmabuf through a mapping
+    fn write_frame(&mut self, frame: &[u8], size: libwayshot::Size) -> io::Result<()> {
+        match (&self.memory, &mut self.cast) {
+            (Some(memory), _) => memory.write_all_at(frame, 0),
+            (None, CaptureBuffer::Dmabuf(dmabuf)) => {
+                dmabuf.write_frame(frame, size.width, size.height)
+            }
+            (None, _) => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        }
+    }
+
+    fn read_frame(&self, size: libwayshot::Size) -> io::Result<Vec<u8>> {
+        match (&self.memory, &self.cast) {
+            (Some(memory), _) => {
+                let mut frame = vec![0; (size.width * size.height * 4) as usize];
+                memory.read_exact_at(&mut frame, 0)?;
+                Ok(frame)
+            }
+            (None, CaptureBuffer::Dmabuf(dmabuf)) => dmabuf.read_frame(size.width, size.height),
+            (None, _) => Err(io::Error::from(io::ErrorKind::Unsupported)),
         }
     }
 }
 
 impl ScreencastThread {
+    /// With `follow_focus` the stream moves to every window which gets the focus, starting
+    /// from the window `target`
     pub async fn start_cast(
-        overlay_cursor: bool,
+        cursor_mode: CursorMode,
         target: CastTarget,
         connection: WayshotConnection,
+        follow_focus: bool,
     ) -> anyhow::Result<Self> {
+        let exclusion = Exclusion::from_config().await;
+        let config = {
+            let config = SETTING_CONFIG.lock().await;
+            StreamConfig {
+                max_framerate: config.screencast_max_fps,
+                hidden_window: HiddenWindow::from_setting(&config.screencast_hidden_window),
+                max_size: config
+                    .screencast_max_resolution
+                    .map(|(width, height)| libwayshot::Size { width, height }),
+                exclusion,
+            }
+        };
         let (tx, rx) = oneshot::channel();
         let (thread_stop_tx, thread_stop_rx) = pipewire::channel::channel::<()>();
+        let (target_lost_tx, target_lost_rx) = oneshot::channel();
+        let (retarget_tx, retarget_rx) = mpsc::channel();
         std::thread::spawn(move || {
-            match start_stream(connection, overlay_cursor, target) {
-                Ok((loop_, listener, _stream, context, node_id_rx)) => {
-                    tx.send(Ok(node_id_rx)).unwrap();
+            match start_stream(
+                connection,
+                cursor_mode,
+                target,
+                follow_focus,
+                config,
+                target_lost_tx,
+                retarget_rx,
+            ) {
+                Ok((loop_, listener, _stream, context, node_id_rx, size)) => {
+                    tx.send(Ok((node_id_rx, size))).unwrap();
                     let weak_loop = loop_.downgrade();
                     let _receiver = thread_stop_rx.attach(loop_.loop_(), move |()| {
                         weak_loop.upgrade().unwrap().quit();
@@ -75,16 +377,46 @@ impl ScreencastThread {
                 Err(err) => tx.send(Err(err)).unwrap(),
             };
         });
+        let (node_id_rx, size) = rx.await??;
         Ok(Self {
-            node_id: rx.await??.await??,
+            node_id: node_id_rx.await??,
+            size,
             thread_stop_tx,
+            target_lost_rx: Some(target_lost_rx),
+            retarget_tx,
         })
     }
 
+    /// Switch the stream to another output or window, the node stays the same and the
+    /// client only sees new params when the size changes
+    pub async fn retarget(&mut self, source: CastSource) -> anyhow::Result<libwayshot::Size> {
+        let (reply, reply_rx) = oneshot::channel();
+        self.retarget_tx
+            .send(Retarget { source, reply })
+            .map_err(|_| anyhow::anyhow!("The stream is stopped"))?;
+        let size = tokio::time::timeout(RETARGET_TIMEOUT, reply_rx)
+            .await
+            .map_err(|_| anyhow::anyhow!("The stream is not running"))?
+            .map_err(|_| anyhow::anyhow!("The stream is stopped"))??;
+        self.size = size;
+        Ok(size)
+    }
+
+    /// Resolves when the window or output of the stream is gone, it is dropped
+    /// without a value when the stream is stopped
+    pub fn take_target_lost(&mut self) -> Option<oneshot::Receiver<()>> {
+        self.target_lost_rx.take()
+    }
+
     pub fn node_id(&self) -> u32 {
         self.node_id
     }
 
+    /// The size of the frames when the stream started or was switched to another source
+    pub fn size(&self) -> libwayshot::Size {
+        self.size
+    }
+
     pub fn stop(&self) {
         let _ = self.thread_stop_tx.send(());
     }
@@ -96,91 +428,824 @@ struct StreamingData {
     connection: WayshotConnection,
     overlay_cursor: bool,
     available_video_formats: Vec<VideoFormat>,
+    // the size of the stream, `capture_size` scaled to what the client asked for
     size: libwayshot::Size,
+    // the size of the captured frame, after the crop
+    capture_size: libwayshot::Size,
+    max_size: Option<libwayshot::Size>,
+    scale: Option<Scale>,
     target: CastTarget,
     gbm_support: bool,
+    crop: Option<Crop>,
+    composite: Option<Composite>,
+    cursor: Option<CursorTracker>,
+    cursor_serial: u64,
+    cursor_sent: Option<CursorSnapshot>,
+    max_framerate: u32,
+    frame_interval: Duration,
+    last_frame: Option<Instant>,
+    session: Option<FrameSession>,
+    pending_buffer: Option<*mut pipewire::sys::pw_buffer>,
+    dmabuf: Option<DmabufAllocator>,
+    dmabuf_offers: Vec<DmabufOffer>,
+    dmabuf_choice: Option<DmabufChoice>,
+    use_dmabuf: bool,
+    target_lost_tx: Option<oneshot::Sender<()>>,
+    hidden_window: HiddenWindow,
+    // set while the window cannot be captured, cleared by the next good frame
+    placeholder: Option<Vec<u8>>,
+    last_good_frame: Option<*mut pipewire::sys::pw_buffer>,
+    retarget_rx: mpsc::Receiver<Retarget>,
+    focus: Option<FocusTracker>,
+    // the identifiers of the windows switched to, the last one most recent. Windows with the
+    // same app id and title are told apart by them
+    shown_windows: Vec<String>,
+    // the followed window is closed, nothing is captured until another one gets the focus
+    waiting_for_focus: bool,
+    exclusion: Exclusion,
+    // followed from the first frame which needs them, a window cast does not
+    window_regions: Option<WindowRegions>,
+    // the excluded windows blacked out in the last frame, in pixels of the stream
+    excluded: Vec<Region>,
+    // nothing is captured while the session is locked, the client gets black frames
+    locked: bool,
 }
 
 impl StreamingData {
     #[allow(clippy::too_many_arguments)]
     fn new(
-        width: u32,
-        height: u32,
+        capture_size: libwayshot::Size,
         connection: WayshotConnection,
         overlay_cursor: bool,
         available_video_formats: Vec<VideoFormat>,
         target: CastTarget,
         gbm_support: bool,
+        crop: Option<Crop>,
+        composite: Option<Composite>,
+        cursor: Option<CursorTracker>,
+        config: StreamConfig,
+        session: Option<FrameSession>,
+        dmabuf: Option<DmabufAllocator>,
+        dmabuf_offers: Vec<DmabufOffer>,
+        target_lost_tx: oneshot::Sender<()>,
+        retarget_rx: mpsc::Receiver<Retarget>,
+        focus: Option<FocusTracker>,
     ) -> Self {
-        Self {
+        let StreamConfig {
+            max_framerate,
+            hidden_window,
+            max_size,
+            exclusion,
+        } = config;
+        let mut data = Self {
             chosen_format: None,
             connection,
             overlay_cursor,
             available_video_formats,
-            size: libwayshot::Size { width, height },
+            size: capture_size,
+            capture_size,
+            max_size,
+            scale: None,
             target,
             gbm_support,
-        }
+            crop,
+            composite,
+            cursor,
+            cursor_serial: 0,
+            cursor_sent: None,
+            max_framerate,
+            frame_interval: frame_interval(max_framerate),
+            last_frame: None,
+            session,
+            pending_buffer: None,
+            dmabuf,
+            dmabuf_offers,
+            dmabuf_choice: None,
+            use_dmabuf: false,
+            target_lost_tx: Some(target_lost_tx),
+            hidden_window,
+            placeholder: None,
+            last_good_frame: None,
+            retarget_rx,
+            focus,
+            shown_windows: Vec::new(),
+            waiting_for_focus: false,
+            exclusion,
+            window_regions: None,
+            excluded: Vec::new(),
+            locked: false,
+        };
+        data.set_stream_size(fit_size(capture_size, max_size));
+        data
+    }
+
+    /// The size of the frame the compositor copies, before the crop
+    fn frame_size(&self) -> libwayshot::Size {
+        self.crop.map_or(self.capture_size, |crop| crop.source_size)
+    }
+
+    /// The largest size the client may ask for, streams are only ever scaled down
+    fn stream_max_size(&self) -> libwayshot::Size {
+        fit_size(self.capture_size, self.max_size)
+    }
+
+    fn set_stream_size(&mut self, size: libwayshot::Size) {
+        self.size = size;
+        self.scale = (size != self.capture_size).then_some(Scale {
+            source: self.capture_size,
+            target: size,
+        });
     }
 
     fn process(&mut self, stream: &pipewire::stream::Stream) {
-        let buffer = unsafe { stream.dequeue_raw_buffer() };
-        if buffer.is_null() {
+        while let Ok(Retarget { source, reply }) = self.retarget_rx.try_recv() {
+            let _ = reply.send(self.retarget(stream, &source));
+        }
+        if let Some(source) = self.focus.as_mut().and_then(FocusTracker::poll)
+            && let Err(e) = self.retarget(stream, &source)
+        {
+            tracing::warn!("Cannot follow the focus to {source:?}: {e}");
+        }
+        if self.waiting_for_focus {
             return;
         }
-        let cast = unsafe {
-            &mut *((*buffer).user_data as *mut libwayshot::screencast::WayshotScreenCast)
+        if session_locked() != self.locked {
+            self.locked = !self.locked;
+            if self.locked {
+                tracing::info!("The session is locked, pause the stream");
+                // The placeholder of a hidden window may be its last frame, which must not
+                // go out while locked
+                self.placeholder = Some(self.black_frame());
+            } else {
+                // The black frame stays until the next captured frame replaces it
+                tracing::info!("The session is unlocked, resume the stream");
+            }
+        }
+        if self.locked {
+            self.process_locked(stream);
+            return;
+        }
+        if let Some(cursor) = self.cursor.as_mut() {
+            cursor.update();
+        }
+        if self.composite.is_some() {
+            self.process_composite(stream);
+        } else if self.session.is_some() {
+            self.process_session(stream);
+        } else {
+            self.process_screencast(stream);
+        }
+    }
+
+    // The buffers stay, only the capture session behind them is replaced. A new size is
+    // negotiated like a resize of the old target
+    fn retarget(
+        &mut self,
+        stream: &pipewire::stream::Stream,
+        source: &CastSource,
+    ) -> anyhow::Result<libwayshot::Size> {
+        if self.composite.is_some() {
+            anyhow::bail!("A stream of all outputs cannot be switched");
+        }
+        if self.session.is_none() {
+            anyhow::bail!("Switching the source needs ext-image-copy-capture");
+        }
+        if self.crop.is_some() {
+            anyhow::bail!("The source of an area cannot be switched");
+        }
+        let mut shown_window = None;
+        let target = match source {
+            CastSource::Output(name) => {
+                self.connection.refresh_outputs()?;
+                self.connection
+                    .get_all_outputs()
+                    .iter()
+                    .find(|output| &output.name == name)
+                    .map(|output| CastTarget::Screen(output.wl_output.clone()))
+                    .ok_or_else(|| anyhow::anyhow!("No output named {name}"))?
+            }
+            CastSource::Toplevel { app_id, .. } if self.exclusion.excludes(app_id) => {
+                anyhow::bail!("The windows of {app_id} are excluded from captures");
+            }
+            CastSource::Toplevel { app_id, title } => {
+                let (handle, identifier) =
+                    find_toplevel(&self.connection, app_id, title, &self.shown_windows)?;
+                shown_window = Some(identifier);
+                CastTarget::TopLevel(handle)
+            }
+        };
+        let session = FrameSession::new(&self.connection, &target, self.overlay_cursor)?;
+        if let Some(identifier) = shown_window.filter(|identifier| !identifier.is_empty()) {
+            self.shown_windows.retain(|shown| shown != &identifier);
+            if self.shown_windows.len() == SHOWN_WINDOWS_MAX {
+                self.shown_windows.remove(0);
+            }
+            self.shown_windows.push(identifier);
+        }
+        let size = session.size();
+
+        // The old session lets go of the pending buffer before it goes back to the queue
+        drop(self.session.replace(session));
+        if let Some(buffer) = self.pending_buffer.take() {
+            set_chunk_size(buffer, 0);
+            unsafe { stream.queue_raw_buffer(buffer) };
+        }
+        if self.cursor.is_some() {
+            self.cursor = CursorTracker::new(&self.connection, &target)
+                .inspect_err(|e| tracing::warn!("Cannot track the cursor for metadata: {e}"))
+                .ok();
+            self.cursor_sent = None;
+        }
+        self.target = target;
+        self.waiting_for_focus = false;
+        self.placeholder = None;
+        self.last_good_frame = None;
+        self.last_frame = None;
+        if size != self.capture_size {
+            self.capture_size = size;
+            self.set_stream_size(fit_size(size, self.max_size));
+            self.update_params(stream, self.stream_params());
+        }
+        tracing::info!("The stream is switched to {source:?}");
+        Ok(self.size)
+    }
+
+    // Skip the tick without touching a buffer, so we only copy at the negotiated rate
+    fn frame_due(&self) -> bool {
+        let frame_interval = if self.placeholder.is_some() {
+            self.frame_interval.max(PLACEHOLDER_FRAME_INTERVAL)
+        } else {
+            self.frame_interval
+        };
+        self.last_frame
+            .is_none_or(|last_frame| last_frame.elapsed() >= frame_interval)
+    }
+
+    // The frame is started on one tick and collected on a later one, the compositor only
+    // finishes it once the target is damaged
+    fn process_session(&mut self, stream: &pipewire::stream::Stream) {
+        if let Some(buffer) = self.pending_buffer {
+            let Some(session) = self.session.as_mut() else {
+                return;
+            };
+            match session.poll() {
+                FrameResult::Pending => self.send_cursor_update(stream),
+                FrameResult::Ready(damage) => {
+                    self.pending_buffer = None;
+                    self.finish_frame(buffer, damage);
+                    unsafe { stream.queue_raw_buffer(buffer) };
+                }
+                FrameResult::Failed(reason) => {
+                    let size = session.size();
+                    self.pending_buffer = None;
+                    if self.window_hidden(reason) {
+                        self.write_placeholder(buffer);
+                    } else {
+                        set_chunk_size(buffer, 0);
+                    }
+                    unsafe { stream.queue_raw_buffer(buffer) };
+                    self.capture_failed(stream, reason, size);
+                }
+            }
+            return;
+        }
+        if !self.frame_due() {
+            return;
+        }
+        let Some(buffer) = dequeue_buffer(stream) else {
+            return;
+        };
+        self.last_frame = Some(Instant::now());
+        let buffer_data = unsafe { &*((*buffer).user_data as *const BufferData) };
+        if let Some(session) = self.session.as_mut() {
+            session.capture(buffer_data.cast.wl_buffer());
+        }
+        self.pending_buffer = Some(buffer);
+    }
+
+    fn process_screencast(&mut self, stream: &pipewire::stream::Stream) {
+        if !self.frame_due() {
+            return;
+        }
+        let Some(buffer) = dequeue_buffer(stream) else {
+            return;
+        };
+        self.last_frame = Some(Instant::now());
+        let buffer_data = unsafe { &mut *((*buffer).user_data as *mut BufferData) };
+        let CaptureBuffer::Screencast(cast) = &mut buffer_data.cast else {
+            unreachable!("dmabufs of our own are only allocated with a capture session");
         };
         match cast.screencast() {
-            Err(libwayshot::Error::FramecopyFailedWithReason(WEnum::Value(
-                FailureReason::BufferConstraints,
-            ))) => {
+            Err(libwayshot::Error::FramecopyFailedWithReason(WEnum::Value(reason))) => {
                 let size = cast.current_size();
-                self.size = libwayshot::Size {
+                let size = libwayshot::Size {
                     width: size.width as u32,
                     height: size.height as u32,
                 };
+                if self.window_hidden(reason) {
+                    self.write_placeholder(buffer);
+                }
+                self.capture_failed(stream, reason, size);
+            }
+            Err(e) => {
+                tracing::error!("Pipewire video capture failed: {e}");
+            }
+            Ok(_) => {
+                // libwayshot does not report damage, so the whole frame counts as changed
+                self.finish_frame(
+                    buffer,
+                    vec![Region {
+                        position: Position::default(),
+                        size: self.frame_size(),
+                    }],
+                );
+            }
+        }
+        unsafe { stream.queue_raw_buffer(buffer) };
+    }
 
-                let libwayshot::Size { width, height } = self.size;
-                let format = format(width, height, self.available_video_formats.clone());
-                let buffers = buffers(width, height);
+    // The outputs are captured one after another and drawn on the cpu, so the whole frame
+    // counts as changed
+    fn process_composite(&mut self, stream: &pipewire::stream::Stream) {
+        if !self.frame_due() {
+            return;
+        }
+        let (Some(composite), Some(format)) = (self.composite.as_mut(), self.chosen_format) else {
+            return;
+        };
+        let Some(buffer) = dequeue_buffer(stream) else {
+            return;
+        };
+        self.last_frame = Some(Instant::now());
+        let frame = composite.draw(
+            &self.connection,
+            format,
+            wl_shm_format_to_spa(format),
+            self.overlay_cursor,
+        );
+        let frame = match &self.scale {
+            Some(scale) => scale.apply(&frame, averages(format)),
+            None => frame,
+        };
+        let buffer_data = unsafe { &*((*buffer).user_data as *const BufferData) };
+        match buffer_data
+            .memory
+            .as_ref()
+            .map(|memory| memory.write_all_at(&frame, 0))
+        {
+            Some(Ok(())) => self.finish_frame(
+                buffer,
+                vec![Region {
+                    position: Position::default(),
+                    size: self.capture_size,
+                }],
+            ),
+            Some(Err(e)) => {
+                tracing::error!("Cannot write the composite frame: {e}");
+                set_chunk_size(buffer, 0);
+            }
+            None => set_chunk_size(buffer, 0),
+        }
+        unsafe { stream.queue_raw_buffer(buffer) };
+    }
 
-                let params = &mut [
-                    pod::Pod::from_bytes(&format).unwrap(),
-                    pod::Pod::from_bytes(&buffers).unwrap(),
-                ];
-                if let Err(err) = stream.update_params(params) {
-                    tracing::error!("failed to update pipewire params: {}", err);
-                }
+    // A frame started before the lock still belongs to the compositor, it is blacked out
+    // once the compositor is done with it
+    fn process_locked(&mut self, stream: &pipewire::stream::Stream) {
+        // A switch of the source or a new size while locked drops or outdates the frame
+        let frame_len = (self.size.width * self.size.height * 4) as usize;
+        if self
+            .placeholder
+            .as_ref()
+            .is_none_or(|frame| frame.len() != frame_len)
+        {
+            self.placeholder = Some(self.black_frame());
+        }
+        if let Some(buffer) = self.pending_buffer {
+            if self
+                .session
+                .as_mut()
+                .is_some_and(|session| matches!(session.poll(), FrameResult::Pending))
+            {
+                return;
+            }
+            self.pending_buffer = None;
+            self.write_placeholder(buffer);
+            unsafe { stream.queue_raw_buffer(buffer) };
+            return;
+        }
+        if !self.frame_due() {
+            return;
+        }
+        let Some(buffer) = dequeue_buffer(stream) else {
+            return;
+        };
+        self.last_frame = Some(Instant::now());
+        self.write_placeholder(buffer);
+        unsafe { stream.queue_raw_buffer(buffer) };
+    }
+
+    fn capture_failed(
+        &mut self,
+        stream: &pipewire::stream::Stream,
+        reason: FailureReason,
+        size: libwayshot::Size,
+    ) {
+        match reason {
+            FailureReason::BufferConstraints => {
+                self.placeholder = None;
+                self.capture_size = match self.crop.as_mut() {
+                    Some(crop) => {
+                        crop.source_size = size;
+                        crop.buffer_region().size
+                    }
+                    None => size,
+                };
+                self.set_stream_size(fit_size(self.capture_size, self.max_size));
+                self.update_params(stream, self.stream_params());
             }
-            Err(libwayshot::Error::FramecopyFailedWithReason(WEnum::Value(
-                FailureReason::Stopped,
-            ))) => {
+            FailureReason::Stopped if self.focus.is_some() => {
+                tracing::info!("The followed window is closed, wait for the focus to move");
+                self.waiting_for_focus = true;
+            }
+            FailureReason::Stopped => {
                 tracing::error!("Pipewire video capture failed capture is stopped");
                 // If the target is dead, we need to stop it
                 let _ = stream.set_active(false);
+                if let Some(target_lost_tx) = self.target_lost_tx.take() {
+                    let _ = target_lost_tx.send(());
+                }
             }
-            Err(e) => {
-                tracing::error!("Pipewire video capture failed: {e}");
+            // A placeholder frame took its place
+            reason if self.window_hidden(reason) => {}
+            reason => {
+                tracing::error!("Pipewire video capture failed: {reason:?}");
+            }
+        }
+    }
+
+    // Minimized windows or windows on another workspace fail to capture until they are back
+    fn window_hidden(&self, reason: FailureReason) -> bool {
+        matches!(self.target, CastTarget::TopLevel(_))
+            && !matches!(
+                reason,
+                FailureReason::BufferConstraints | FailureReason::Stopped
+            )
+    }
+
+    // A dmabuf of libwayshot cannot be written by us, so it only carries the metadata and the
+    // client keeps showing the last frame
+    fn write_placeholder(&mut self, buffer: *mut pipewire::sys::pw_buffer) {
+        let buffer_data = unsafe { &mut *((*buffer).user_data as *mut BufferData) };
+        if !buffer_data.writable() {
+            set_chunk_size(buffer, 0);
+            return;
+        }
+        if self.placeholder.is_none() {
+            tracing::info!("The window cannot be captured, stream placeholder frames");
+            self.placeholder = Some(self.placeholder_frame());
+        }
+        let Some(frame) = &self.placeholder else {
+            return;
+        };
+        if let Err(e) = buffer_data.write_frame(frame, self.size) {
+            tracing::error!("Cannot write the placeholder frame: {e}");
+            set_chunk_size(buffer, 0);
+            return;
+        }
+        set_chunk_size(buffer, buffer_data.chunk_size);
+        self.write_cursor_meta(buffer);
+        write_damage_meta(
+            buffer,
+            &[Region {
+                position: Position::default(),
+                size: self.size,
+            }],
+        );
+    }
+
+    fn placeholder_frame(&self) -> Vec<u8> {
+        let libwayshot::Size { width, height } = self.size;
+        if self.hidden_window == HiddenWindow::LastFrame
+            && let Some(last_good_frame) = self.last_good_frame
+        {
+            let buffer_data = unsafe { &*((*last_good_frame).user_data as *const BufferData) };
+            match buffer_data.read_frame(self.size) {
+                Ok(frame) => return frame,
+                Err(e) => tracing::error!("Cannot read the last frame: {e}"),
+            }
+        }
+        hidden_window_card(
+            width,
+            height,
+            self.chosen_format.and_then(wl_shm_format_to_spa),
+        )
+    }
+
+    fn black_frame(&self) -> Vec<u8> {
+        let libwayshot::Size { width, height } = self.size;
+        let black = gray_pixel(self.chosen_format.and_then(wl_shm_format_to_spa))
+            .map_or([0; 4], |pixel| pixel(0));
+        black.repeat((width * height) as usize)
+    }
+
+    fn finish_frame(&mut self, buffer: *mut pipewire::sys::pw_buffer, damage: Vec<Region>) {
+        let resumed = self.placeholder.take().is_some();
+        if resumed {
+            tracing::info!("The stream shows captured frames again");
+        }
+        let buffer_data = unsafe { &*((*buffer).user_data as *const BufferData) };
+        let damage = match &self.crop {
+            Some(crop) => crop.crop_damage(&damage),
+            None => damage,
+        };
+        let mut damage = match &self.scale {
+            Some(scale) => scale.damage(&damage),
+            None => damage,
+        };
+        // Where a window was and is blacked out changes even when nothing else does
+        let excluded = self.excluded_regions();
+        if excluded != self.excluded {
+            damage.extend(self.excluded.iter().chain(&excluded).copied());
+        }
+        // The compositor only knows what changed since its last frame, not the placeholder
+        if resumed {
+            damage = vec![Region {
+                position: Position::default(),
+                size: self.size,
+            }];
+        }
+        if damage.is_empty() {
+            // Nothing changed inside the stream, so only the metadata goes out
+            set_chunk_size(buffer, 0);
+        } else {
+            if let Some(crop_buffers) = &buffer_data.crop
+                && let Err(e) = self.convert_frame(crop_buffers)
+            {
+                tracing::error!("Cannot crop or scale the frame: {e}");
+            }
+            // A buffer we cannot write does not go out with the excluded windows in it
+            let hidden = excluded.is_empty()
+                || buffer_data.memory.as_ref().is_some_and(|memory| {
+                    black_out(
+                        memory,
+                        self.size,
+                        &excluded,
+                        self.chosen_format.and_then(wl_shm_format_to_spa),
+                    )
+                    .inspect_err(|e| tracing::error!("Cannot hide the excluded windows: {e}"))
+                    .is_ok()
+                });
+            if hidden {
+                set_chunk_size(buffer, buffer_data.chunk_size);
+                self.last_good_frame = Some(buffer);
+            } else {
+                set_chunk_size(buffer, 0);
+            }
+        }
+        self.excluded = excluded;
+        self.write_cursor_meta(buffer);
+        write_damage_meta(buffer, &damage);
+    }
+
+    /// The excluded windows in pixels of the stream. A window cast only shows its window, and
+    /// when the windows cannot be found the whole frame is blacked out
+    fn excluded_regions(&mut self) -> Vec<Region> {
+        if self.exclusion.is_empty() || matches!(self.target, CastTarget::TopLevel(_)) {
+            return Vec::new();
+        }
+        let whole_frame = vec![Region {
+            position: Position::default(),
+            size: self.size,
+        }];
+        let Ok(windows) = self
+            .window_regions
+            .get_or_insert_with(|| self.exclusion.watch())
+            .current()
+        else {
+            return whole_frame;
+        };
+        let regions: Vec<Region> = match (&self.composite, &self.target) {
+            (Some(composite), _) => windows
+                .iter()
+                .map(|window| composite.frame_region(*window))
+                .collect(),
+            (None, CastTarget::Screen(output) | CastTarget::Area { output, .. }) => {
+                let Some(output) = self
+                    .connection
+                    .get_all_outputs()
+                    .iter()
+                    .find(|info| &info.wl_output == output)
+                else {
+                    return whole_frame;
+                };
+                let frame_size = self.frame_size();
+                windows
+                    .iter()
+                    .filter_map(|window| {
+                        output_frame_region(
+                            output.logical_region.inner,
+                            output.transform,
+                            frame_size,
+                            *window,
+                        )
+                    })
+                    .collect()
             }
-            _ => {}
+            _ => Vec::new(),
+        };
+        let regions = match &self.crop {
+            Some(crop) => crop.crop_damage(&regions),
+            None => regions,
+        };
+        match &self.scale {
+            Some(scale) => scale.damage(&regions),
+            None => regions,
         }
+    }
+
+    fn convert_frame(&self, buffers: &CropBuffers) -> io::Result<()> {
+        let frame = match &self.crop {
+            Some(crop) => crop.cut(&buffers.source)?,
+            None => {
+                let libwayshot::Size { width, height } = self.capture_size;
+                let mut frame = vec![0; width as usize * height as usize * 4];
+                buffers.source.read_exact_at(&mut frame, 0)?;
+                frame
+            }
+        };
+        let frame = match &self.scale {
+            Some(scale) => scale.apply(&frame, self.chosen_format.is_some_and(averages)),
+            None => frame,
+        };
+        buffers.target.write_all_at(&frame, 0)
+    }
+
+    // While the frame waits for damage, a cursor move is sent in a buffer without video data
+    fn send_cursor_update(&mut self, stream: &pipewire::stream::Stream) {
+        let Some(cursor) = &self.cursor else {
+            return;
+        };
+        if self.cursor_sent == Some(cursor.snapshot()) {
+            return;
+        }
+        let Some(buffer) = dequeue_buffer(stream) else {
+            return;
+        };
+        set_chunk_size(buffer, 0);
+        self.write_cursor_meta(buffer);
+        write_damage_meta(buffer, &[]);
         unsafe { stream.queue_raw_buffer(buffer) };
     }
 
-    fn add_buffer(&mut self, buffer: *mut pipewire::sys::pw_buffer) {
+    fn write_cursor_meta(&mut self, buffer: *mut pipewire::sys::pw_buffer) {
+        let Some(cursor) = self.cursor.as_ref() else {
+            return;
+        };
+        self.cursor_sent = Some(cursor.snapshot());
+
+        let meta = unsafe {
+            libspa_sys::spa_buffer_find_meta((*buffer).buffer, libspa_sys::SPA_META_Cursor)
+        };
+        if meta.is_null() {
+            return;
+        }
+        let meta = unsafe { &mut *meta };
+        let meta_size = meta.size as usize;
+        let bitmap_offset = size_of::<libspa_sys::spa_meta_cursor>();
+        let pixels_offset = size_of::<libspa_sys::spa_meta_bitmap>();
+        if meta_size < bitmap_offset + pixels_offset {
+            return;
+        }
+        let meta_cursor = unsafe { &mut *(meta.data as *mut libspa_sys::spa_meta_cursor) };
+        let meta_bitmap = unsafe {
+            &mut *((meta.data as *mut u8).add(bitmap_offset) as *mut libspa_sys::spa_meta_bitmap)
+        };
+        let (mut x, mut y) = cursor.position();
+        let mut inside = cursor.inside();
+        if let Some(crop) = &self.crop {
+            let region = crop.buffer_region();
+            x -= region.position.x;
+            y -= region.position.y;
+            inside &=
+                x >= 0 && y >= 0 && x < region.size.width as i32 && y < region.size.height as i32;
+        }
+        if let Some(scale) = &self.scale {
+            (x, y) = scale.point(x, y);
+        }
+        let (hotspot_x, hotspot_y) = cursor.hotspot();
+        meta_cursor.id = 1;
+        meta_cursor.flags = 0;
+        meta_cursor.position = libspa_sys::spa_point { x, y };
+        meta_cursor.hotspot = libspa_sys::spa_point {
+            x: hotspot_x,
+            y: hotspot_y,
+        };
+        meta_cursor.bitmap_offset = 0;
+
+        // An empty bitmap tells the client to hide the cursor
+        if !inside {
+            meta_bitmap.format = VideoFormat::BGRA.as_raw();
+            meta_bitmap.size = libspa_sys::spa_rectangle {
+                width: 0,
+                height: 0,
+            };
+            meta_bitmap.stride = 0;
+            meta_bitmap.offset = pixels_offset as u32;
+            meta_cursor.bitmap_offset = bitmap_offset as u32;
+            self.cursor_serial = 0;
+            return;
+        }
+
+        // Only send the image when it changed, a zero bitmap_offset keeps the previous one
+        let Some((bitmap, serial)) = cursor.bitmap() else {
+            return;
+        };
+        if serial == self.cursor_serial
+            || bitmap_offset + pixels_offset + bitmap.data.len() > meta_size
+        {
+            return;
+        }
+        let Some(format) = wl_shm_format_to_spa(bitmap.format) else {
+            return;
+        };
+        meta_bitmap.format = format.as_raw();
+        meta_bitmap.size = libspa_sys::spa_rectangle {
+            width: bitmap.width,
+            height: bitmap.height,
+        };
+        meta_bitmap.stride = (bitmap.width * 4) as i32;
+        meta_bitmap.offset = pixels_offset as u32;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                bitmap.data.as_ptr(),
+                (meta_bitmap as *mut libspa_sys::spa_meta_bitmap as *mut u8).add(pixels_offset),
+                bitmap.data.len(),
+            );
+        }
+        meta_cursor.bitmap_offset = bitmap_offset as u32;
+        self.cursor_serial = serial;
+    }
+
+    fn add_buffer(
+        &mut self,
+        stream: &pipewire::stream::Stream,
+        buffer: *mut pipewire::sys::pw_buffer,
+    ) {
         let libwayshot::Size { width, height } = self.size;
         let buf = unsafe { &mut *(*buffer).buffer };
         let unit;
+        let mut crop_buffers = None;
+        let mut memory = None;
 
         let datas = unsafe { slice::from_raw_parts_mut(buf.datas, buf.n_datas as usize) };
-        if (datas[0].type_ & (1 << spa::sys::SPA_DATA_DmaBuf) != 0) && self.gbm_support {
+        // The compositor writes a dmabuf at the size of the capture, a scaled stream is shm
+        let dmabuf_choice = self
+            .dmabuf_choice
+            .filter(|_| self.use_dmabuf && self.scale.is_none());
+        if let Some(choice) = dmabuf_choice
+            && datas[0].type_ & (1 << spa::sys::SPA_DATA_DmaBuf) != 0
+        {
+            let dmabuf = match self.allocate_dmabuf(choice) {
+                Ok(dmabuf) => dmabuf,
+                Err(e) => {
+                    // The buffer stays without user data, it is renegotiated right away
+                    tracing::error!("Cannot allocate the dmabuf: {e}");
+                    self.drop_dmabuf_offer(stream, choice.format);
+                    return;
+                }
+            };
+            tracing::info!(
+                "Allocate dmabuf buffer with modifier {:#x}",
+                choice.modifier
+            );
+            let bo = &dmabuf.bo;
+            for (plane, data) in datas.iter_mut().enumerate().take(bo.plane_count() as usize) {
+                let plane = plane as i32;
+                let stride = bo.stride_for_plane(plane);
+                data.type_ = spa::sys::SPA_DATA_DmaBuf;
+                data.flags = 0;
+                data.fd = bo.fd_for_plane(plane).unwrap().into_raw_fd() as _;
+                data.data = std::ptr::null_mut();
+                data.maxsize = height * stride;
+                data.mapoffset = 0;
+
+                let chunk = unsafe { &mut *data.chunk };
+                chunk.size = height * stride;
+                chunk.offset = bo.offset(plane);
+                chunk.stride = stride as i32;
+            }
+            unit = CaptureBuffer::Dmabuf(dmabuf);
+        } else if (datas[0].type_ & (1 << spa::sys::SPA_DATA_DmaBuf) != 0)
+            && self.gbm_support
+            && self.scale.is_none()
+        {
             tracing::info!("Allocate dmabuf buffer");
-            unit = self
+            let cast = self
                 .connection
                 .create_screencast_with_dmabuf(self.target.wayshot_target(), self.overlay_cursor)
                 .expect("We should make sure the protocol is existed");
-            let bo = unit.dmabuf_bo().unwrap();
+            let bo = cast.dmabuf_bo().unwrap();
             let plane_len = bo.plane_count() as usize;
             let data_len = datas.len();
             let loop_len = plane_len.min(data_len);
@@ -202,6 +1267,7 @@ impl StreamingData {
                 chunk.offset = plane_offset;
                 chunk.stride = plane_stride as i32;
             }
+            unit = CaptureBuffer::Screencast(cast);
         } else {
             assert_eq!(datas.len(), 1);
             let data = &mut datas[0];
@@ -209,16 +1275,45 @@ impl StreamingData {
             let fd = rustix::fs::memfd_create(name, rustix::fs::MemfdFlags::CLOEXEC).unwrap();
             rustix::fs::ftruncate(&fd, (width * height * 4) as _).unwrap();
 
-            unit = self
-                .connection
-                .create_screencast_with_shm(
-                    self.target.wayshot_target(),
-                    self.overlay_cursor,
-                    self.chosen_format.unwrap(),
-                    &fd,
-                )
-                .expect("We should make sure the protocol is existed");
+            if self.composite.is_some() {
+                unit = CaptureBuffer::Composite;
+            } else if self.crop.is_some() || self.scale.is_some() {
+                let libwayshot::Size {
+                    width: source_width,
+                    height: source_height,
+                } = self.frame_size();
+                let source_name = c"pipewire-screencopy-source";
+                let source_fd =
+                    rustix::fs::memfd_create(source_name, rustix::fs::MemfdFlags::CLOEXEC).unwrap();
+                rustix::fs::ftruncate(&source_fd, (source_width * source_height * 4) as _).unwrap();
+                let cast = self
+                    .connection
+                    .create_screencast_with_shm(
+                        self.target.wayshot_target(),
+                        self.overlay_cursor,
+                        self.chosen_format.unwrap(),
+                        &source_fd,
+                    )
+                    .expect("We should make sure the protocol is existed");
+                unit = CaptureBuffer::Screencast(cast);
+                crop_buffers = Some(CropBuffers {
+                    source: File::from(source_fd),
+                    target: File::from(fd.try_clone().unwrap()),
+                });
+            } else {
+                let cast = self
+                    .connection
+                    .create_screencast_with_shm(
+                        self.target.wayshot_target(),
+                        self.overlay_cursor,
+                        self.chosen_format.unwrap(),
+                        &fd,
+                    )
+                    .expect("We should make sure the protocol is existed");
+                unit = CaptureBuffer::Screencast(cast);
+            }
 
+            memory = fd.try_clone().ok().map(File::from);
             data.type_ = libspa_sys::SPA_DATA_MemFd;
             data.flags = 0;
             data.fd = fd.into_raw_fd().into();
@@ -231,11 +1326,29 @@ impl StreamingData {
             chunk.offset = 0;
             chunk.stride = 4 * width as i32;
         }
-        let user_data = Box::into_raw(Box::new(unit)) as *mut c_void;
+        let chunk_size = unsafe { (*datas[0].chunk).size };
+        let user_data = Box::into_raw(Box::new(BufferData {
+            cast: unit,
+            crop: crop_buffers,
+            memory,
+            chunk_size,
+        })) as *mut c_void;
         unsafe { (*buffer).user_data = user_data };
     }
 
-    fn remove_buffer(&self, buffer: *mut pipewire::sys::pw_buffer) {
+    fn remove_buffer(&mut self, buffer: *mut pipewire::sys::pw_buffer) {
+        if self.last_good_frame == Some(buffer) {
+            self.last_good_frame = None;
+        }
+        if self.pending_buffer == Some(buffer) {
+            self.pending_buffer = None;
+            if let Some(session) = self.session.as_mut() {
+                session.cancel();
+            }
+        }
+        if unsafe { (*buffer).user_data }.is_null() {
+            return;
+        }
         let buf = unsafe { &mut *(*buffer).buffer };
         let datas = unsafe { slice::from_raw_parts_mut(buf.datas, buf.n_datas as usize) };
 
@@ -243,12 +1356,11 @@ impl StreamingData {
             unsafe { rustix::io::close(data.fd as _) };
             data.fd = -1;
         }
-        let cast: Box<libwayshot::screencast::WayshotScreenCast> =
-            unsafe { Box::from_raw((*buffer).user_data as *mut _) };
-        drop(cast);
+        let buffer_data: Box<BufferData> = unsafe { Box::from_raw((*buffer).user_data as *mut _) };
+        drop(buffer_data);
     }
 
-    fn param_changed(&mut self, id: u32, pod: Option<&Pod>) {
+    fn param_changed(&mut self, stream: &pipewire::stream::Stream, id: u32, pod: Option<&Pod>) {
         if id != libspa_sys::SPA_PARAM_Format {
             return;
         }
@@ -256,6 +1368,39 @@ impl StreamingData {
             let mut chosen_format_info = VideoInfoRaw::new();
             match chosen_format_info.parse(pod) {
                 Ok(_) => {
+                    let max_framerate = chosen_format_info.max_framerate();
+                    let framerate = if max_framerate.num > 0 {
+                        max_framerate
+                    } else {
+                        chosen_format_info.framerate()
+                    };
+                    // The client may only lower the rate below the configured one
+                    self.frame_interval = if framerate.num > 0 && framerate.denom > 0 {
+                        Duration::from_secs_f64(framerate.denom as f64 / framerate.num as f64)
+                            .max(frame_interval(self.max_framerate))
+                    } else {
+                        frame_interval(self.max_framerate)
+                    };
+                    tracing::info!(
+                        "Negotiated framerate: {}/{}",
+                        framerate.num,
+                        framerate.denom
+                    );
+                    let size = chosen_format_info.size();
+                    if size.width > 0 && size.height > 0 {
+                        let size = libwayshot::Size {
+                            width: size.width,
+                            height: size.height,
+                        };
+                        if size != self.size {
+                            tracing::info!("Negotiated size: {}x{}", size.width, size.height);
+                            self.set_stream_size(size);
+                            // With dmabufs the buffers follow from their negotiation
+                            if self.dmabuf.is_none() {
+                                self.update_params(stream, self.buffer_params());
+                            }
+                        }
+                    }
                     if let Some(wl_shm_fmt) = spa_format_to_wl_shm(chosen_format_info.format()) {
                         self.chosen_format = Some(wl_shm_fmt);
                     } else {
@@ -263,11 +1408,167 @@ impl StreamingData {
                             "Could not convert SPA format chosen by PipeWire server to wl_shm format"
                         );
                     }
+                    self.negotiate_dmabuf(stream, pod, chosen_format_info.format());
                 }
                 Err(e) => tracing::error!("Could not parse format chosen by PipeWire server: {e}"),
             };
         }
     }
+
+    // With DONT_FIXATE the client leaves the modifier open. We allocate with the modifiers it
+    // can import and offer the one the driver picked, which comes back to us fixed
+    fn negotiate_dmabuf(
+        &mut self,
+        stream: &pipewire::stream::Stream,
+        pod: &Pod,
+        format: VideoFormat,
+    ) {
+        if self.dmabuf.is_none() {
+            return;
+        }
+        match modifier_property(pod) {
+            ModifierProperty::None => self.use_dmabuf = false,
+            ModifierProperty::Fixed(modifier) => {
+                let fixated = self
+                    .dmabuf_choice
+                    .is_some_and(|choice| choice.format == format && choice.modifier == modifier);
+                if !fixated {
+                    let Some(choice) = self.fixate(format, &[modifier]) else {
+                        self.drop_dmabuf_offer(stream, format);
+                        return;
+                    };
+                    self.dmabuf_choice = Some(choice);
+                }
+                self.use_dmabuf = true;
+            }
+            ModifierProperty::Choice(modifiers) => {
+                match self.fixate(format, &modifiers) {
+                    Some(choice) => {
+                        tracing::info!("Fixate the dmabuf modifier {:#x}", choice.modifier);
+                        self.dmabuf_choice = Some(choice);
+                        self.use_dmabuf = false;
+                        self.update_params(stream, self.stream_params());
+                    }
+                    None => self.drop_dmabuf_offer(stream, format),
+                }
+                return;
+            }
+        }
+        self.update_params(stream, self.buffer_params());
+    }
+
+    /// Try an allocation with the modifiers both sides support
+    fn fixate(&self, format: VideoFormat, modifiers: &[u64]) -> Option<DmabufChoice> {
+        let allocator = self.dmabuf.as_ref()?;
+        let offer = self
+            .dmabuf_offers
+            .iter()
+            .find(|offer| offer.format == format)?;
+        let modifiers: Vec<u64> = modifiers
+            .iter()
+            .copied()
+            .filter(|modifier| offer.modifiers.contains(modifier))
+            .collect();
+        if modifiers.is_empty() {
+            return None;
+        }
+        let libwayshot::Size { width, height } = self.capture_size;
+        let bo = allocator
+            .allocate(width, height, offer.fourcc, &modifiers)
+            .inspect_err(|e| tracing::warn!("Cannot allocate a dmabuf for {format:?}: {e}"))
+            .ok()?;
+        let modifier = if modifiers == [DRM_FORMAT_MOD_INVALID] {
+            DRM_FORMAT_MOD_INVALID
+        } else {
+            bo.modifier().into()
+        };
+        Some(DmabufChoice {
+            format,
+            fourcc: offer.fourcc,
+            modifier,
+            planes: bo.plane_count(),
+        })
+    }
+
+    fn allocate_dmabuf(&self, choice: DmabufChoice) -> anyhow::Result<DmabufBuffer> {
+        let (Some(allocator), Some(session)) = (&self.dmabuf, &self.session) else {
+            return Err(anyhow::anyhow!("dmabufs need a capture session"));
+        };
+        let libwayshot::Size { width, height } = self.capture_size;
+        let bo = allocator.allocate(width, height, choice.fourcc, &[choice.modifier])?;
+        session.create_dmabuf_buffer(bo, choice.fourcc)
+    }
+
+    // The format is offered as shm only from now on, which the client can always fall back to
+    fn drop_dmabuf_offer(&mut self, stream: &pipewire::stream::Stream, format: VideoFormat) {
+        tracing::warn!("Stop offering {format:?} as dmabuf");
+        self.dmabuf_offers.retain(|offer| offer.format != format);
+        self.dmabuf_choice = None;
+        self.use_dmabuf = false;
+        self.update_params(stream, self.stream_params());
+    }
+
+    fn update_params(&self, stream: &pipewire::stream::Stream, params: Vec<Vec<u8>>) {
+        let params = &mut params
+            .iter()
+            .map(|param| pod::Pod::from_bytes(param).unwrap())
+            .collect::<Vec<_>>();
+        if let Err(err) = stream.update_params(params) {
+            tracing::error!("failed to update pipewire params: {}", err);
+        }
+    }
+
+    fn stream_params(&self) -> Vec<Vec<u8>> {
+        let max_size = self.stream_max_size();
+        let mut params = Vec::new();
+        // Dmabufs only come at the size of the capture, the fixated format goes first and the
+        // others stay as a fallback
+        if max_size == self.capture_size {
+            if let Some(choice) = self.dmabuf_choice {
+                params.push(format(
+                    self.capture_size,
+                    None,
+                    &[choice.format],
+                    ModifierParam::Fixed(choice.modifier),
+                    self.max_framerate,
+                ));
+            }
+            params.extend(self.dmabuf_offers.iter().map(|offer| {
+                format(
+                    self.capture_size,
+                    None,
+                    &[offer.format],
+                    ModifierParam::Choice(&offer.modifiers),
+                    self.max_framerate,
+                )
+            }));
+        }
+        params.push(format(
+            self.size,
+            Some(max_size),
+            &self.available_video_formats,
+            ModifierParam::None,
+            self.max_framerate,
+        ));
+        params.extend(self.buffer_params());
+        params
+    }
+
+    fn buffer_params(&self) -> Vec<Vec<u8>> {
+        let libwayshot::Size { width, height } = self.size;
+        let buffer_type = match self.dmabuf_choice {
+            Some(choice) if self.use_dmabuf => BufferType::DmaBuf {
+                planes: choice.planes,
+            },
+            _ if self.dmabuf.is_some() || self.scale.is_some() => BufferType::MemFd,
+            _ => BufferType::Any,
+        };
+        let mut params = vec![buffers(width, height, buffer_type), damage_meta_param()];
+        if self.cursor.is_some() {
+            params.push(cursor_meta_param());
+        }
+        params
+    }
 }
 
 type PipewireStreamResult = (
@@ -276,13 +1577,25 @@ type PipewireStreamResult = (
     pipewire::stream::StreamRc,
     pipewire::context::ContextRc,
     oneshot::Receiver<anyhow::Result<u32>>,
+    libwayshot::Size,
 );
 
 fn start_stream(
     mut connection: WayshotConnection,
-    overlay_cursor: bool,
+    cursor_mode: CursorMode,
     target: CastTarget,
+    follow_focus: bool,
+    config: StreamConfig,
+    target_lost_tx: oneshot::Sender<()>,
+    retarget_rx: mpsc::Receiver<Retarget>,
 ) -> anyhow::Result<PipewireStreamResult> {
+    let composite = match target {
+        CastTarget::AllOutputs => Some(Composite::new(&connection)?),
+        _ => None,
+    };
+    // The cursor of a composite frame cannot be sent as metadata, so it is drawn in
+    let overlay_cursor = cursor_mode == CursorMode::Embedded
+        || (composite.is_some() && cursor_mode == CursorMode::Metadata);
     let loop_ = pipewire::main_loop::MainLoopRc::new(None).unwrap();
     let context = pipewire::context::ContextRc::new(&loop_, None).unwrap();
     let core = context.connect_rc(None).unwrap();
@@ -301,42 +1614,142 @@ fn start_stream(
     let (node_id_tx, node_id_rx) = oneshot::channel();
     let mut node_id_tx = Some(node_id_tx);
 
+    // libwayshot starts a new capture for every frame, damage needs a session of our own
+    let session = if connection.image_copy_support() && composite.is_none() {
+        FrameSession::new(&connection, &target, overlay_cursor)
+            .inspect_err(|e| tracing::warn!("Cannot create a capture session: {e}"))
+            .ok()
+    } else {
+        None
+    };
+
     // HACK: it wm is started with headless mode,
     // We won't use dmabuf when started with headless mode
-    let gbm_support = if *HEADLESS_START {
-        false
-    } else {
-        let mut gbm_support = connection.try_init_dmabuf(target.wayshot_target()).is_ok();
-        if gbm_support {
-            // NOTE: try to run screencast once
-            // If succeeded, We can think that it has gbm_support
-            gbm_support = connection
-                .create_screencast_with_dmabuf(target.wayshot_target(), overlay_cursor)
-                .is_ok();
-        }
+    // An area is cropped and a composite drawn on the cpu, so they are always captured into shm.
+    // Excluded windows are blacked out on the cpu as well, unless only a window is cast
+    let dmabuf_capable = !*HEADLESS_START
+        && !matches!(target, CastTarget::Area { .. } | CastTarget::AllOutputs)
+        && (config.exclusion.is_empty() || matches!(target, CastTarget::TopLevel(_)));
+    // Buffers in a layout the client can import need the device the compositor renders with
+    let dmabuf = session
+        .as_ref()
+        .and_then(FrameSession::dmabuf_device)
+        .filter(|_| dmabuf_capable)
+        .and_then(|device| {
+            DmabufAllocator::new(device)
+                .inspect_err(|e| tracing::warn!("Cannot allocate dmabufs: {e}"))
+                .ok()
+        });
+    let dmabuf_offers: Vec<DmabufOffer> = match (&dmabuf, &session) {
+        (Some(_), Some(session)) => session
+            .dmabuf_formats()
+            .iter()
+            .filter(|dmabuf_format| !dmabuf_format.modifiers.is_empty())
+            .filter_map(|dmabuf_format| {
+                Some(DmabufOffer {
+                    format: wl_shm_format_to_spa(fourcc_to_wl_shm(dmabuf_format.fourcc)?)?,
+                    fourcc: dmabuf_format.fourcc,
+                    modifiers: dmabuf_format.modifiers.clone(),
+                })
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    // libwayshot allocates linear buffers without any negotiation, it is only the fallback
+    // when we cannot allocate the dmabufs ourselves. A window gets shm instead, its placeholder
+    // frames cannot be drawn into the buffers of libwayshot
+    let gbm_support =
+        if !dmabuf_capable || dmabuf.is_some() || matches!(target, CastTarget::TopLevel(_)) {
+            false
+        } else {
+            let mut gbm_support = connection.try_init_dmabuf(target.wayshot_target()).is_ok();
+            if gbm_support {
+                // NOTE: try to run screencast once
+                // If succeeded, We can think that it has gbm_support
+                gbm_support = connection
+                    .create_screencast_with_dmabuf(target.wayshot_target(), overlay_cursor)
+                    .is_ok();
+            }
 
-        gbm_support
+            gbm_support
+        };
+    let (source_size, frame_formats): (libwayshot::Size, Vec<Format>) = match &composite {
+        Some(composite) => (composite.size(), composite.formats().to_vec()),
+        None => {
+            let frame_format_list =
+                connection.get_available_frame_formats(&target.wayshot_target())?;
+            if frame_format_list.is_empty() {
+                return Err(anyhow::anyhow!("We need at least one format"));
+            }
+            (
+                frame_format_list[0].size,
+                frame_format_list
+                    .iter()
+                    .map(|frame_format| frame_format.format)
+                    .collect(),
+            )
+        }
     };
-    let frame_format_list = connection.get_available_frame_formats(&target.wayshot_target())?;
-    if frame_format_list.is_empty() {
-        return Err(anyhow::anyhow!("We need at least one format"));
-    }
-    let libwayshot::Size { width, height } = frame_format_list[0].size;
-    let available_video_formats: Vec<VideoFormat> = frame_format_list
-        .iter()
-        .filter_map(|frame_format| wl_shm_format_to_spa(frame_format.format))
+    let crop = match &target {
+        CastTarget::Area {
+            region,
+            output_size,
+            ..
+        } => Some(Crop {
+            region: *region,
+            output_size: *output_size,
+            source_size,
+        }),
+        _ => None,
+    };
+    let capture_size = crop
+        .map(|crop| crop.buffer_region().size)
+        .unwrap_or(source_size);
+    let available_video_formats: Vec<VideoFormat> = frame_formats
+        .into_iter()
+        .filter_map(wl_shm_format_to_spa)
         .collect();
+    // Only a capture session can be moved to another window
+    let focus = match (&session, follow_focus) {
+        (Some(_), true) => FocusTracker::new(&connection)
+            .inspect_err(|e| tracing::warn!("Cannot follow the focus: {e}"))
+            .ok(),
+        (None, true) => {
+            tracing::warn!("Following the focus needs ext-image-copy-capture");
+            None
+        }
+        (_, false) => None,
+    };
+    let cursor = if cursor_mode == CursorMode::Metadata && composite.is_none() {
+        CursorTracker::new(&connection, &target)
+            .inspect_err(|e| tracing::warn!("Cannot track the cursor for metadata: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    let streaming_data = StreamingData::new(
+        capture_size,
+        connection,
+        overlay_cursor,
+        available_video_formats,
+        target,
+        gbm_support,
+        crop,
+        composite,
+        cursor,
+        config,
+        session,
+        dmabuf,
+        dmabuf_offers,
+        target_lost_tx,
+        retarget_rx,
+        focus,
+    );
+    let params = streaming_data.stream_params();
+    let size = streaming_data.size;
 
     let listener = stream
-        .add_local_listener_with_user_data(StreamingData::new(
-            width,
-            height,
-            connection,
-            overlay_cursor,
-            available_video_formats.clone(),
-            target,
-            gbm_support,
-        ))
+        .add_local_listener_with_user_data(streaming_data)
         .state_changed(move |stream, _, old, new| {
             tracing::info!("state-changed '{:?}' -> '{:?}'", old, new);
             match new {
@@ -351,11 +1764,11 @@ fn start_stream(
                 _ => {}
             }
         })
-        .param_changed(|_stream, streaming_data, id, pod| {
-            streaming_data.param_changed(id, pod);
+        .param_changed(|stream, streaming_data, id, pod| {
+            streaming_data.param_changed(stream, id, pod);
         })
-        .add_buffer(|_, data, buffer| {
-            data.add_buffer(buffer);
+        .add_buffer(|stream, data, buffer| {
+            data.add_buffer(stream, buffer);
         })
         .remove_buffer(|_, data, buffer| {
             data.remove_buffer(buffer);
@@ -364,17 +1777,14 @@ fn start_stream(
             streaming_data.process(stream);
         })
         .register()?;
-    let format = format(width, height, available_video_formats);
-    let buffers = buffers(width, height);
-
-    let params = &mut [
-        pod::Pod::from_bytes(&format).unwrap(),
-        pod::Pod::from_bytes(&buffers).unwrap(),
-    ];
+    let params = &mut params
+        .iter()
+        .map(|param| pod::Pod::from_bytes(param).unwrap())
+        .collect::<Vec<_>>();
 
     let flags = pipewire::stream::StreamFlags::ALLOC_BUFFERS;
     stream.connect(pipewire::spa::utils::Direction::Output, None, flags, params)?;
-    Ok((loop_, listener, stream, context, node_id_rx))
+    Ok((loop_, listener, stream, context, node_id_rx, size))
 }
 
 fn value_to_bytes(value: pod::Value) -> Vec<u8> {
@@ -384,53 +1794,176 @@ fn value_to_bytes(value: pod::Value) -> Vec<u8> {
     bytes
 }
 
-fn buffers(width: u32, height: u32) -> Vec<u8> {
+// A buffer the process callback can use, one whose allocation failed goes straight back
+fn dequeue_buffer(stream: &pipewire::stream::Stream) -> Option<*mut pipewire::sys::pw_buffer> {
+    let buffer = unsafe { stream.dequeue_raw_buffer() };
+    if buffer.is_null() {
+        return None;
+    }
+    if unsafe { (*buffer).user_data }.is_null() {
+        unsafe { stream.queue_raw_buffer(buffer) };
+        return None;
+    }
+    Some(buffer)
+}
+
+fn modifier_property(pod: &Pod) -> ModifierProperty {
+    let Ok((_, pod::Value::Object(object))) = PodDeserializer::deserialize_any_from(pod.as_bytes())
+    else {
+        return ModifierProperty::None;
+    };
+    let Some(property) = object.properties.into_iter().find(|property| {
+        property.key == spa::param::format::FormatProperties::VideoModifier.as_raw()
+    }) else {
+        return ModifierProperty::None;
+    };
+    match property.value {
+        pod::Value::Long(modifier) => ModifierProperty::Fixed(modifier as u64),
+        pod::Value::Choice(pod::ChoiceValue::Long(spa::utils::Choice(
+            _,
+            spa::utils::ChoiceEnum::Enum {
+                default,
+                alternatives,
+            },
+        ))) => {
+            if !property.flags.contains(pod::PropertyFlags::DONT_FIXATE) {
+                return ModifierProperty::Fixed(default as u64);
+            }
+            let mut modifiers: Vec<u64> = std::iter::once(default)
+                .chain(alternatives)
+                .map(|modifier| modifier as u64)
+                .collect();
+            modifiers.sort_unstable();
+            modifiers.dedup();
+            ModifierProperty::Choice(modifiers)
+        }
+        _ => ModifierProperty::None,
+    }
+}
+
+fn damage_meta_param() -> Vec<u8> {
+    let region_size = size_of::<libspa_sys::spa_meta_region>() as i32;
     value_to_bytes(pod::Value::Object(pod::Object {
-        type_: libspa_sys::SPA_TYPE_OBJECT_ParamBuffers,
-        id: libspa_sys::SPA_PARAM_Buffers,
+        type_: libspa_sys::SPA_TYPE_OBJECT_ParamMeta,
+        id: libspa_sys::SPA_PARAM_Meta,
         properties: vec![
-            /*
             pod::Property {
-                key: spa_sys::SPA_PARAM_BUFFERS_dataType,
+                key: libspa_sys::SPA_PARAM_META_type,
+                flags: pod::PropertyFlags::empty(),
+                value: pod::Value::Id(spa::utils::Id(libspa_sys::SPA_META_VideoDamage)),
+            },
+            pod::Property {
+                key: libspa_sys::SPA_PARAM_META_size,
                 flags: pod::PropertyFlags::empty(),
                 value: pod::Value::Choice(pod::ChoiceValue::Int(spa::utils::Choice(
                     spa::utils::ChoiceFlags::empty(),
-                    spa::utils::ChoiceEnum::Flags {
-                        default: 1 << spa_sys::SPA_DATA_MemFd,
-                        flags: vec![],
+                    spa::utils::ChoiceEnum::Range {
+                        default: region_size * DAMAGE_META_MAX_REGIONS as i32,
+                        min: region_size,
+                        max: region_size * DAMAGE_META_MAX_REGIONS as i32,
                     },
                 ))),
             },
-            */
-            pod::Property {
-                key: libspa_sys::SPA_PARAM_BUFFERS_size,
-                flags: pod::PropertyFlags::empty(),
-                value: pod::Value::Int(width as i32 * height as i32 * 4),
-            },
-            pod::Property {
-                key: libspa_sys::SPA_PARAM_BUFFERS_stride,
-                flags: pod::PropertyFlags::empty(),
-                value: pod::Value::Int(width as i32 * 4),
-            },
-            pod::Property {
-                key: libspa_sys::SPA_PARAM_BUFFERS_align,
-                flags: pod::PropertyFlags::empty(),
-                value: pod::Value::Int(16),
-            },
+        ],
+    }))
+}
+
+fn set_chunk_size(buffer: *mut pipewire::sys::pw_buffer, size: u32) {
+    let buf = unsafe { &mut *(*buffer).buffer };
+    let datas = unsafe { slice::from_raw_parts_mut(buf.datas, buf.n_datas as usize) };
+    if let Some(data) = datas.first_mut() {
+        unsafe { (*data.chunk).size = size };
+    }
+}
+
+/// The list ends with an empty region when it is shorter than the meta,
+/// and is merged into its bounding box when it does not fit
+fn write_damage_meta(buffer: *mut pipewire::sys::pw_buffer, damage: &[Region]) {
+    let meta = unsafe {
+        libspa_sys::spa_buffer_find_meta((*buffer).buffer, libspa_sys::SPA_META_VideoDamage)
+    };
+    if meta.is_null() {
+        return;
+    }
+    let meta = unsafe { &*meta };
+    let count = meta.size as usize / size_of::<libspa_sys::spa_meta_region>();
+    if count == 0 {
+        return;
+    }
+    let slots =
+        unsafe { slice::from_raw_parts_mut(meta.data as *mut libspa_sys::spa_meta_region, count) };
+    let bounding_box;
+    let damage = if damage.len() > count {
+        bounding_box = [bounding_box_of(damage)];
+        &bounding_box[..]
+    } else {
+        damage
+    };
+    let spa_region = |region: &Region| libspa_sys::spa_region {
+        position: libspa_sys::spa_point {
+            x: region.position.x,
+            y: region.position.y,
+        },
+        size: libspa_sys::spa_rectangle {
+            width: region.size.width,
+            height: region.size.height,
+        },
+    };
+    for (slot, region) in slots.iter_mut().zip(damage) {
+        slot.region = spa_region(region);
+    }
+    if let Some(end) = slots.get_mut(damage.len()) {
+        end.region = spa_region(&Region::default());
+    }
+}
+
+fn bounding_box_of(regions: &[Region]) -> Region {
+    let left = regions.iter().map(|r| r.position.x).min().unwrap_or(0);
+    let top = regions.iter().map(|r| r.position.y).min().unwrap_or(0);
+    let right = regions
+        .iter()
+        .map(|r| r.position.x + r.size.width as i32)
+        .max()
+        .unwrap_or(0);
+    let bottom = regions
+        .iter()
+        .map(|r| r.position.y + r.size.height as i32)
+        .max()
+        .unwrap_or(0);
+    Region {
+        position: Position { x: left, y: top },
+        size: libwayshot::Size {
+            width: (right - left).max(0) as u32,
+            height: (bottom - top).max(0) as u32,
+        },
+    }
+}
+
+fn cursor_meta_size(width: u32, height: u32) -> i32 {
+    (size_of::<libspa_sys::spa_meta_cursor>()
+        + size_of::<libspa_sys::spa_meta_bitmap>()
+        + (width * height * 4) as usize) as i32
+}
+
+fn cursor_meta_param() -> Vec<u8> {
+    value_to_bytes(pod::Value::Object(pod::Object {
+        type_: libspa_sys::SPA_TYPE_OBJECT_ParamMeta,
+        id: libspa_sys::SPA_PARAM_Meta,
+        properties: vec![
             pod::Property {
-                key: libspa_sys::SPA_PARAM_BUFFERS_blocks,
+                key: libspa_sys::SPA_PARAM_META_type,
                 flags: pod::PropertyFlags::empty(),
-                value: pod::Value::Int(1),
+                value: pod::Value::Id(spa::utils::Id(libspa_sys::SPA_META_Cursor)),
             },
             pod::Property {
-                key: libspa_sys::SPA_PARAM_BUFFERS_buffers,
+                key: libspa_sys::SPA_PARAM_META_size,
                 flags: pod::PropertyFlags::empty(),
                 value: pod::Value::Choice(pod::ChoiceValue::Int(spa::utils::Choice(
                     spa::utils::ChoiceFlags::empty(),
                     spa::utils::ChoiceEnum::Range {
-                        default: 4,
-                        min: 1,
-                        max: 32,
+                        default: cursor_meta_size(64, 64),
+                        min: cursor_meta_size(1, 1),
+                        max: cursor_meta_size(CURSOR_META_MAX_SIZE, CURSOR_META_MAX_SIZE),
                     },
                 ))),
             },
@@ -438,7 +1971,104 @@ fn buffers(width: u32, height: u32) -> Vec<u8> {
     }))
 }
 
-fn format(width: u32, height: u32, available_video_formats: Vec<VideoFormat>) -> Vec<u8> {
+fn buffers(width: u32, height: u32, buffer_type: BufferType) -> Vec<u8> {
+    let mut properties = Vec::new();
+    let data_type = match buffer_type {
+        BufferType::Any => None,
+        BufferType::MemFd => Some(1 << libspa_sys::SPA_DATA_MemFd),
+        BufferType::DmaBuf { .. } => Some(1 << libspa_sys::SPA_DATA_DmaBuf),
+    };
+    if let Some(data_type) = data_type {
+        properties.push(pod::Property {
+            key: libspa_sys::SPA_PARAM_BUFFERS_dataType,
+            flags: pod::PropertyFlags::empty(),
+            value: pod::Value::Choice(pod::ChoiceValue::Int(spa::utils::Choice(
+                spa::utils::ChoiceFlags::empty(),
+                spa::utils::ChoiceEnum::Flags {
+                    default: data_type,
+                    flags: vec![],
+                },
+            ))),
+        });
+    }
+    // The layout of a dmabuf is up to the modifier, the size and stride are not ours to say
+    let blocks = match buffer_type {
+        BufferType::DmaBuf { planes } => planes as i32,
+        BufferType::Any | BufferType::MemFd => {
+            properties.push(pod::Property {
+                key: libspa_sys::SPA_PARAM_BUFFERS_size,
+                flags: pod::PropertyFlags::empty(),
+                value: pod::Value::Int(width as i32 * height as i32 * 4),
+            });
+            properties.push(pod::Property {
+                key: libspa_sys::SPA_PARAM_BUFFERS_stride,
+                flags: pod::PropertyFlags::empty(),
+                value: pod::Value::Int(width as i32 * 4),
+            });
+            1
+        }
+    };
+    properties.extend([
+        pod::Property {
+            key: libspa_sys::SPA_PARAM_BUFFERS_align,
+            flags: pod::PropertyFlags::empty(),
+            value: pod::Value::Int(16),
+        },
+        pod::Property {
+            key: libspa_sys::SPA_PARAM_BUFFERS_blocks,
+            flags: pod::PropertyFlags::empty(),
+            value: pod::Value::Int(blocks),
+        },
+        pod::Property {
+            key: libspa_sys::SPA_PARAM_BUFFERS_buffers,
+            flags: pod::PropertyFlags::empty(),
+            value: pod::Value::Choice(pod::ChoiceValue::Int(spa::utils::Choice(
+                spa::utils::ChoiceFlags::empty(),
+                spa::utils::ChoiceEnum::Range {
+                    default: 4,
+                    min: 1,
+                    max: 32,
+                },
+            ))),
+        },
+    ]);
+    value_to_bytes(pod::Value::Object(pod::Object {
+        type_: libspa_sys::SPA_TYPE_OBJECT_ParamBuffers,
+        id: libspa_sys::SPA_PARAM_Buffers,
+        properties,
+    }))
+}
+
+fn frame_interval(framerate: u32) -> Duration {
+    Duration::from_secs_f64(1.0 / framerate.max(1) as f64)
+}
+
+/// With `max_size` the client may pick any size up to it, otherwise the size is fixed
+fn format(
+    size: libwayshot::Size,
+    max_size: Option<libwayshot::Size>,
+    available_video_formats: &[VideoFormat],
+    modifier: ModifierParam,
+    max_framerate: u32,
+) -> Vec<u8> {
+    let max_framerate = max_framerate.max(1);
+    let libwayshot::Size { width, height } = size;
+    let (min, max) = match max_size {
+        Some(max) => (
+            spa::utils::Rectangle {
+                width: 1,
+                height: 1,
+            },
+            spa::utils::Rectangle {
+                width: max.width,
+                height: max.height,
+            },
+        ),
+        None => (
+            spa::utils::Rectangle { width, height },
+            spa::utils::Rectangle { width, height },
+        ),
+    };
     let mut obj = spa::pod::object!(
         spa::utils::SpaTypes::ObjectParamFormat,
         spa::param::ParamType::EnumFormat,
@@ -452,26 +2082,36 @@ fn format(width: u32, height: u32, available_video_formats: Vec<VideoFormat>) ->
             Id,
             spa::param::format::MediaSubtype::Raw
         ),
-        // XXX modifiers
         spa::pod::property!(
             spa::param::format::FormatProperties::VideoSize,
             Choice,
             Range,
             Rectangle,
             spa::utils::Rectangle { width, height },
-            spa::utils::Rectangle { width, height },
-            spa::utils::Rectangle { width, height }
+            min,
+            max
         ),
+        // A variable framerate, frames are produced as often as maxFramerate allows
         spa::pod::property!(
             spa::param::format::FormatProperties::VideoFramerate,
+            Fraction,
+            spa::utils::Fraction { num: 0, denom: 1 }
+        ),
+        spa::pod::property!(
+            spa::param::format::FormatProperties::VideoMaxFramerate,
             Choice,
             Range,
             Fraction,
-            spa::utils::Fraction { num: 60, denom: 1 },
-            spa::utils::Fraction { num: 60, denom: 1 },
-            spa::utils::Fraction { num: 60, denom: 1 }
+            spa::utils::Fraction {
+                num: max_framerate,
+                denom: 1
+            },
+            spa::utils::Fraction { num: 1, denom: 1 },
+            spa::utils::Fraction {
+                num: max_framerate,
+                denom: 1
+            }
         ),
-        // TODO max framerate
     );
 
     let format_choice =
@@ -493,6 +2133,30 @@ fn format(width: u32, height: u32, available_video_formats: Vec<VideoFormat>) ->
         flags: pod::PropertyFlags::empty(),
         value: format_choice,
     });
+    let modifier = match modifier {
+        ModifierParam::None => None,
+        ModifierParam::Fixed(modifier) => Some((
+            pod::PropertyFlags::MANDATORY,
+            pod::Value::Long(modifier as i64),
+        )),
+        ModifierParam::Choice(modifiers) => Some((
+            pod::PropertyFlags::MANDATORY | pod::PropertyFlags::DONT_FIXATE,
+            pod::Value::Choice(pod::ChoiceValue::Long(spa::utils::Choice(
+                spa::utils::ChoiceFlags::empty(),
+                spa::utils::ChoiceEnum::Enum {
+                    default: modifiers[0] as i64,
+                    alternatives: modifiers.iter().map(|modifier| *modifier as i64).collect(),
+                },
+            ))),
+        )),
+    };
+    if let Some((flags, value)) = modifier {
+        obj.properties.push(pod::Property {
+            key: spa::param::format::FormatProperties::VideoModifier.as_raw(),
+            flags,
+            value,
+        });
+    }
     value_to_bytes(pod::Value::Object(obj))
 }
 
@@ -540,3 +2204,295 @@ fn wl_shm_format_to_spa(format: Format) -> Option<VideoFormat> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(bytes: &[u8]) -> pod::Object {
+        let (_, value) = PodDeserializer::deserialize_any_from(bytes).unwrap();
+        let pod::Value::Object(object) = value else {
+            panic!("not an object: {value:?}");
+        };
+        object
+    }
+
+    fn property(object: &pod::Object, key: u32) -> Option<&pod::Property> {
+        object
+            .properties
+            .iter()
+            .find(|property| property.key == key)
+    }
+
+    fn value(object: &pod::Object, key: u32) -> &pod::Value {
+        &property(object, key).unwrap().value
+    }
+
+    fn int_range(default: i32, min: i32, max: i32) -> pod::Value {
+        pod::Value::Choice(pod::ChoiceValue::Int(spa::utils::Choice(
+            spa::utils::ChoiceFlags::empty(),
+            spa::utils::ChoiceEnum::Range { default, min, max },
+        )))
+    }
+
+    fn fraction(num: u32) -> spa::utils::Fraction {
+        spa::utils::Fraction { num, denom: 1 }
+    }
+
+    fn rectangle(width: u32, height: u32) -> spa::utils::Rectangle {
+        spa::utils::Rectangle { width, height }
+    }
+
+    const SIZE: libwayshot::Size = libwayshot::Size {
+        width: 1920,
+        height: 1080,
+    };
+    const FORMATS: [VideoFormat; 2] = [VideoFormat::BGRx, VideoFormat::BGRA];
+    const VIDEO_SIZE: u32 = libspa_sys::SPA_FORMAT_VIDEO_size;
+    const VIDEO_MODIFIER: u32 = libspa_sys::SPA_FORMAT_VIDEO_modifier;
+
+    #[test]
+    fn format_offers_sizes_up_to_the_max() {
+        let max = libwayshot::Size {
+            width: 3840,
+            height: 2160,
+        };
+        let object = parse(&format(SIZE, Some(max), &FORMATS, ModifierParam::None, 60));
+        assert_eq!(object.id, libspa_sys::SPA_PARAM_EnumFormat);
+        assert_eq!(
+            value(&object, VIDEO_SIZE),
+            &pod::Value::Choice(pod::ChoiceValue::Rectangle(spa::utils::Choice(
+                spa::utils::ChoiceFlags::empty(),
+                spa::utils::ChoiceEnum::Range {
+                    default: rectangle(1920, 1080),
+                    min: rectangle(1, 1),
+                    max: rectangle(3840, 2160),
+                },
+            )))
+        );
+        assert!(property(&object, VIDEO_MODIFIER).is_none());
+    }
+
+    #[test]
+    fn format_without_max_size_fixes_the_size() {
+        let object = parse(&format(SIZE, None, &FORMATS, ModifierParam::None, 60));
+        assert_eq!(
+            value(&object, VIDEO_SIZE),
+            &pod::Value::Choice(pod::ChoiceValue::Rectangle(spa::utils::Choice(
+                spa::utils::ChoiceFlags::empty(),
+                spa::utils::ChoiceEnum::Range {
+                    default: rectangle(1920, 1080),
+                    min: rectangle(1920, 1080),
+                    max: rectangle(1920, 1080),
+                },
+            )))
+        );
+    }
+
+    #[test]
+    fn format_framerate_is_variable_up_to_the_max() {
+        let object = parse(&format(SIZE, None, &FORMATS, ModifierParam::None, 30));
+        assert_eq!(
+            value(&object, libspa_sys::SPA_FORMAT_VIDEO_framerate),
+            &pod::Value::Fraction(spa::utils::Fraction { num: 0, denom: 1 })
+        );
+        assert_eq!(
+            value(&object, libspa_sys::SPA_FORMAT_VIDEO_maxFramerate),
+            &pod::Value::Choice(pod::ChoiceValue::Fraction(spa::utils::Choice(
+                spa::utils::ChoiceFlags::empty(),
+                spa::utils::ChoiceEnum::Range {
+                    default: fraction(30),
+                    min: fraction(1),
+                    max: fraction(30),
+                },
+            )))
+        );
+
+        let object = parse(&format(SIZE, None, &FORMATS, ModifierParam::None, 0));
+        let pod::Value::Choice(pod::ChoiceValue::Fraction(spa::utils::Choice(
+            _,
+            spa::utils::ChoiceEnum::Range { max, .. },
+        ))) = value(&object, libspa_sys::SPA_FORMAT_VIDEO_maxFramerate)
+        else {
+            panic!("the max framerate is not a range");
+        };
+        assert_eq!(*max, fraction(1));
+    }
+
+    #[test]
+    fn format_video_formats_default_to_the_first() {
+        let object = parse(&format(SIZE, None, &FORMATS, ModifierParam::None, 60));
+        let pod::Value::Choice(pod::ChoiceValue::Id(spa::utils::Choice(
+            _,
+            spa::utils::ChoiceEnum::Enum { default, .. },
+        ))) = value(&object, libspa_sys::SPA_FORMAT_VIDEO_format)
+        else {
+            panic!("the video format is not an enum");
+        };
+        assert_eq!(default.0, VideoFormat::BGRx.as_raw());
+    }
+
+    #[test]
+    fn modifier_choice_is_left_to_the_client() {
+        let modifiers = [0x0100_0000_0000_0002, 0, DRM_FORMAT_MOD_INVALID];
+        let bytes = format(SIZE, None, &FORMATS, ModifierParam::Choice(&modifiers), 60);
+        let object = parse(&bytes);
+        assert_eq!(
+            property(&object, VIDEO_MODIFIER).unwrap().flags,
+            pod::PropertyFlags::MANDATORY | pod::PropertyFlags::DONT_FIXATE
+        );
+        let ModifierProperty::Choice(parsed) = modifier_property(Pod::from_bytes(&bytes).unwrap())
+        else {
+            panic!("the modifier is not left open");
+        };
+        assert_eq!(parsed, [0, DRM_FORMAT_MOD_INVALID, 0x0100_0000_0000_0002]);
+    }
+
+    #[test]
+    fn modifier_choice_without_dont_fixate_takes_the_default() {
+        let modifiers = [0x0100_0000_0000_0002, 0];
+        let mut object = parse(&format(
+            SIZE,
+            None,
+            &FORMATS,
+            ModifierParam::Choice(&modifiers),
+            60,
+        ));
+        for property in &mut object.properties {
+            if property.key == VIDEO_MODIFIER {
+                property.flags.remove(pod::PropertyFlags::DONT_FIXATE);
+            }
+        }
+        let bytes = value_to_bytes(pod::Value::Object(object));
+        assert!(matches!(
+            modifier_property(Pod::from_bytes(&bytes).unwrap()),
+            ModifierProperty::Fixed(0x0100_0000_0000_0002)
+        ));
+    }
+
+    #[test]
+    fn modifier_fixed_and_missing() {
+        let bytes = format(SIZE, None, &FORMATS, ModifierParam::Fixed(0), 60);
+        assert_eq!(
+            property(&parse(&bytes), VIDEO_MODIFIER).unwrap().flags,
+            pod::PropertyFlags::MANDATORY
+        );
+        assert!(matches!(
+            modifier_property(Pod::from_bytes(&bytes).unwrap()),
+            ModifierProperty::Fixed(0)
+        ));
+
+        let bytes = format(SIZE, None, &FORMATS, ModifierParam::None, 60);
+        assert!(matches!(
+            modifier_property(Pod::from_bytes(&bytes).unwrap()),
+            ModifierProperty::None
+        ));
+    }
+
+    #[test]
+    fn bounding_box_reaches_left_of_and_above_the_origin() {
+        let region = |x, y, width, height| Region {
+            position: Position { x, y },
+            size: libwayshot::Size { width, height },
+        };
+        assert_eq!(
+            bounding_box_of(&[region(-1920, -200, 1920, 1080), region(0, 0, 2560, 1440)]),
+            region(-1920, -200, 4480, 1640)
+        );
+        assert_eq!(bounding_box_of(&[]), Region::default());
+    }
+
+    #[test]
+    fn memfd_buffers_have_a_size_and_stride() {
+        let object = parse(&buffers(1920, 1080, BufferType::MemFd));
+        assert_eq!(object.id, libspa_sys::SPA_PARAM_Buffers);
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_BUFFERS_dataType),
+            &pod::Value::Choice(pod::ChoiceValue::Int(spa::utils::Choice(
+                spa::utils::ChoiceFlags::empty(),
+                spa::utils::ChoiceEnum::Flags {
+                    default: 1 << libspa_sys::SPA_DATA_MemFd,
+                    flags: vec![],
+                },
+            )))
+        );
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_BUFFERS_size),
+            &pod::Value::Int(1920 * 1080 * 4)
+        );
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_BUFFERS_stride),
+            &pod::Value::Int(1920 * 4)
+        );
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_BUFFERS_blocks),
+            &pod::Value::Int(1)
+        );
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_BUFFERS_buffers),
+            &int_range(4, 1, 32)
+        );
+    }
+
+    #[test]
+    fn dmabuf_buffers_leave_the_layout_to_the_modifier() {
+        let object = parse(&buffers(1920, 1080, BufferType::DmaBuf { planes: 2 }));
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_BUFFERS_dataType),
+            &pod::Value::Choice(pod::ChoiceValue::Int(spa::utils::Choice(
+                spa::utils::ChoiceFlags::empty(),
+                spa::utils::ChoiceEnum::Flags {
+                    default: 1 << libspa_sys::SPA_DATA_DmaBuf,
+                    flags: vec![],
+                },
+            )))
+        );
+        assert!(property(&object, libspa_sys::SPA_PARAM_BUFFERS_size).is_none());
+        assert!(property(&object, libspa_sys::SPA_PARAM_BUFFERS_stride).is_none());
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_BUFFERS_blocks),
+            &pod::Value::Int(2)
+        );
+
+        let object = parse(&buffers(1920, 1080, BufferType::Any));
+        assert!(property(&object, libspa_sys::SPA_PARAM_BUFFERS_dataType).is_none());
+    }
+
+    #[test]
+    fn damage_meta_holds_up_to_the_max_regions() {
+        let object = parse(&damage_meta_param());
+        let region_size = size_of::<libspa_sys::spa_meta_region>() as i32;
+        assert_eq!(object.id, libspa_sys::SPA_PARAM_Meta);
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_META_type),
+            &pod::Value::Id(spa::utils::Id(libspa_sys::SPA_META_VideoDamage))
+        );
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_META_size),
+            &int_range(
+                region_size * DAMAGE_META_MAX_REGIONS as i32,
+                region_size,
+                region_size * DAMAGE_META_MAX_REGIONS as i32,
+            )
+        );
+    }
+
+    #[test]
+    fn cursor_meta_fits_the_largest_bitmap() {
+        let object = parse(&cursor_meta_param());
+        let header = (size_of::<libspa_sys::spa_meta_cursor>()
+            + size_of::<libspa_sys::spa_meta_bitmap>()) as i32;
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_META_type),
+            &pod::Value::Id(spa::utils::Id(libspa_sys::SPA_META_Cursor))
+        );
+        assert_eq!(
+            value(&object, libspa_sys::SPA_PARAM_META_size),
+            &int_range(
+                header + 64 * 64 * 4,
+                header + 4,
+                header + (CURSOR_META_MAX_SIZE * CURSOR_META_MAX_SIZE * 4) as i32,
+            )
+        );
+    }
+}
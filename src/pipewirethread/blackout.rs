@@ -0,0 +1,213 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+use libwayshot::region::{Position, Region};
+use pipewire::spa::param::video::VideoFormat;
+use wayland_client::protocol::wl_output::Transform;
+
+use super::composite::{frame_position, upright_size};
+use super::placeholder::gray_pixel;
+
+/// A region of the compositor space in pixels of an image of `size` showing the logical
+/// region `logical`. It grows to whole pixels and is clipped to the image, None when nothing
+/// of it is inside
+pub(crate) fn logical_to_buffer_rect(
+    logical: Region,
+    size: libwayshot::Size,
+    region: Region,
+) -> Option<Region> {
+    let scale_x = size.width as f64 / logical.size.width.max(1) as f64;
+    let scale_y = size.height as f64 / logical.size.height.max(1) as f64;
+    let scaled = |value: i32, origin: i32, scale: f64, limit: u32, round_up: bool| {
+        let value = (value - origin) as f64 * scale;
+        let value = if round_up {
+            value.ceil()
+        } else {
+            value.floor()
+        };
+        (value.max(0.0) as u32).min(limit)
+    };
+    let left = scaled(
+        region.position.x,
+        logical.position.x,
+        scale_x,
+        size.width,
+        false,
+    );
+    let top = scaled(
+        region.position.y,
+        logical.position.y,
+        scale_y,
+        size.height,
+        false,
+    );
+    let right = scaled(
+        region.position.x + region.size.width as i32,
+        logical.position.x,
+        scale_x,
+        size.width,
+        true,
+    );
+    let bottom = scaled(
+        region.position.y + region.size.height as i32,
+        logical.position.y,
+        scale_y,
+        size.height,
+        true,
+    );
+    if right <= left || bottom <= top {
+        return None;
+    }
+    Some(Region {
+        position: Position {
+            x: left as i32,
+            y: top as i32,
+        },
+        size: libwayshot::Size {
+            width: right - left,
+            height: bottom - top,
+        },
+    })
+}
+
+/// A region of the compositor space in pixels of the frame of the output at `logical`, which
+/// the compositor hands over unrotated
+pub(super) fn output_frame_region(
+    logical: Region,
+    transform: Transform,
+    frame_size: libwayshot::Size,
+    region: Region,
+) -> Option<Region> {
+    let upright = logical_to_buffer_rect(logical, upright_size(transform, frame_size), region)?;
+    let (left, top) = (upright.position.x as u32, upright.position.y as u32);
+    let right = left + upright.size.width;
+    let bottom = top + upright.size.height;
+    // The corners stay corners under any rotation
+    let (start_x, start_y) = frame_position(transform, frame_size, left, top);
+    let (end_x, end_y) = frame_position(transform, frame_size, right - 1, bottom - 1);
+    Some(Region {
+        position: Position {
+            x: start_x.min(end_x) as i32,
+            y: start_y.min(end_y) as i32,
+        },
+        size: libwayshot::Size {
+            width: start_x.abs_diff(end_x) + 1,
+            height: start_y.abs_diff(end_y) + 1,
+        },
+    })
+}
+
+/// Paint the regions black in a packed frame of `size`
+pub(super) fn black_out(
+    memory: &File,
+    size: libwayshot::Size,
+    regions: &[Region],
+    format: Option<VideoFormat>,
+) -> io::Result<()> {
+    let black = gray_pixel(format).map_or([0; 4], |pixel| pixel(0));
+    for region in regions {
+        let left = region.position.x.clamp(0, size.width as i32) as usize;
+        let top = region.position.y.clamp(0, size.height as i32) as usize;
+        let right = (region.position.x + region.size.width as i32).clamp(0, size.width as i32);
+        let bottom = (region.position.y + region.size.height as i32).clamp(0, size.height as i32);
+        let (right, bottom) = (right as usize, bottom as usize);
+        if right <= left {
+            continue;
+        }
+        let row = black.repeat(right - left);
+        for y in top..bottom {
+            memory.write_all_at(&row, ((y * size.width as usize + left) * 4) as u64)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(x: i32, y: i32, width: u32, height: u32) -> Region {
+        Region {
+            position: Position { x, y },
+            size: libwayshot::Size { width, height },
+        }
+    }
+
+    // A 1920x1080 output right of another one, with a scale of 2
+    const OUTPUT: Region = Region {
+        position: Position { x: 1920, y: 0 },
+        size: libwayshot::Size {
+            width: 1920,
+            height: 1080,
+        },
+    };
+    const FRAME: libwayshot::Size = libwayshot::Size {
+        width: 3840,
+        height: 2160,
+    };
+
+    #[test]
+    fn logical_rect_grows_to_whole_pixels() {
+        // A screenshot of 1.5 pixels per logical pixel
+        let size = libwayshot::Size {
+            width: 2880,
+            height: 1620,
+        };
+        assert_eq!(
+            logical_to_buffer_rect(OUTPUT, size, region(1921, 1, 3, 3)),
+            Some(region(1, 1, 5, 5))
+        );
+        assert_eq!(
+            logical_to_buffer_rect(OUTPUT, size, region(3800, 1000, 200, 200)),
+            Some(region(2820, 1500, 60, 120))
+        );
+        assert_eq!(
+            logical_to_buffer_rect(OUTPUT, size, region(1920, 1080, 10, 10)),
+            None
+        );
+    }
+
+    #[test]
+    fn window_is_moved_into_the_output_and_scaled() {
+        assert_eq!(
+            output_frame_region(
+                OUTPUT,
+                Transform::Normal,
+                FRAME,
+                region(2020, 100, 200, 100)
+            ),
+            Some(region(200, 200, 400, 200))
+        );
+    }
+
+    #[test]
+    fn window_is_clipped_to_the_output() {
+        assert_eq!(
+            output_frame_region(
+                OUTPUT,
+                Transform::Normal,
+                FRAME,
+                region(1800, -50, 300, 150)
+            ),
+            Some(region(0, 0, 360, 200))
+        );
+        assert_eq!(
+            output_frame_region(OUTPUT, Transform::Normal, FRAME, region(0, 0, 1920, 1080)),
+            None
+        );
+    }
+
+    #[test]
+    fn window_on_a_rotated_output_is_rotated_into_the_frame() {
+        let output = region(0, 0, 1080, 1920);
+        let frame = libwayshot::Size {
+            width: 1920,
+            height: 1080,
+        };
+        assert_eq!(
+            output_frame_region(output, Transform::_90, frame, region(0, 0, 100, 50)),
+            Some(region(1870, 0, 50, 100))
+        );
+    }
+}
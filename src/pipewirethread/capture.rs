@@ -0,0 +1,354 @@
+use std::io;
+use std::os::fd::AsFd;
+
+use gbm::BufferObject;
+
+use libwayshot::WayshotConnection;
+use libwayshot::reexport::FailureReason;
+use libwayshot::region::{Position, Region};
+use wayland_client::{
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum, backend::WaylandError,
+    delegate_noop, globals::GlobalList, protocol::wl_buffer::WlBuffer,
+};
+use wayland_protocols::ext::image_capture_source::v1::client::{
+    ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
+    ext_image_capture_source_v1::ExtImageCaptureSourceV1,
+    ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+};
+use wayland_protocols::ext::image_copy_capture::v1::client::{
+    ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1},
+    ext_image_copy_capture_manager_v1::{ExtImageCopyCaptureManagerV1, Options},
+    ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
+};
+use wayland_protocols::wp::linux_dmabuf::zv1::client::{
+    zwp_linux_buffer_params_v1::{self, ZwpLinuxBufferParamsV1},
+    zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
+};
+
+use super::CastTarget;
+use super::dmabuf::{DmabufBuffer, DmabufFormat};
+
+/// Create the ext-image-capture source of a cast target on the given queue
+pub(super) fn image_capture_source<State>(
+    globals: &GlobalList,
+    qh: &QueueHandle<State>,
+    target: &CastTarget,
+) -> anyhow::Result<ExtImageCaptureSourceV1>
+where
+    State: Dispatch<ExtOutputImageCaptureSourceManagerV1, ()>
+        + Dispatch<ExtForeignToplevelImageCaptureSourceManagerV1, ()>
+        + Dispatch<ExtImageCaptureSourceV1, ()>
+        + 'static,
+{
+    let source = match target {
+        CastTarget::Screen(output) | CastTarget::Area { output, .. } => {
+            let source_manager: ExtOutputImageCaptureSourceManagerV1 =
+                globals.bind(qh, 1..=1, ())?;
+            let source = source_manager.create_source(output, qh, ());
+            source_manager.destroy();
+            source
+        }
+        CastTarget::TopLevel(toplevel) => {
+            let source_manager: ExtForeignToplevelImageCaptureSourceManagerV1 =
+                globals.bind(qh, 1..=1, ())?;
+            let source = source_manager.create_source(toplevel, qh, ());
+            source_manager.destroy();
+            source
+        }
+        CastTarget::AllOutputs => anyhow::bail!("All outputs have no capture source in common"),
+    };
+    Ok(source)
+}
+
+/// Take in the events which already arrived on the socket, without blocking
+pub(super) fn dispatch_nonblocking<State>(
+    event_queue: &mut EventQueue<State>,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    event_queue.dispatch_pending(state)?;
+    event_queue.flush()?;
+    if let Some(guard) = event_queue.prepare_read() {
+        match guard.read() {
+            Ok(_) => {}
+            Err(WaylandError::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    event_queue.dispatch_pending(state)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub(super) enum FrameResult {
+    Pending,
+    /// The frame is copied, with the regions which changed since the previous frame
+    Ready(Vec<Region>),
+    Failed(FailureReason),
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    buffer_size: (u32, u32),
+    dmabuf_device: Option<u64>,
+    dmabuf_formats: Vec<DmabufFormat>,
+    // the next constraint event starts a new set of constraints
+    constraints_done: bool,
+    stopped: bool,
+    damage: Vec<Region>,
+    ready: bool,
+    failed: Option<FailureReason>,
+}
+
+/// A capture session which lives as long as the stream. Unlike a new session per frame,
+/// the compositor holds each frame back until the target is damaged, and tells us where
+pub(super) struct FrameSession {
+    event_queue: EventQueue<SessionState>,
+    qh: QueueHandle<SessionState>,
+    state: SessionState,
+    source: ExtImageCaptureSourceV1,
+    session: ExtImageCopyCaptureSessionV1,
+    frame: Option<ExtImageCopyCaptureFrameV1>,
+    linux_dmabuf: Option<ZwpLinuxDmabufV1>,
+}
+
+impl std::fmt::Debug for FrameSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FrameSession")
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FrameSession {
+    pub(super) fn new(
+        connection: &WayshotConnection,
+        target: &CastTarget,
+        overlay_cursor: bool,
+    ) -> anyhow::Result<Self> {
+        let mut event_queue = connection.conn.new_event_queue();
+        let qh = event_queue.handle();
+        let globals = &connection.globals;
+
+        let manager: ExtImageCopyCaptureManagerV1 = globals.bind(&qh, 1..=1, ())?;
+        let source = image_capture_source(globals, &qh, target)?;
+        let options = if overlay_cursor {
+            Options::PaintCursors
+        } else {
+            Options::empty()
+        };
+        let session = manager.create_session(&source, options, &qh, ());
+        manager.destroy();
+        let linux_dmabuf = globals.bind(&qh, 4..=5, ()).ok();
+
+        let mut state = SessionState::default();
+        event_queue.roundtrip(&mut state)?;
+
+        Ok(Self {
+            event_queue,
+            qh,
+            state,
+            source,
+            session,
+            frame: None,
+            linux_dmabuf,
+        })
+    }
+
+    /// The buffer size from the latest constraints of the compositor
+    pub(super) fn size(&self) -> libwayshot::Size {
+        let (width, height) = self.state.buffer_size;
+        libwayshot::Size { width, height }
+    }
+
+    /// The dev_t of the device dmabufs have to be allocated on
+    pub(super) fn dmabuf_device(&self) -> Option<u64> {
+        self.linux_dmabuf.as_ref()?;
+        self.state.dmabuf_device
+    }
+
+    pub(super) fn dmabuf_formats(&self) -> &[DmabufFormat] {
+        &self.state.dmabuf_formats
+    }
+
+    /// Wrap a dmabuf into a wl_buffer the compositor can copy into
+    pub(super) fn create_dmabuf_buffer(
+        &self,
+        bo: BufferObject<()>,
+        fourcc: u32,
+    ) -> anyhow::Result<DmabufBuffer> {
+        let Some(linux_dmabuf) = &self.linux_dmabuf else {
+            return Err(anyhow::anyhow!("The compositor has no linux-dmabuf"));
+        };
+        let modifier: u64 = bo.modifier().into();
+        let params = linux_dmabuf.create_params(&self.qh, ());
+        for plane in 0..bo.plane_count() as i32 {
+            let fd = bo.fd_for_plane(plane)?;
+            params.add(
+                fd.as_fd(),
+                plane as u32,
+                bo.offset(plane),
+                bo.stride_for_plane(plane),
+                (modifier >> 32) as u32,
+                (modifier & 0xffffffff) as u32,
+            );
+        }
+        let buffer = params.create_immed(
+            bo.width() as i32,
+            bo.height() as i32,
+            fourcc,
+            zwp_linux_buffer_params_v1::Flags::empty(),
+            &self.qh,
+            (),
+        );
+        params.destroy();
+        Ok(DmabufBuffer { bo, buffer })
+    }
+
+    pub(super) fn capture(&mut self, buffer: &WlBuffer) {
+        if let Some(frame) = self.frame.take() {
+            frame.destroy();
+        }
+        self.state.damage.clear();
+        self.state.ready = false;
+        self.state.failed = None;
+        if self.state.stopped {
+            self.state.failed = Some(FailureReason::Stopped);
+            return;
+        }
+
+        let (width, height) = self.state.buffer_size;
+        let frame = self.session.create_frame(&self.qh, ());
+        frame.attach_buffer(buffer);
+        // NOTE: buffers rotate, so every one of them is written completely
+        frame.damage_buffer(0, 0, width as i32, height as i32);
+        frame.capture();
+        self.frame = Some(frame);
+    }
+
+    /// Drop the frame in flight, its buffer is going away
+    pub(super) fn cancel(&mut self) {
+        if let Some(frame) = self.frame.take() {
+            frame.destroy();
+        }
+        self.state.ready = false;
+        self.state.failed = None;
+    }
+
+    pub(super) fn poll(&mut self) -> FrameResult {
+        if let Err(e) = dispatch_nonblocking(&mut self.event_queue, &mut self.state) {
+            tracing::warn!("Cannot dispatch capture events: {e}");
+            return FrameResult::Failed(FailureReason::Unknown);
+        }
+        let result = if let Some(reason) = self.state.failed.take() {
+            FrameResult::Failed(reason)
+        } else if self.state.ready {
+            FrameResult::Ready(std::mem::take(&mut self.state.damage))
+        } else {
+            return FrameResult::Pending;
+        };
+        self.state.ready = false;
+        if let Some(frame) = self.frame.take() {
+            frame.destroy();
+        }
+        result
+    }
+}
+
+impl Drop for FrameSession {
+    fn drop(&mut self) {
+        if let Some(frame) = self.frame.take() {
+            frame.destroy();
+        }
+        self.session.destroy();
+        self.source.destroy();
+        if let Some(linux_dmabuf) = self.linux_dmabuf.take() {
+            linux_dmabuf.destroy();
+        }
+        let _ = self.event_queue.flush();
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for SessionState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ExtImageCopyCaptureSessionV1,
+        event: <ExtImageCopyCaptureSessionV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        if state.constraints_done
+            && !matches!(
+                event,
+                ext_image_copy_capture_session_v1::Event::Done
+                    | ext_image_copy_capture_session_v1::Event::Stopped
+            )
+        {
+            state.constraints_done = false;
+            state.dmabuf_device = None;
+            state.dmabuf_formats.clear();
+        }
+        match event {
+            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
+                state.buffer_size = (width, height);
+            }
+            ext_image_copy_capture_session_v1::Event::DmabufDevice { device } => {
+                state.dmabuf_device = device.try_into().ok().map(u64::from_ne_bytes);
+            }
+            ext_image_copy_capture_session_v1::Event::DmabufFormat { format, modifiers } => {
+                state.dmabuf_formats.push(DmabufFormat {
+                    fourcc: format,
+                    modifiers: modifiers
+                        .chunks_exact(8)
+                        .map(|modifier| u64::from_ne_bytes(modifier.try_into().unwrap()))
+                        .collect(),
+                });
+            }
+            ext_image_copy_capture_session_v1::Event::Done => state.constraints_done = true,
+            ext_image_copy_capture_session_v1::Event::Stopped => state.stopped = true,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, ()> for SessionState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ExtImageCopyCaptureFrameV1,
+        event: <ExtImageCopyCaptureFrameV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_image_copy_capture_frame_v1::Event::Damage {
+                x,
+                y,
+                width,
+                height,
+            } => state.damage.push(Region {
+                position: Position { x, y },
+                size: libwayshot::Size {
+                    width: width.max(0) as u32,
+                    height: height.max(0) as u32,
+                },
+            }),
+            ext_image_copy_capture_frame_v1::Event::Ready => state.ready = true,
+            ext_image_copy_capture_frame_v1::Event::Failed { reason } => {
+                state.failed = Some(match reason {
+                    WEnum::Value(reason) => reason,
+                    WEnum::Unknown(_) => FailureReason::Unknown,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(SessionState: ExtImageCopyCaptureManagerV1);
+delegate_noop!(SessionState: ExtOutputImageCaptureSourceManagerV1);
+delegate_noop!(SessionState: ExtForeignToplevelImageCaptureSourceManagerV1);
+delegate_noop!(SessionState: ExtImageCaptureSourceV1);
+delegate_noop!(SessionState: ignore ZwpLinuxDmabufV1);
+delegate_noop!(SessionState: ignore ZwpLinuxBufferParamsV1);
+delegate_noop!(SessionState: ignore WlBuffer);
@@ -0,0 +1,403 @@
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+
+use libwayshot::region::{Position, Region};
+use libwayshot::{OutputInfo, WayshotConnection, WayshotTarget, screencast::WayshotScreenCast};
+use pipewire::spa::param::video::VideoFormat;
+use wayland_client::protocol::wl_output::{Transform, WlOutput};
+use wayland_client::protocol::wl_shm::Format;
+
+use super::bounding_box_of;
+use super::placeholder::gray_pixel;
+
+// A larger frame is drawn at a lower scale, clients rarely take more
+const COMPOSITE_MAX_SIZE: u32 = 8192;
+// The space between the outputs is black
+const COMPOSITE_GAP_VALUE: u8 = 0;
+
+/// An output and its place in the composite frame
+#[derive(Debug)]
+struct CompositeOutput {
+    name: String,
+    output: WlOutput,
+    transform: Transform,
+    /// As the compositor hands the frame over, before the transform
+    frame_size: libwayshot::Size,
+    /// In pixels of the composite frame
+    place: Region,
+}
+
+impl CompositeOutput {
+    // Nearest neighbour is enough, most outputs share a scale and are copied row by row
+    fn draw(&self, pixels: &[u8], frame: &mut [u8], size: libwayshot::Size) {
+        let libwayshot::Size {
+            width: source_width,
+            height: source_height,
+        } = upright_size(self.transform, self.frame_size);
+        let Region {
+            position,
+            size: place,
+        } = self.place;
+        let visible_x = position.x.max(0)..(position.x + place.width as i32).min(size.width as i32);
+        if visible_x.is_empty() {
+            return;
+        }
+        let copy_rows = self.transform == Transform::Normal && source_width == place.width;
+        for y in 0..place.height {
+            let target_y = position.y + y as i32;
+            if target_y < 0 || target_y >= size.height as i32 {
+                continue;
+            }
+            let target_row = target_y as usize * size.width as usize * 4;
+            let source_y = (y as u64 * source_height as u64 / place.height as u64) as u32;
+            if copy_rows {
+                let source_x = (visible_x.start - position.x) as usize;
+                let source_start = (source_y as usize * source_width as usize + source_x) * 4;
+                let len = visible_x.len() * 4;
+                let target_start = target_row + visible_x.start as usize * 4;
+                frame[target_start..target_start + len]
+                    .copy_from_slice(&pixels[source_start..source_start + len]);
+                continue;
+            }
+            for target_x in visible_x.clone() {
+                let x = (target_x - position.x) as u32;
+                let source_x = (x as u64 * source_width as u64 / place.width as u64) as u32;
+                let (frame_x, frame_y) =
+                    frame_position(self.transform, self.frame_size, source_x, source_y);
+                let source =
+                    (frame_y as usize * self.frame_size.width as usize + frame_x as usize) * 4;
+                let target = target_row + target_x as usize * 4;
+                frame[target..target + 4].copy_from_slice(&pixels[source..source + 4]);
+            }
+        }
+    }
+}
+
+/// The capture of an output into memory of our own
+struct OutputCapture {
+    cast: WayshotScreenCast,
+    memory: File,
+}
+
+/// Every output drawn at its place in the compositor space. The frame has the scale of the
+/// densest output, outputs with a lower scale factor are scaled up to it
+pub(super) struct Composite {
+    outputs: Vec<CompositeOutput>,
+    /// The top left corner of the frame in the compositor space
+    origin: Position,
+    size: libwayshot::Size,
+    // frame pixels per logical pixel
+    scale: f64,
+    formats: Vec<Format>,
+    // created once the format is negotiated, an output which cannot be captured is None
+    captures: Option<(Format, Vec<Option<OutputCapture>>)>,
+}
+
+impl std::fmt::Debug for Composite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Composite")
+            .field("outputs", &self.outputs)
+            .field("origin", &self.origin)
+            .field("size", &self.size)
+            .field("scale", &self.scale)
+            .field("formats", &self.formats)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Composite {
+    pub(super) fn new(connection: &WayshotConnection) -> anyhow::Result<Self> {
+        let output_infos = connection.get_all_outputs();
+        if output_infos.is_empty() {
+            anyhow::bail!("There is no output to capture");
+        }
+        let mut formats: Option<Vec<Format>> = None;
+        let mut frame_sizes = Vec::with_capacity(output_infos.len());
+        for info in output_infos {
+            let frame_formats = connection
+                .get_available_frame_formats(&WayshotTarget::Screen(info.wl_output.clone()))?;
+            let Some(frame_format) = frame_formats.first() else {
+                anyhow::bail!("The output {} has no frame format", info.name);
+            };
+            frame_sizes.push(frame_format.size);
+            let output_formats: Vec<Format> = frame_formats
+                .iter()
+                .map(|frame_format| frame_format.format)
+                .collect();
+            formats = Some(match formats {
+                Some(formats) => formats
+                    .into_iter()
+                    .filter(|format| output_formats.contains(format))
+                    .collect(),
+                None => output_formats,
+            });
+        }
+        let formats = formats.unwrap_or_default();
+        if formats.is_empty() {
+            anyhow::bail!("The outputs have no frame format in common");
+        }
+
+        let Region {
+            position: origin,
+            size: space,
+        } = logical_extent(output_infos);
+        let scale = output_infos
+            .iter()
+            .zip(&frame_sizes)
+            .map(|(info, frame_size)| {
+                upright_size(info.transform, *frame_size).width as f64
+                    / info.logical_region.inner.size.width.max(1) as f64
+            })
+            .fold(1.0, f64::max)
+            .min(COMPOSITE_MAX_SIZE as f64 / space.width.max(space.height).max(1) as f64);
+        let scaled = |value: i32| (value as f64 * scale).round() as i32;
+        let outputs = output_infos
+            .iter()
+            .zip(frame_sizes)
+            .map(|(info, frame_size)| {
+                let region = info.logical_region.inner;
+                CompositeOutput {
+                    name: info.name.clone(),
+                    output: info.wl_output.clone(),
+                    transform: info.transform,
+                    frame_size,
+                    place: Region {
+                        position: Position {
+                            x: scaled(region.position.x - origin.x),
+                            y: scaled(region.position.y - origin.y),
+                        },
+                        size: libwayshot::Size {
+                            width: scaled(region.size.width as i32).max(1) as u32,
+                            height: scaled(region.size.height as i32).max(1) as u32,
+                        },
+                    },
+                }
+            })
+            .collect();
+        Ok(Self {
+            outputs,
+            origin,
+            size: libwayshot::Size {
+                width: scaled(space.width as i32).max(1) as u32,
+                height: scaled(space.height as i32).max(1) as u32,
+            },
+            scale,
+            formats,
+            captures: None,
+        })
+    }
+
+    pub(super) fn size(&self) -> libwayshot::Size {
+        self.size
+    }
+
+    /// A region of the compositor space in pixels of the frame
+    pub(super) fn frame_region(&self, region: Region) -> Region {
+        let x = region.position.x - self.origin.x;
+        let y = region.position.y - self.origin.y;
+        let left = (x as f64 * self.scale).floor() as i32;
+        let top = (y as f64 * self.scale).floor() as i32;
+        let right = ((x + region.size.width as i32) as f64 * self.scale).ceil();
+        let bottom = ((y + region.size.height as i32) as f64 * self.scale).ceil();
+        Region {
+            position: Position { x: left, y: top },
+            size: libwayshot::Size {
+                width: (right as i32 - left).max(0) as u32,
+                height: (bottom as i32 - top).max(0) as u32,
+            },
+        }
+    }
+
+    /// The formats every output can be captured in
+    pub(super) fn formats(&self) -> &[Format] {
+        &self.formats
+    }
+
+    /// Capture every output and draw the frame, an output which fails to capture is
+    /// filled like the gaps
+    pub(super) fn draw(
+        &mut self,
+        connection: &WayshotConnection,
+        format: Format,
+        video_format: Option<VideoFormat>,
+        overlay_cursor: bool,
+    ) -> Vec<u8> {
+        let libwayshot::Size { width, height } = self.size;
+        let mut frame = vec![0; width as usize * height as usize * 4];
+        if let Some(pixel) = gray_pixel(video_format) {
+            let gap = pixel(COMPOSITE_GAP_VALUE);
+            for chunk in frame.chunks_exact_mut(4) {
+                chunk.copy_from_slice(&gap);
+            }
+        }
+
+        if self
+            .captures
+            .as_ref()
+            .is_none_or(|(captures_format, _)| *captures_format != format)
+        {
+            let captures = self
+                .outputs
+                .iter()
+                .map(|output| {
+                    capture_output(connection, output, format, overlay_cursor)
+                        .inspect_err(|e| {
+                            tracing::error!("Cannot capture the output {}: {e}", output.name)
+                        })
+                        .ok()
+                })
+                .collect();
+            self.captures = Some((format, captures));
+        }
+        let Some((_, captures)) = self.captures.as_mut() else {
+            return frame;
+        };
+        for (output, capture) in self.outputs.iter().zip(captures.iter_mut()) {
+            let Some(capture) = capture else {
+                continue;
+            };
+            if let Err(e) = capture.cast.screencast() {
+                tracing::error!("Cannot capture the output {}: {e}", output.name);
+                continue;
+            }
+            let libwayshot::Size {
+                width: frame_width,
+                height: frame_height,
+            } = output.frame_size;
+            let mut pixels = vec![0; frame_width as usize * frame_height as usize * 4];
+            if let Err(e) = capture.memory.read_exact_at(&mut pixels, 0) {
+                tracing::error!("Cannot read the frame of {}: {e}", output.name);
+                continue;
+            }
+            output.draw(&pixels, &mut frame, self.size);
+        }
+        frame
+    }
+}
+
+fn capture_output(
+    connection: &WayshotConnection,
+    output: &CompositeOutput,
+    format: Format,
+    overlay_cursor: bool,
+) -> anyhow::Result<OutputCapture> {
+    let libwayshot::Size { width, height } = output.frame_size;
+    let fd = rustix::fs::memfd_create(
+        c"pipewire-screencopy-output",
+        rustix::fs::MemfdFlags::CLOEXEC,
+    )?;
+    rustix::fs::ftruncate(&fd, width as u64 * height as u64 * 4)?;
+    let cast = connection.create_screencast_with_shm(
+        WayshotTarget::Screen(output.output.clone()),
+        overlay_cursor,
+        format,
+        &fd,
+    )?;
+    Ok(OutputCapture {
+        cast,
+        memory: File::from(fd),
+    })
+}
+
+/// The bounding box of the outputs in the compositor space, which is logical. Outputs may
+/// be placed left of or above the origin
+pub(crate) fn logical_extent(outputs: &[OutputInfo]) -> Region {
+    let regions: Vec<Region> = outputs
+        .iter()
+        .map(|output| output.logical_region.inner)
+        .collect();
+    bounding_box_of(&regions)
+}
+
+/// The size of the output the way the user sees it
+pub(super) fn upright_size(transform: Transform, frame_size: libwayshot::Size) -> libwayshot::Size {
+    match transform {
+        Transform::_90 | Transform::_270 | Transform::Flipped90 | Transform::Flipped270 => {
+            libwayshot::Size {
+                width: frame_size.height,
+                height: frame_size.width,
+            }
+        }
+        _ => frame_size,
+    }
+}
+
+/// Where a pixel of the upright output is in the frame, the frame of a rotated output is
+/// handed over unrotated. Flipped outputs are drawn like their rotation alone
+pub(super) fn frame_position(
+    transform: Transform,
+    frame_size: libwayshot::Size,
+    x: u32,
+    y: u32,
+) -> (u32, u32) {
+    let libwayshot::Size { width, height } = frame_size;
+    match transform {
+        Transform::_90 | Transform::Flipped90 => (width - 1 - y, x),
+        Transform::_180 | Transform::Flipped180 => (width - 1 - x, height - 1 - y),
+        Transform::_270 | Transform::Flipped270 => (y, height - 1 - x),
+        _ => (x, y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: libwayshot::Size = libwayshot::Size {
+        width: 4,
+        height: 2,
+    };
+
+    const TRANSFORMS: [Transform; 8] = [
+        Transform::Normal,
+        Transform::_90,
+        Transform::_180,
+        Transform::_270,
+        Transform::Flipped,
+        Transform::Flipped90,
+        Transform::Flipped180,
+        Transform::Flipped270,
+    ];
+
+    #[test]
+    fn rotated_quarter_turns_swap_the_size() {
+        let swapped = libwayshot::Size {
+            width: 2,
+            height: 4,
+        };
+        assert_eq!(upright_size(Transform::Normal, FRAME), FRAME);
+        assert_eq!(upright_size(Transform::_180, FRAME), FRAME);
+        assert_eq!(upright_size(Transform::_90, FRAME), swapped);
+        assert_eq!(upright_size(Transform::_270, FRAME), swapped);
+        assert_eq!(upright_size(Transform::Flipped90, FRAME), swapped);
+    }
+
+    #[test]
+    fn frame_position_covers_the_frame_once() {
+        for transform in TRANSFORMS {
+            let upright = upright_size(transform, FRAME);
+            let mut seen = vec![false; (FRAME.width * FRAME.height) as usize];
+            for y in 0..upright.height {
+                for x in 0..upright.width {
+                    let (frame_x, frame_y) = frame_position(transform, FRAME, x, y);
+                    assert!(frame_x < FRAME.width && frame_y < FRAME.height);
+                    let index = (frame_y * FRAME.width + frame_x) as usize;
+                    assert!(!seen[index], "{transform:?} maps two pixels to one");
+                    seen[index] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frame_position_of_the_top_left_corner() {
+        assert_eq!(frame_position(Transform::Normal, FRAME, 0, 0), (0, 0));
+        assert_eq!(frame_position(Transform::_90, FRAME, 0, 0), (3, 0));
+        assert_eq!(frame_position(Transform::_180, FRAME, 0, 0), (3, 1));
+        assert_eq!(frame_position(Transform::_270, FRAME, 0, 0), (0, 1));
+        assert_eq!(
+            frame_position(Transform::Flipped90, FRAME, 0, 0),
+            frame_position(Transform::_90, FRAME, 0, 0)
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use std::{
+    fs::File,
+    os::{
+        fd::{AsFd, OwnedFd},
+        unix::fs::FileExt,
+    },
+};
+
+use libwayshot::WayshotConnection;
+use wayland_client::{
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle, WEnum, delegate_noop,
+    protocol::{
+        wl_buffer::WlBuffer,
+        wl_pointer::WlPointer,
+        wl_seat::WlSeat,
+        wl_shm::{Format, WlShm},
+        wl_shm_pool::WlShmPool,
+    },
+};
+use wayland_protocols::ext::image_capture_source::v1::client::{
+    ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1,
+    ext_image_capture_source_v1::ExtImageCaptureSourceV1,
+    ext_output_image_capture_source_manager_v1::ExtOutputImageCaptureSourceManagerV1,
+};
+use wayland_protocols::ext::image_copy_capture::v1::client::{
+    ext_image_copy_capture_cursor_session_v1::{self, ExtImageCopyCaptureCursorSessionV1},
+    ext_image_copy_capture_frame_v1::{self, ExtImageCopyCaptureFrameV1},
+    ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1,
+    ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
+};
+
+use super::capture::{dispatch_nonblocking, image_capture_source};
+use super::{CastTarget, wl_shm_format_to_spa};
+
+/// Everything the cursor metadata is built from, to tell when it needs sending again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct CursorSnapshot {
+    position: (i32, i32),
+    hotspot: (i32, i32),
+    inside: bool,
+    serial: u64,
+}
+
+pub(super) struct CursorBitmap {
+    pub(super) format: Format,
+    pub(super) width: u32,
+    pub(super) height: u32,
+    pub(super) data: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum FrameStatus {
+    #[default]
+    Idle,
+    Pending,
+    Ready,
+    Failed,
+}
+
+#[derive(Debug, Default)]
+struct CursorState {
+    inside: bool,
+    position: (i32, i32),
+    hotspot: (i32, i32),
+    buffer_size: (u32, u32),
+    pending_formats: Vec<Format>,
+    shm_formats: Vec<Format>,
+    constraints_changed: bool,
+    stopped: bool,
+    frame: FrameStatus,
+}
+
+struct CursorBuffer {
+    fd: OwnedFd,
+    pool: WlShmPool,
+    buffer: WlBuffer,
+    format: Format,
+    width: u32,
+    height: u32,
+}
+
+impl Drop for CursorBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        self.pool.destroy();
+    }
+}
+
+/// Follows the pointer over a cast target through an ext-image-copy-capture cursor session,
+/// so the cursor can be sent as stream metadata instead of being painted into the frames
+pub(super) struct CursorTracker {
+    event_queue: EventQueue<CursorState>,
+    qh: QueueHandle<CursorState>,
+    state: CursorState,
+    shm: WlShm,
+    source: ExtImageCaptureSourceV1,
+    pointer: WlPointer,
+    cursor_session: ExtImageCopyCaptureCursorSessionV1,
+    capture_session: ExtImageCopyCaptureSessionV1,
+    frame: Option<ExtImageCopyCaptureFrameV1>,
+    buffer: Option<CursorBuffer>,
+    bitmap: Option<CursorBitmap>,
+    serial: u64,
+}
+
+impl std::fmt::Debug for CursorTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CursorTracker")
+            .field("state", &self.state)
+            .field("serial", &self.serial)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CursorTracker {
+    pub(super) fn new(connection: &WayshotConnection, target: &CastTarget) -> anyhow::Result<Self> {
+        // NOTE: a queue of our own, so libwayshot's blocking dispatch never runs our handlers
+        let mut event_queue = connection.conn.new_event_queue();
+        let qh = event_queue.handle();
+        let globals = &connection.globals;
+
+        let manager: ExtImageCopyCaptureManagerV1 = globals.bind(&qh, 1..=1, ())?;
+        let seat: WlSeat = globals.bind(&qh, 1..=9, ())?;
+        let shm: WlShm = globals.bind(&qh, 1..=1, ())?;
+        let source = image_capture_source(globals, &qh, target)?;
+        let pointer = seat.get_pointer(&qh, ());
+        let cursor_session = manager.create_pointer_cursor_session(&source, &pointer, &qh, ());
+        let capture_session = cursor_session.get_capture_session(&qh, ());
+        manager.destroy();
+
+        let mut state = CursorState::default();
+        event_queue.roundtrip(&mut state)?;
+
+        Ok(Self {
+            event_queue,
+            qh,
+            state,
+            shm,
+            source,
+            pointer,
+            cursor_session,
+            capture_session,
+            frame: None,
+            buffer: None,
+            bitmap: None,
+            serial: 0,
+        })
+    }
+
+    /// Whether the pointer is currently over the cast target
+    pub(super) fn inside(&self) -> bool {
+        self.state.inside
+    }
+
+    /// Position of the hotspot, relative to the cast target
+    pub(super) fn position(&self) -> (i32, i32) {
+        self.state.position
+    }
+
+    pub(super) fn hotspot(&self) -> (i32, i32) {
+        self.state.hotspot
+    }
+
+    pub(super) fn snapshot(&self) -> CursorSnapshot {
+        CursorSnapshot {
+            position: self.state.position,
+            hotspot: self.state.hotspot,
+            inside: self.state.inside,
+            serial: self.serial,
+        }
+    }
+
+    /// The latest cursor image with a serial which changes every time the image does
+    pub(super) fn bitmap(&self) -> Option<(&CursorBitmap, u64)> {
+        self.bitmap.as_ref().map(|bitmap| (bitmap, self.serial))
+    }
+
+    /// Take in whatever arrived without blocking, and keep one cursor image capture in flight
+    pub(super) fn update(&mut self) {
+        if let Err(e) = dispatch_nonblocking(&mut self.event_queue, &mut self.state) {
+            tracing::warn!("Cannot dispatch cursor events: {e}");
+            return;
+        }
+        match self.state.frame {
+            FrameStatus::Ready => {
+                self.read_bitmap();
+                self.destroy_frame();
+            }
+            FrameStatus::Failed => self.destroy_frame(),
+            FrameStatus::Idle | FrameStatus::Pending => {}
+        }
+        if self.state.frame == FrameStatus::Idle && !self.state.stopped {
+            self.capture();
+        }
+    }
+
+    fn destroy_frame(&mut self) {
+        if let Some(frame) = self.frame.take() {
+            frame.destroy();
+        }
+        self.state.frame = FrameStatus::Idle;
+    }
+
+    fn capture(&mut self) {
+        if self.state.constraints_changed || self.buffer.is_none() {
+            self.state.constraints_changed = false;
+            self.buffer = self.create_buffer();
+        }
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        // NOTE: the compositor holds the frame back until the cursor image changes
+        let frame = self.capture_session.create_frame(&self.qh, ());
+        frame.attach_buffer(&buffer.buffer);
+        frame.damage_buffer(0, 0, buffer.width as i32, buffer.height as i32);
+        frame.capture();
+        self.frame = Some(frame);
+        self.state.frame = FrameStatus::Pending;
+    }
+
+    fn create_buffer(&self) -> Option<CursorBuffer> {
+        let (width, height) = self.state.buffer_size;
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let format = self
+            .state
+            .shm_formats
+            .iter()
+            .copied()
+            .find(|format| *format == Format::Argb8888)
+            .or_else(|| {
+                self.state
+                    .shm_formats
+                    .iter()
+                    .copied()
+                    .find(|format| wl_shm_format_to_spa(*format).is_some())
+            })?;
+        let stride = width * 4;
+        let size = stride * height;
+        let fd =
+            rustix::fs::memfd_create(c"luminous-cursor", rustix::fs::MemfdFlags::CLOEXEC).ok()?;
+        rustix::fs::ftruncate(&fd, size as _).ok()?;
+        let pool = self.shm.create_pool(fd.as_fd(), size as i32, &self.qh, ());
+        let buffer = pool.create_buffer(
+            0,
+            width as i32,
+            height as i32,
+            stride as i32,
+            format,
+            &self.qh,
+            (),
+        );
+        Some(CursorBuffer {
+            fd,
+            pool,
+            buffer,
+            format,
+            width,
+            height,
+        })
+    }
+
+    fn read_bitmap(&mut self) {
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+        let mut data = vec![0; (buffer.width * buffer.height * 4) as usize];
+        let read = buffer
+            .fd
+            .try_clone()
+            .map(File::from)
+            .and_then(|file| file.read_exact_at(&mut data, 0));
+        if let Err(e) = read {
+            tracing::warn!("Cannot read the cursor image: {e}");
+            return;
+        }
+        self.bitmap = Some(CursorBitmap {
+            format: buffer.format,
+            width: buffer.width,
+            height: buffer.height,
+            data,
+        });
+        self.serial += 1;
+    }
+}
+
+impl Drop for CursorTracker {
+    fn drop(&mut self) {
+        if let Some(frame) = self.frame.take() {
+            frame.destroy();
+        }
+        self.buffer.take();
+        self.capture_session.destroy();
+        self.cursor_session.destroy();
+        self.source.destroy();
+        if self.pointer.version() >= 3 {
+            self.pointer.release();
+        }
+        let _ = self.event_queue.flush();
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureCursorSessionV1, ()> for CursorState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ExtImageCopyCaptureCursorSessionV1,
+        event: <ExtImageCopyCaptureCursorSessionV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_image_copy_capture_cursor_session_v1::Event::Enter => state.inside = true,
+            ext_image_copy_capture_cursor_session_v1::Event::Leave => state.inside = false,
+            ext_image_copy_capture_cursor_session_v1::Event::Position { x, y } => {
+                state.position = (x, y);
+            }
+            ext_image_copy_capture_cursor_session_v1::Event::Hotspot { x, y } => {
+                state.hotspot = (x, y);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureSessionV1, ()> for CursorState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ExtImageCopyCaptureSessionV1,
+        event: <ExtImageCopyCaptureSessionV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_image_copy_capture_session_v1::Event::BufferSize { width, height } => {
+                state.buffer_size = (width, height);
+            }
+            ext_image_copy_capture_session_v1::Event::ShmFormat {
+                format: WEnum::Value(format),
+            } => {
+                state.pending_formats.push(format);
+            }
+            ext_image_copy_capture_session_v1::Event::Done => {
+                state.shm_formats = std::mem::take(&mut state.pending_formats);
+                state.constraints_changed = true;
+            }
+            ext_image_copy_capture_session_v1::Event::Stopped => state.stopped = true,
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ExtImageCopyCaptureFrameV1, ()> for CursorState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ExtImageCopyCaptureFrameV1,
+        event: <ExtImageCopyCaptureFrameV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_image_copy_capture_frame_v1::Event::Ready => state.frame = FrameStatus::Ready,
+            ext_image_copy_capture_frame_v1::Event::Failed { .. } => {
+                state.frame = FrameStatus::Failed;
+            }
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(CursorState: ignore WlSeat);
+delegate_noop!(CursorState: ignore WlPointer);
+delegate_noop!(CursorState: ignore WlShm);
+delegate_noop!(CursorState: WlShmPool);
+delegate_noop!(CursorState: ignore WlBuffer);
+delegate_noop!(CursorState: ExtImageCopyCaptureManagerV1);
+delegate_noop!(CursorState: ExtOutputImageCaptureSourceManagerV1);
+delegate_noop!(CursorState: ExtForeignToplevelImageCaptureSourceManagerV1);
+delegate_noop!(CursorState: ExtImageCaptureSourceV1);
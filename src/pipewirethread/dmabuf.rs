@@ -0,0 +1,207 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::PathBuf;
+
+use gbm::{BufferObject, BufferObjectFlags, Modifier};
+use wayland_client::protocol::{wl_buffer::WlBuffer, wl_shm::Format};
+
+/// The implicit modifier, the driver picks the layout on its own
+pub(super) const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+const DRM_FORMAT_ARGB8888: u32 = u32::from_le_bytes(*b"AR24");
+const DRM_FORMAT_XRGB8888: u32 = u32::from_le_bytes(*b"XR24");
+
+/// A dmabuf format the compositor can write into
+#[derive(Debug, Clone)]
+pub(super) struct DmabufFormat {
+    pub(super) fourcc: u32,
+    pub(super) modifiers: Vec<u64>,
+}
+
+/// Allocates the dmabufs on the device the compositor renders with
+pub(super) struct DmabufAllocator {
+    gbm: gbm::Device<File>,
+}
+
+impl std::fmt::Debug for DmabufAllocator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DmabufAllocator").finish_non_exhaustive()
+    }
+}
+
+impl DmabufAllocator {
+    pub(super) fn new(device: u64) -> anyhow::Result<Self> {
+        let path = drm_node(device)?;
+        let file = OpenOptions::new().read(true).write(true).open(&path)?;
+        tracing::info!("Allocate dmabuf buffers on {}", path.display());
+        Ok(Self {
+            gbm: gbm::Device::new(file)?,
+        })
+    }
+
+    /// The driver picks one of the modifiers, see [`BufferObject::modifier`] for which one
+    pub(super) fn allocate(
+        &self,
+        width: u32,
+        height: u32,
+        fourcc: u32,
+        modifiers: &[u64],
+    ) -> anyhow::Result<BufferObject<()>> {
+        let format = gbm::Format::try_from(fourcc)?;
+        let bo = if modifiers == [DRM_FORMAT_MOD_INVALID] {
+            self.gbm.create_buffer_object::<()>(
+                width,
+                height,
+                format,
+                BufferObjectFlags::RENDERING,
+            )?
+        } else {
+            self.gbm.create_buffer_object_with_modifiers2::<()>(
+                width,
+                height,
+                format,
+                modifiers.iter().map(|modifier| Modifier::from(*modifier)),
+                BufferObjectFlags::RENDERING,
+            )?
+        };
+        Ok(bo)
+    }
+}
+
+/// A dmabuf with the wl_buffer the compositor copies into
+pub(super) struct DmabufBuffer {
+    pub(super) bo: BufferObject<()>,
+    pub(super) buffer: WlBuffer,
+}
+
+impl std::fmt::Debug for DmabufBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DmabufBuffer")
+            .field("buffer", &self.buffer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DmabufBuffer {
+    /// Write a frame we drew ourselves, rows of 4 byte pixels without padding. The mapping is
+    /// linear whatever the modifier, the driver converts it back when it is unmapped
+    pub(super) fn write_frame(&mut self, frame: &[u8], width: u32, height: u32) -> io::Result<()> {
+        self.bo.map_mut(0, 0, width, height, |mapped| {
+            let stride = mapped.stride() as usize;
+            copy_rows(
+                frame,
+                width as usize * 4,
+                mapped.buffer_mut(),
+                stride,
+                height as usize,
+            )
+        })?
+    }
+
+    pub(super) fn read_frame(&self, width: u32, height: u32) -> io::Result<Vec<u8>> {
+        self.bo.map(0, 0, width, height, |mapped| {
+            let row = width as usize * 4;
+            let mut frame = vec![0; row * height as usize];
+            copy_rows(
+                mapped.buffer(),
+                mapped.stride() as usize,
+                &mut frame,
+                row,
+                height as usize,
+            )?;
+            Ok(frame)
+        })?
+    }
+}
+
+impl Drop for DmabufBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+    }
+}
+
+/// Copy `rows` rows between buffers whose rows start `source_stride` and `target_stride` bytes
+/// apart, the padding at the end of the longer rows is left alone
+fn copy_rows(
+    source: &[u8],
+    source_stride: usize,
+    target: &mut [u8],
+    target_stride: usize,
+    rows: usize,
+) -> io::Result<()> {
+    let row = source_stride.min(target_stride);
+    let fits = |len: usize, stride: usize| rows == 0 || len >= (rows - 1) * stride + row;
+    if !fits(source.len(), source_stride) || !fits(target.len(), target_stride) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The frame does not fit the dmabuf",
+        ));
+    }
+    for (source, target) in source
+        .chunks(source_stride)
+        .zip(target.chunks_mut(target_stride))
+        .take(rows)
+    {
+        target[..row].copy_from_slice(&source[..row]);
+    }
+    Ok(())
+}
+
+// The node type of the device is not specified, we prefer the render node of the same gpu
+fn drm_node(device: u64) -> anyhow::Result<PathBuf> {
+    let (major, minor) = (rustix::fs::major(device), rustix::fs::minor(device));
+    let drm_dir = format!("/sys/dev/char/{major}:{minor}/device/drm");
+    if let Ok(entries) = fs::read_dir(drm_dir)
+        && let Some(render_node) = entries
+            .flatten()
+            .map(|entry| entry.file_name())
+            .find(|name| name.to_string_lossy().starts_with("renderD"))
+    {
+        return Ok(PathBuf::from("/dev/dri").join(render_node));
+    }
+    fs::read_dir("/dev/dri")?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| rustix::fs::stat(path).is_ok_and(|stat| stat.st_rdev == device))
+        .ok_or_else(|| anyhow::anyhow!("No drm node for the dmabuf device {major}:{minor}"))
+}
+
+// wl_shm uses FourCC codes, except for the two formats every compositor supports
+pub(super) fn fourcc_to_wl_shm(fourcc: u32) -> Option<Format> {
+    match fourcc {
+        DRM_FORMAT_ARGB8888 => Some(Format::Argb8888),
+        DRM_FORMAT_XRGB8888 => Some(Format::Xrgb8888),
+        fourcc => Format::try_from(fourcc).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_rows_pads_to_the_stride_of_the_dmabuf() {
+        let frame: Vec<u8> = (1..=16).collect();
+        let mut mapped = vec![0; 2 * 12];
+        copy_rows(&frame, 8, &mut mapped, 12, 2).unwrap();
+        assert_eq!(
+            mapped,
+            [
+                1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 0, //
+                9, 10, 11, 12, 13, 14, 15, 16, 0, 0, 0, 0,
+            ]
+        );
+
+        let mut frame_back = vec![0; 16];
+        copy_rows(&mapped, 12, &mut frame_back, 8, 2).unwrap();
+        assert_eq!(frame_back, frame);
+    }
+
+    #[test]
+    fn copy_rows_rejects_a_short_buffer() {
+        let frame = vec![1; 16];
+        let mut mapped = vec![0; 12 + 7];
+        assert!(copy_rows(&frame, 8, &mut mapped, 12, 2).is_err());
+        assert_eq!(mapped, vec![0; 12 + 7]);
+    }
+}
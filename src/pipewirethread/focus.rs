@@ -0,0 +1,186 @@
+use libwayshot::WayshotConnection;
+use wayland_client::protocol::wl_output::WlOutput;
+use wayland_client::{Connection, Dispatch, EventQueue, Proxy, QueueHandle, event_created_child};
+use wayland_protocols_wlr::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+};
+
+use super::CastSource;
+use super::capture::dispatch_nonblocking;
+
+#[derive(Debug)]
+struct Toplevel {
+    handle: ZwlrForeignToplevelHandleV1,
+    app_id: String,
+    title: String,
+    activated: bool,
+    outputs: Vec<WlOutput>,
+}
+
+#[derive(Debug, Default)]
+struct FocusState {
+    toplevels: Vec<Toplevel>,
+    focused: Option<ZwlrForeignToplevelHandleV1>,
+    changed: bool,
+}
+
+/// Tracks the window with the keyboard focus. Only wlr-foreign-toplevel-management tells
+/// which window is activated, the ext list does not
+#[derive(Debug)]
+pub(crate) struct FocusTracker {
+    event_queue: EventQueue<FocusState>,
+    state: FocusState,
+    manager: ZwlrForeignToplevelManagerV1,
+}
+
+impl FocusTracker {
+    pub(crate) fn new(connection: &WayshotConnection) -> anyhow::Result<Self> {
+        let mut event_queue = connection.conn.new_event_queue();
+        let qh = event_queue.handle();
+        let manager: ZwlrForeignToplevelManagerV1 = connection
+            .globals
+            .bind(&qh, 1..=3, ())
+            .map_err(|e| anyhow::anyhow!("The compositor does not tell the focused window: {e}"))?;
+        let mut state = FocusState::default();
+        // The first roundtrip brings the windows, the second one their state
+        event_queue.roundtrip(&mut state)?;
+        event_queue.roundtrip(&mut state)?;
+        state.changed = false;
+        Ok(Self {
+            event_queue,
+            state,
+            manager,
+        })
+    }
+
+    pub(super) fn focused(&self) -> Option<CastSource> {
+        let focused = self.state.focused.as_ref()?;
+        self.state
+            .toplevels
+            .iter()
+            .find(|toplevel| &toplevel.handle == focused)
+            .map(|toplevel| CastSource::Toplevel {
+                app_id: toplevel.app_id.clone(),
+                title: toplevel.title.clone(),
+            })
+    }
+
+    /// The output a window of the app is on, the focused one if the app has several. Without
+    /// an app id it is the output of the focused window
+    pub(crate) fn output_of(&self, app_id: Option<&str>) -> Option<WlOutput> {
+        let focused = self.state.focused.as_ref();
+        let mut windows: Vec<&Toplevel> = self
+            .state
+            .toplevels
+            .iter()
+            .filter(|toplevel| match app_id {
+                Some(app_id) => toplevel.app_id == app_id,
+                None => Some(&toplevel.handle) == focused,
+            })
+            .collect();
+        windows.sort_by_key(|toplevel| Some(&toplevel.handle) != focused);
+        windows
+            .into_iter()
+            .find_map(|toplevel| toplevel.outputs.first().cloned())
+    }
+
+    /// The window which got the focus since the last call. When the focus goes to
+    /// something else than a window, the last window stays
+    pub(super) fn poll(&mut self) -> Option<CastSource> {
+        if let Err(e) = dispatch_nonblocking(&mut self.event_queue, &mut self.state) {
+            tracing::warn!("Cannot follow the focus: {e}");
+            return None;
+        }
+        if !std::mem::take(&mut self.state.changed) {
+            return None;
+        }
+        self.focused()
+    }
+}
+
+impl Drop for FocusTracker {
+    fn drop(&mut self) {
+        for toplevel in &self.state.toplevels {
+            toplevel.handle.destroy();
+        }
+        self.manager.stop();
+        let _ = self.event_queue.flush();
+    }
+}
+
+impl Dispatch<ZwlrForeignToplevelManagerV1, ()> for FocusState {
+    fn event(
+        state: &mut Self,
+        _proxy: &ZwlrForeignToplevelManagerV1,
+        event: <ZwlrForeignToplevelManagerV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        if let zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } = event {
+            state.toplevels.push(Toplevel {
+                handle: toplevel,
+                app_id: String::new(),
+                title: String::new(),
+                activated: false,
+                outputs: Vec::new(),
+            });
+        }
+    }
+
+    event_created_child!(FocusState, ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (ZwlrForeignToplevelHandleV1, ())
+    ]);
+}
+
+impl Dispatch<ZwlrForeignToplevelHandleV1, ()> for FocusState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZwlrForeignToplevelHandleV1,
+        event: <ZwlrForeignToplevelHandleV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        let Some(index) = state
+            .toplevels
+            .iter()
+            .position(|toplevel| &toplevel.handle == proxy)
+        else {
+            return;
+        };
+        let toplevel = &mut state.toplevels[index];
+        match event {
+            zwlr_foreign_toplevel_handle_v1::Event::AppId { app_id } => toplevel.app_id = app_id,
+            zwlr_foreign_toplevel_handle_v1::Event::Title { title } => toplevel.title = title,
+            zwlr_foreign_toplevel_handle_v1::Event::OutputEnter { output } => {
+                toplevel.outputs.push(output);
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::OutputLeave { output } => {
+                toplevel.outputs.retain(|entered| entered != &output);
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::State { state: states } => {
+                toplevel.activated = states
+                    .chunks_exact(4)
+                    .map(|chunk| u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                    .any(|value| value == zwlr_foreign_toplevel_handle_v1::State::Activated as u32);
+            }
+            // The state is complete, a title change alone does not move the stream
+            zwlr_foreign_toplevel_handle_v1::Event::Done => {
+                if toplevel.activated && state.focused.as_ref() != Some(proxy) {
+                    state.focused = Some(proxy.clone());
+                    state.changed = true;
+                }
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+                state.toplevels.swap_remove(index);
+                if state.focused.as_ref() == Some(proxy) {
+                    state.focused = None;
+                }
+                proxy.destroy();
+            }
+            _ => {}
+        }
+    }
+}
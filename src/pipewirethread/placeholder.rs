@@ -0,0 +1,81 @@
+use pipewire::spa::param::video::VideoFormat;
+
+const CARD_BACKGROUND: u8 = 0x24;
+const CARD_FOREGROUND: u8 = 0x80;
+
+/// What a window cast shows while the window cannot be captured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum HiddenWindow {
+    LastFrame,
+    Card,
+}
+
+impl HiddenWindow {
+    pub(super) fn from_setting(setting: &str) -> Self {
+        match setting {
+            "card" => Self::Card,
+            _ => Self::LastFrame,
+        }
+    }
+}
+
+/// An opaque gray pixel of the format. For gray only the place of the alpha channel differs
+/// between the formats, the 10 bit formats have none
+pub(super) fn gray_pixel(format: Option<VideoFormat>) -> Option<fn(u8) -> [u8; 4]> {
+    match format {
+        Some(VideoFormat::BGRA | VideoFormat::BGRx | VideoFormat::RGBA | VideoFormat::RGBx) => {
+            Some(|value: u8| [value, value, value, u8::MAX])
+        }
+        Some(VideoFormat::ARGB | VideoFormat::xRGB | VideoFormat::ABGR | VideoFormat::xBGR) => {
+            Some(|value: u8| [u8::MAX, value, value, value])
+        }
+        _ => None,
+    }
+}
+
+/// A dark card with a crossed out window in the middle, packed 4 bytes per pixel
+pub(super) fn hidden_window_card(width: u32, height: u32, format: Option<VideoFormat>) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let mut frame = vec![0; width * height * 4];
+    // The 10 bit formats stay black
+    let Some(pixel) = gray_pixel(format) else {
+        return frame;
+    };
+
+    let background = pixel(CARD_BACKGROUND);
+    let foreground = pixel(CARD_FOREGROUND);
+    for chunk in frame.chunks_exact_mut(4) {
+        chunk.copy_from_slice(&background);
+    }
+
+    let thickness = (width.min(height) / 100).max(2);
+    let (window_width, window_height) = (width / 4, height / 4);
+    let (left, top) = ((width - window_width) / 2, (height - window_height) / 2);
+    let (right, bottom) = (left + window_width, top + window_height);
+    let title_bar = (window_height / 6).max(thickness);
+    let mut fill = |x: usize, y: usize| {
+        if x < width && y < height {
+            let offset = (y * width + x) * 4;
+            frame[offset..offset + 4].copy_from_slice(&foreground);
+        }
+    };
+    for y in top..bottom {
+        for x in left..right {
+            let border = x < left + thickness
+                || x >= right.saturating_sub(thickness)
+                || y < top + title_bar
+                || y >= bottom.saturating_sub(thickness);
+            if border {
+                fill(x, y);
+            }
+        }
+    }
+    // The slash goes from the top left to the bottom right corner
+    for x in left..right {
+        let y = top + (x - left) * window_height / window_width.max(1);
+        for offset in 0..thickness {
+            fill(x, y + offset);
+        }
+    }
+    frame
+}
@@ -0,0 +1,224 @@
+use std::ops::Range;
+
+use libwayshot::region::{Position, Region};
+use wayland_client::protocol::wl_shm::Format;
+
+/// Brings the frames to the size the client asked for, on the cpu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Scale {
+    pub(super) source: libwayshot::Size,
+    pub(super) target: libwayshot::Size,
+}
+
+impl Scale {
+    /// Every pixel is the average of the source pixels it covers, or the nearest one when
+    /// `average` is off
+    pub(super) fn apply(&self, frame: &[u8], average: bool) -> Vec<u8> {
+        let (source_width, source_height) =
+            (self.source.width as usize, self.source.height as usize);
+        let (target_width, target_height) =
+            (self.target.width as usize, self.target.height as usize);
+        let span = |index: usize, source: usize, target: usize| -> Range<usize> {
+            let start = index * source / target;
+            let end = ((index + 1) * source / target).clamp(start + 1, source);
+            if average {
+                start..end
+            } else {
+                start..start + 1
+            }
+        };
+        let columns: Vec<Range<usize>> = (0..target_width)
+            .map(|x| span(x, source_width, target_width))
+            .collect();
+        let mut scaled = vec![0; target_width * target_height * 4];
+        for y in 0..target_height {
+            let rows = span(y, source_height, target_height);
+            for (x, columns) in columns.iter().enumerate() {
+                let mut sum = [0u32; 4];
+                for row in rows.clone() {
+                    for column in columns.clone() {
+                        let pixel = (row * source_width + column) * 4;
+                        for (channel, value) in sum.iter_mut().zip(&frame[pixel..pixel + 4]) {
+                            *channel += *value as u32;
+                        }
+                    }
+                }
+                let count = (rows.len() * columns.len()) as u32;
+                let target = (y * target_width + x) * 4;
+                for (channel, value) in scaled[target..target + 4].iter_mut().zip(sum) {
+                    *channel = (value / count) as u8;
+                }
+            }
+        }
+        scaled
+    }
+
+    pub(super) fn point(&self, x: i32, y: i32) -> (i32, i32) {
+        (
+            (x as i64 * self.target.width as i64 / self.source.width.max(1) as i64) as i32,
+            (y as i64 * self.target.height as i64 / self.source.height.max(1) as i64) as i32,
+        )
+    }
+
+    /// The damage grows to whole pixels of the scaled frame
+    pub(super) fn damage(&self, damage: &[Region]) -> Vec<Region> {
+        let scale = |value: i32, source: u32, target: u32, round_up: bool| {
+            let value = value.max(0) as u64 * target as u64;
+            let source = source.max(1) as u64;
+            let value = if round_up {
+                value.div_ceil(source)
+            } else {
+                value / source
+            };
+            value.min(target as u64) as i32
+        };
+        damage
+            .iter()
+            .filter_map(|region| {
+                let left = scale(
+                    region.position.x,
+                    self.source.width,
+                    self.target.width,
+                    false,
+                );
+                let top = scale(
+                    region.position.y,
+                    self.source.height,
+                    self.target.height,
+                    false,
+                );
+                let right = scale(
+                    region.position.x + region.size.width as i32,
+                    self.source.width,
+                    self.target.width,
+                    true,
+                );
+                let bottom = scale(
+                    region.position.y + region.size.height as i32,
+                    self.source.height,
+                    self.target.height,
+                    true,
+                );
+                (right > left && bottom > top).then(|| Region {
+                    position: Position { x: left, y: top },
+                    size: libwayshot::Size {
+                        width: (right - left) as u32,
+                        height: (bottom - top) as u32,
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+/// Averaging bytes only works for formats with 8 bit channels
+pub(super) fn averages(format: Format) -> bool {
+    matches!(
+        format,
+        Format::Argb8888
+            | Format::Xrgb8888
+            | Format::Rgba8888
+            | Format::Rgbx8888
+            | Format::Abgr8888
+            | Format::Xbgr8888
+            | Format::Bgra8888
+            | Format::Bgrx8888
+    )
+}
+
+/// The largest size within `max` with the aspect ratio of `size`, sizes are only shrunk
+pub(super) fn fit_size(size: libwayshot::Size, max: Option<libwayshot::Size>) -> libwayshot::Size {
+    let Some(max) = max else {
+        return size;
+    };
+    if size.width <= max.width && size.height <= max.height {
+        return size;
+    }
+    let ratio = (max.width as f64 / size.width as f64).min(max.height as f64 / size.height as f64);
+    libwayshot::Size {
+        width: ((size.width as f64 * ratio).round() as u32).max(1),
+        height: ((size.height as f64 * ratio).round() as u32).max(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(width: u32, height: u32) -> libwayshot::Size {
+        libwayshot::Size { width, height }
+    }
+
+    fn region(x: i32, y: i32, width: u32, height: u32) -> Region {
+        Region {
+            position: Position { x, y },
+            size: size(width, height),
+        }
+    }
+
+    // A 2x2 frame of gray pixels with the values 0, 40, 80 and 120
+    const FRAME: [u8; 16] = [
+        0, 0, 0, 0, 40, 40, 40, 40, //
+        80, 80, 80, 80, 120, 120, 120, 120,
+    ];
+
+    #[test]
+    fn apply_averages_the_covered_pixels() {
+        let scale = Scale {
+            source: size(2, 2),
+            target: size(1, 1),
+        };
+        assert_eq!(scale.apply(&FRAME, true), [60; 4]);
+        assert_eq!(scale.apply(&FRAME, false), [0; 4]);
+    }
+
+    #[test]
+    fn apply_repeats_pixels_when_scaling_up() {
+        let scale = Scale {
+            source: size(2, 2),
+            target: size(4, 2),
+        };
+        let scaled = scale.apply(&FRAME, true);
+        assert_eq!(scaled.len(), 4 * 2 * 4);
+        assert_eq!(
+            scaled[..16],
+            [0, 0, 0, 0, 0, 0, 0, 0, 40, 40, 40, 40, 40, 40, 40, 40]
+        );
+    }
+
+    #[test]
+    fn damage_grows_to_whole_pixels() {
+        let scale = Scale {
+            source: size(100, 100),
+            target: size(30, 30),
+        };
+        assert_eq!(scale.damage(&[region(10, 10, 5, 5)]), [region(3, 3, 2, 2)]);
+    }
+
+    #[test]
+    fn damage_outside_the_frame_is_dropped() {
+        let scale = Scale {
+            source: size(100, 100),
+            target: size(50, 50),
+        };
+        assert_eq!(
+            scale.damage(&[region(90, 90, 20, 20)]),
+            [region(45, 45, 5, 5)]
+        );
+        assert!(scale.damage(&[region(-20, 0, 10, 10)]).is_empty());
+    }
+
+    #[test]
+    fn fit_size_keeps_the_aspect_ratio() {
+        assert_eq!(fit_size(size(3840, 2160), None), size(3840, 2160));
+        assert_eq!(
+            fit_size(size(3840, 2160), Some(size(1920, 1920))),
+            size(1920, 1080)
+        );
+        assert_eq!(
+            fit_size(size(1280, 720), Some(size(1920, 1080))),
+            size(1280, 720)
+        );
+        assert_eq!(fit_size(size(10000, 1), Some(size(100, 100))), size(100, 1));
+    }
+}
@@ -0,0 +1,198 @@
+use libwayshot::WayshotConnection;
+use wayland_client::{Connection, Dispatch, Proxy, QueueHandle, event_created_child};
+use wayland_protocols::ext::foreign_toplevel_list::v1::client::{
+    ext_foreign_toplevel_handle_v1::{self, ExtForeignToplevelHandleV1},
+    ext_foreign_toplevel_list_v1::{self, ExtForeignToplevelListV1},
+};
+
+#[derive(Debug)]
+struct Toplevel {
+    handle: ExtForeignToplevelHandleV1,
+    app_id: String,
+    title: String,
+    identifier: String,
+}
+
+#[derive(Debug, Default)]
+struct ToplevelList {
+    toplevels: Vec<Toplevel>,
+}
+
+/// Look a window up on the connection of the stream. libwayshot only lists the windows
+/// which existed when the connection was made. Returns the window with its identifier, see
+/// [`pick_toplevel`] for which one is taken when several match
+pub(super) fn find_toplevel(
+    connection: &WayshotConnection,
+    app_id: &str,
+    title: &str,
+    shown: &[String],
+) -> anyhow::Result<(ExtForeignToplevelHandleV1, String)> {
+    let mut event_queue = connection.conn.new_event_queue();
+    let qh = event_queue.handle();
+    let list: ExtForeignToplevelListV1 = connection.globals.bind(&qh, 1..=1, ())?;
+    let mut state = ToplevelList::default();
+    // The first roundtrip brings the windows, the second one their details
+    event_queue.roundtrip(&mut state)?;
+    event_queue.roundtrip(&mut state)?;
+    list.stop();
+    list.destroy();
+
+    let windows: Vec<(&str, &str, &str)> = state
+        .toplevels
+        .iter()
+        .map(|toplevel| {
+            (
+                toplevel.app_id.as_str(),
+                toplevel.title.as_str(),
+                toplevel.identifier.as_str(),
+            )
+        })
+        .collect();
+    let found = pick_toplevel(&windows, app_id, title, shown)
+        .map(|index| state.toplevels.swap_remove(index));
+    for toplevel in state.toplevels {
+        toplevel.handle.destroy();
+    }
+    let _ = event_queue.flush();
+    found.map(|toplevel| (toplevel.handle, toplevel.identifier))
+}
+
+/// The index of the window with the app id and title among `(app_id, title, identifier)`.
+/// The title may change between runs, so without a match the app id alone is enough. Windows
+/// are not told apart further, so among several matches the one last in `shown` wins, and
+/// when none of them was shown before nothing is picked rather than a guess
+fn pick_toplevel(
+    windows: &[(&str, &str, &str)],
+    app_id: &str,
+    title: &str,
+    shown: &[String],
+) -> anyhow::Result<usize> {
+    let matching = |with_title: bool| -> Vec<usize> {
+        windows
+            .iter()
+            .enumerate()
+            .filter(|(_, (window_app_id, window_title, _))| {
+                *window_app_id == app_id && (!with_title || *window_title == title)
+            })
+            .map(|(index, _)| index)
+            .collect()
+    };
+    let mut matches = matching(true);
+    if matches.is_empty() {
+        matches = matching(false);
+    }
+    match matches[..] {
+        [] => Err(anyhow::anyhow!("No window of {app_id}")),
+        [index] => Ok(index),
+        _ => shown
+            .iter()
+            .rev()
+            .find_map(|identifier| {
+                matches
+                    .iter()
+                    .copied()
+                    .find(|&index| windows[index].2 == identifier)
+            })
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{} windows of {app_id} are titled {title:?}, cannot tell which one is meant",
+                    matches.len()
+                )
+            }),
+    }
+}
+
+impl Dispatch<ExtForeignToplevelListV1, ()> for ToplevelList {
+    fn event(
+        state: &mut Self,
+        _proxy: &ExtForeignToplevelListV1,
+        event: <ExtForeignToplevelListV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        if let ext_foreign_toplevel_list_v1::Event::Toplevel { toplevel } = event {
+            state.toplevels.push(Toplevel {
+                handle: toplevel,
+                app_id: String::new(),
+                title: String::new(),
+                identifier: String::new(),
+            });
+        }
+    }
+
+    event_created_child!(ToplevelList, ExtForeignToplevelListV1, [
+        ext_foreign_toplevel_list_v1::EVT_TOPLEVEL_OPCODE => (ExtForeignToplevelHandleV1, ())
+    ]);
+}
+
+impl Dispatch<ExtForeignToplevelHandleV1, ()> for ToplevelList {
+    fn event(
+        state: &mut Self,
+        proxy: &ExtForeignToplevelHandleV1,
+        event: <ExtForeignToplevelHandleV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        let Some(toplevel) = state
+            .toplevels
+            .iter_mut()
+            .find(|toplevel| &toplevel.handle == proxy)
+        else {
+            return;
+        };
+        match event {
+            ext_foreign_toplevel_handle_v1::Event::AppId { app_id } => toplevel.app_id = app_id,
+            ext_foreign_toplevel_handle_v1::Event::Title { title } => toplevel.title = title,
+            ext_foreign_toplevel_handle_v1::Event::Identifier { identifier } => {
+                toplevel.identifier = identifier;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TERMINALS: [(&str, &str, &str); 3] = [
+        ("foot", "fish", "a"),
+        ("firefox", "Mozilla Firefox", "b"),
+        ("foot", "fish", "c"),
+    ];
+
+    #[test]
+    fn pick_toplevel_takes_the_only_match() {
+        assert_eq!(
+            pick_toplevel(&TERMINALS, "firefox", "Mozilla Firefox", &[]).unwrap(),
+            1
+        );
+        // The title changed since
+        assert_eq!(
+            pick_toplevel(&TERMINALS, "firefox", "GitHub", &[]).unwrap(),
+            1
+        );
+        assert!(pick_toplevel(&TERMINALS, "mpv", "", &[]).is_err());
+    }
+
+    #[test]
+    fn pick_toplevel_of_two_matches_takes_the_one_shown_last() {
+        let shown = ["b".to_owned(), "c".to_owned(), "a".to_owned()];
+        assert_eq!(
+            pick_toplevel(&TERMINALS, "foot", "fish", &shown).unwrap(),
+            0
+        );
+        assert_eq!(
+            pick_toplevel(&TERMINALS, "foot", "fish", &shown[..2]).unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn pick_toplevel_of_two_matches_never_shown_is_an_error() {
+        assert!(pick_toplevel(&TERMINALS, "foot", "fish", &[]).is_err());
+        assert!(pick_toplevel(&TERMINALS, "foot", "fish", &["b".to_owned()]).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::LazyLock;
+
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+use zbus::{interface, object_server::SignalEmitter, zvariant::OwnedObjectPath};
+
+use crate::session::{SESSIONS, close_session};
+
+static PRIVACY_MODE: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+
+/// While privacy mode is on, every screenshot, screencast, remote desktop and input capture
+/// request is cancelled
+pub fn privacy_mode() -> bool {
+    *PRIVACY_MODE.borrow()
+}
+
+pub fn set_privacy_mode(enabled: bool) {
+    PRIVACY_MODE.send_if_modified(|current| {
+        let changed = *current != enabled;
+        *current = enabled;
+        changed
+    });
+}
+
+/// Lets the user turn privacy mode on and off, from a status bar or a key binding
+pub struct Privacy;
+
+#[interface(name = "org.waycrate.Luminous.Privacy")]
+impl Privacy {
+    #[zbus(property, name = "version")]
+    fn version(&self) -> u32 {
+        1
+    }
+
+    #[zbus(property)]
+    fn enabled(&self) -> bool {
+        privacy_mode()
+    }
+
+    /// A method rather than a property setter, zbus would emit the property change on top of
+    /// the one [`watch_privacy_mode`] emits for every change
+    fn set_enabled(&self, enabled: bool) {
+        set_privacy_mode(enabled);
+    }
+
+    /// Flip privacy mode, returns the new state
+    fn toggle(&self) -> bool {
+        set_privacy_mode(!privacy_mode());
+        privacy_mode()
+    }
+
+    #[zbus(signal)]
+    async fn changed(emitter: &SignalEmitter<'_>, enabled: bool) -> zbus::Result<()>;
+}
+
+/// Stop every session once privacy mode is turned on, and tell listeners about each change
+pub async fn watch_privacy_mode(connection: zbus::Connection) {
+    let mut receiver = PRIVACY_MODE.subscribe();
+    let emitter = SignalEmitter::new(&connection, "/org/freedesktop/portal/desktop").unwrap();
+    while receiver.changed().await.is_ok() {
+        let enabled = *receiver.borrow_and_update();
+        tracing::info!("Privacy mode is {}", if enabled { "on" } else { "off" });
+        if enabled {
+            let handles: Vec<OwnedObjectPath> = SESSIONS
+                .lock()
+                .await
+                .iter()
+                .map(|session| session.handle_path.clone())
+                .collect();
+            for handle in handles {
+                close_session(&connection, handle, "Privacy mode").await;
+            }
+        }
+        if let Err(e) = Privacy::changed(&emitter, enabled).await {
+            tracing::warn!("Cannot emit the privacy mode change: {e}");
+        }
+        if let Err(e) = Privacy.enabled_changed(&emitter).await {
+            tracing::warn!("Cannot emit the privacy mode property change: {e}");
+        }
+    }
+}
+
+/// SIGUSR1 flips privacy mode, for compositors which bind keys to commands
+pub async fn toggle_on_signal() {
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            tracing::warn!("Cannot listen for SIGUSR1: {e}");
+            return;
+        }
+    };
+    while signals.recv().await.is_some() {
+        set_privacy_mode(!privacy_mode());
+    }
+}
@@ -27,15 +27,18 @@ use zbus::zvariant::{
 use zbus::{interface, object_server::ResponseDispatchNotifier};
 
 use crate::PortalResponse;
-use crate::dialog::{CopySelect, Message, PermissionMode, PermissionResult};
+use crate::dialog::{CopySelect, DialogParent, Message, PermissionMode, PermissionResult};
 use crate::input_capture::BarrierInfo;
 use crate::pipewirethread::CastTarget;
 use crate::pipewirethread::ScreencastThread;
+use crate::privacy::privacy_mode;
 use crate::request::RequestInterface;
 use crate::session::{
     DeviceType, PersistMode, SESSIONS, Session, SessionType, SourceType, append_session,
+    close_on_target_lost, mark_session_started,
 };
 use crate::settings::WHITE_LIST_MAINTAINER;
+use crate::sharing::{SharingKind, SharingSession, add_sharing_session, remove_sharing_session};
 use crate::utils::get_selection_from_socket;
 
 pub use self::eis_server::{EisServerMsg, InputEvent};
@@ -94,7 +97,7 @@ pub struct Stream(u32, StreamProperties);
 #[derive(Clone, Default, Type, Debug, Serialize, Deserialize)]
 /// The stream properties.
 #[zvariant(signature = "dict")]
-struct StreamProperties {
+pub struct StreamProperties {
     #[serde(with = "optional", skip_serializing_if = "Option::is_none", default)]
     id: Option<String>,
     #[serde(with = "optional", skip_serializing_if = "Option::is_none", default)]
@@ -141,6 +144,10 @@ fn remote_start_other() -> ResponseDispatchNotifier<PortalResponse<RemoteStartRe
     ResponseDispatchNotifier::new(PortalResponse::Other).0
 }
 
+fn remote_start_cancelled() -> ResponseDispatchNotifier<PortalResponse<RemoteStartReturnValue>> {
+    ResponseDispatchNotifier::new(PortalResponse::Cancelled).0
+}
+
 #[derive(Type, Debug, Default, Deserialize, Serialize, Clone)]
 #[zvariant(signature = "(suv)")]
 pub struct RestoreData {
@@ -150,7 +157,7 @@ pub struct RestoreData {
     data: LuminousData,
 }
 
-const VENDOR_NAME: &str = "luminous";
+pub const VENDOR_NAME: &str = "luminous";
 const RESTORE_DATA_VERSION: u32 = 1;
 
 impl RestoreData {
@@ -190,7 +197,9 @@ pub struct CursorPosition {
 
 pub struct RemoteSessionData {
     pub session_handle: String,
+    app_id: String,
     pub cast_thread: Option<ScreencastThread>,
+    stream_properties: Option<StreamProperties>,
     pub remote_control: RemoteControl,
     pub zones: Vec<Zone>,
     pub zone_id: ZoneId,
@@ -203,14 +212,18 @@ pub struct RemoteSessionData {
 impl RemoteSessionData {
     pub fn new(
         session_handle: String,
+        app_id: String,
         cast_thread: Option<ScreencastThread>,
+        stream_properties: Option<StreamProperties>,
         remote_control: RemoteControl,
         zones: Vec<Zone>,
         restore_data: impl Into<Option<RestoreData>>,
     ) -> Self {
         Self {
             session_handle,
+            app_id,
             cast_thread,
+            stream_properties,
             remote_control,
             zones,
             zone_id: ZoneId::unique(),
@@ -267,7 +280,10 @@ impl RemoteSessionData {
         let Some(cast_thread) = &self.cast_thread else {
             return vec![];
         };
-        vec![Stream(cast_thread.node_id(), StreamProperties::default())]
+        vec![Stream(
+            cast_thread.node_id(),
+            self.stream_properties.clone().unwrap_or_default(),
+        )]
     }
 }
 
@@ -275,6 +291,15 @@ pub static REMOTE_SESSIONS: LazyLock<Arc<Mutex<Vec<RemoteSessionData>>>> =
     LazyLock::new(|| Arc::new(Mutex::new(Vec::new())));
 
 pub async fn append_remote_session(session: RemoteSessionData) {
+    add_sharing_session(SharingSession {
+        handle: session.session_handle.clone(),
+        app_id: session.app_id.clone(),
+        kind: if session.cast_thread.is_some() {
+            SharingKind::RemoteDesktop
+        } else {
+            SharingKind::Input
+        },
+    });
     let mut sessions = REMOTE_SESSIONS.lock().await;
     sessions.push(session)
 }
@@ -288,6 +313,7 @@ pub async fn remove_remote_session(path: &str) {
         return;
     };
     sessions[index].stop();
+    remove_sharing_session(path);
     tracing::info!("session {} is stopped", sessions[index].session_handle);
     sessions.remove(index);
 }
@@ -454,6 +480,7 @@ impl RemoteDesktopBackend {
                     message: format!("Allow '{}' to make remote?", app_id),
                     mode: PermissionMode::Remote,
                     id_valid: !app_id.is_empty(),
+                    parent: DialogParent::default(),
                 })
                 .await
                 .map_err(|e| zbus::Error::Failure(e.to_string()))?;
@@ -479,7 +506,7 @@ impl RemoteDesktopBackend {
                 },
             )
             .await?;
-        let current_session = Session::new(session_handle.clone(), SessionType::Remote);
+        let current_session = Session::new(session_handle.clone(), SessionType::Remote, app_id);
         append_session(current_session.clone()).await;
         server.at(session_handle.clone(), current_session).await?;
         Ok(PortalResponse::Success(SessionCreateResult {
@@ -513,11 +540,14 @@ impl RemoteDesktopBackend {
         &self,
         _request_handle: ObjectPath<'_>,
         session_handle: ObjectPath<'_>,
-        _app_id: String,
+        app_id: String,
         _parent_window: String,
         _options: HashMap<String, Value<'_>>,
         #[zbus(connection)] dbus_connection: &zbus::Connection,
     ) -> zbus::fdo::Result<ResponseDispatchNotifier<PortalResponse<RemoteStartReturnValue>>> {
+        if privacy_mode() {
+            return Ok(remote_start_cancelled());
+        }
         let locked_sessions = SESSIONS.lock().await;
         let Some(index) = locked_sessions
             .iter()
@@ -600,30 +630,58 @@ impl RemoteDesktopBackend {
         } else {
             get_monitor_info_from_socket(&connection)?
         };
+        // Privacy mode may have been turned on while the output was being chosen
+        if privacy_mode() {
+            return Ok(remote_start_cancelled());
+        }
         if screen_share_enabled {
-            let show_cursor = current_session.cursor_mode.show_cursor();
+            let cursor_mode = current_session.cursor_mode;
 
             let output = wl_output;
+            // The stream shows the output, the zone is the whole input space
+            let (position, size) = connection
+                .get_all_outputs()
+                .iter()
+                .find(|output_info| output_info.wl_output == output)
+                .map(|output_info| {
+                    let region = output_info.logical_region.inner;
+                    (
+                        Some((region.position.x, region.position.y)),
+                        (region.size.width as i32, region.size.height as i32),
+                    )
+                })
+                .unwrap_or((Some((x, y)), (width, height)));
 
-            let cast_thread_target =
-                ScreencastThread::start_cast(show_cursor, CastTarget::Screen(output), connection)
-                    .await
-                    .map_err(|e| {
-                        zbus::Error::Failure(format!("cannot start pipewire stream, error: {e}"))
-                    })?;
+            let mut cast_thread_target = ScreencastThread::start_cast(
+                cursor_mode,
+                CastTarget::Screen(output),
+                connection,
+                false,
+            )
+            .await
+            .map_err(|e| {
+                zbus::Error::Failure(format!("cannot start pipewire stream, error: {e}"))
+            })?;
 
             let node_id = cast_thread_target.node_id();
             streams.push(Stream(
                 node_id,
                 StreamProperties {
-                    size: (width, height),
+                    id: Some("0".to_owned()),
+                    position,
+                    size,
                     source_type: SourceType::Monitor,
-                    ..Default::default()
                 },
             ));
+            close_on_target_lost(
+                dbus_connection.clone(),
+                current_session.handle_path.clone(),
+                cast_thread_target.take_target_lost().into_iter().collect(),
+            );
             cast_thread = Some(cast_thread_target);
         }
         let remote_control = RemoteControl::init(x as u32, y as u32, width as u32, height as u32);
+        let targets = vec![output_name.clone()];
         let restore_data = current_session.persist_mode.is_persist().then(|| {
             RestoreData::new(LuminousData {
                 display: output_name,
@@ -631,7 +689,9 @@ impl RemoteDesktopBackend {
         });
         append_remote_session(RemoteSessionData::new(
             session_handle.to_string(),
+            app_id,
             cast_thread,
+            streams.first().map(|stream| stream.1.clone()),
             remote_control,
             vec![Zone {
                 x_offset: x,
@@ -642,6 +702,7 @@ impl RemoteDesktopBackend {
             restore_data.clone(),
         ))
         .await;
+        mark_session_started(&current_session.handle_path, targets).await;
         let clipboard_enabled = clipboard_requested
             && crate::clipboard::ensure_clipboard_session(&session_handle, dbus_connection.clone())
                 .await;
@@ -1,25 +1,28 @@
 use std::collections::HashMap;
 use std::thread::ScopedJoinHandle;
 
-use libwayshot::{OutputInfo, TopLevel};
+use libwayshot::reexport::WlOutput;
+use libwayshot::region::{Position, Region};
+use libwayshot::{OutputInfo, TopLevel, WayshotConnection};
 use stream_message::SERVER_SOCK;
-use zbus::interface;
+use zbus::{fdo, interface};
 
 use zbus::zvariant::{
-    ObjectPath, OwnedValue, Type, Value,
+    LE, ObjectPath, OwnedObjectPath, OwnedValue, Type, Value,
     as_value::{self, optional},
+    serialized::Context,
 };
 
 use enumflags2::BitFlags;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 use futures::{
     SinkExt, StreamExt,
     channel::mpsc::{Receiver, Sender},
 };
 
-use crate::pipewirethread::CastTarget;
+use crate::pipewirethread::{CastSource, CastTarget, active_window, logical_extent};
 use crate::utils::get_selection_from_socket;
 use std::sync::Arc;
 use std::sync::LazyLock;
@@ -27,14 +30,20 @@ use tokio::sync::Mutex;
 
 use crate::PortalResponse;
 use crate::pipewirethread::ScreencastThread;
+use crate::remotedesktop::VENDOR_NAME;
 use crate::request::RequestInterface;
 use crate::session::{
     CursorMode, PersistMode, SESSIONS, Session, SessionType, SourceType, append_session,
+    close_on_target_lost, mark_session_started, update_session_target,
 };
+use crate::sharing::{SharingKind, SharingSession, add_sharing_session, remove_sharing_session};
+use crate::virtual_output::{VirtualOutput, VirtualOutputs};
 
 use crate::dialog::{CopySelect, Message, TopLevelInfo, WlOutputInfo};
+use crate::exclusion::Exclusion;
+use crate::privacy::privacy_mode;
 
-//use libwaysip::{SelectionType, WaySip};
+use libwaysip::{SelectionType, WaySip};
 
 #[derive(Type, Debug, Default, Serialize, Deserialize)]
 /// Specified options for a [`Screencast::create_session`] request.
@@ -62,6 +71,336 @@ pub struct SelectSourcesOptions {
     pub restore_token: Option<String>,
     #[serde(with = "optional", skip_serializing_if = "Option::is_none", default)]
     pub persist_mode: Option<PersistMode>,
+    #[serde(with = "optional", skip_serializing_if = "Option::is_none", default)]
+    pub restore_data: Option<CastRestoreData>,
+}
+
+#[derive(Type, Debug, Default, Serialize, Clone)]
+#[zvariant(signature = "(suv)")]
+pub struct CastRestoreData {
+    vendor_name: String,
+    version: u32,
+    #[serde(with = "as_value")]
+    data: LuminousCastData,
+}
+
+// Version 2 remembers the area of a monitor source
+const CAST_RESTORE_DATA_VERSION: u32 = 2;
+
+/// The data is only parsed for the versions we know the layout of, version 1 is migrated. The
+/// data of any other vendor or version is kept empty and rejected when restoring
+impl<'de> Deserialize<'de> for CastRestoreData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (vendor_name, version, data) = <(String, u32, OwnedValue)>::deserialize(deserializer)?;
+        let data = match version {
+            1 if vendor_name == VENDOR_NAME => {
+                parse_cast_data::<LuminousCastDataV1>(&data).map(LuminousCastData::from)
+            }
+            CAST_RESTORE_DATA_VERSION if vendor_name == VENDOR_NAME => parse_cast_data(&data),
+            _ => {
+                return Ok(Self {
+                    vendor_name,
+                    version,
+                    data: LuminousCastData::default(),
+                });
+            }
+        };
+        data.map(Self::new).map_err(serde::de::Error::custom)
+    }
+}
+
+fn parse_cast_data<T>(data: &Value) -> zbus::zvariant::Result<T>
+where
+    T: for<'de> Deserialize<'de> + Type,
+{
+    let encoded = zbus::zvariant::to_bytes(Context::new_dbus(LE, 0), data)?;
+    let (data, _): (as_value::Deserialize<T>, _) = encoded.deserialize()?;
+    Ok(data.0)
+}
+
+impl CastRestoreData {
+    pub fn new(data: LuminousCastData) -> Self {
+        Self {
+            vendor_name: VENDOR_NAME.to_owned(),
+            version: CAST_RESTORE_DATA_VERSION,
+            data,
+        }
+    }
+
+    /// Resolve the stored sources back to live outputs or toplevels, failing if any is gone
+    fn restore_targets(
+        &self,
+        connection: &WayshotConnection,
+        multiple: bool,
+        exclusion: &Exclusion,
+    ) -> Option<Vec<(CastTarget, SourceType, RestoreSource)>> {
+        if self.vendor_name != VENDOR_NAME || self.version != CAST_RESTORE_DATA_VERSION {
+            return None;
+        }
+        let count = if multiple { self.data.sources.len() } else { 1 };
+        let targets: Vec<_> = self
+            .data
+            .sources
+            .iter()
+            .take(count)
+            .map(|source| {
+                let target = source.find_target(connection, exclusion)?;
+                Some((target, source.source_type, source.clone()))
+            })
+            .collect::<Option<_>>()?;
+        (!targets.is_empty()).then_some(targets)
+    }
+}
+
+#[derive(Type, Debug, Default, Deserialize, Serialize, Clone)]
+pub struct LuminousCastData {
+    pub sources: Vec<RestoreSource>,
+}
+
+/// Version 1, sources without an area
+#[derive(Type, Debug, Deserialize)]
+struct LuminousCastDataV1 {
+    sources: Vec<(SourceType, String, String)>,
+}
+
+impl From<LuminousCastDataV1> for LuminousCastData {
+    fn from(data: LuminousCastDataV1) -> Self {
+        Self {
+            sources: data
+                .sources
+                .into_iter()
+                .map(|(source_type, name, title)| RestoreSource {
+                    source_type,
+                    name,
+                    title,
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+}
+
+// The name of a window source which follows the focus, app ids never start with @
+const ACTIVE_WINDOW: &str = "@active-window";
+// The name of a monitor source with every output, output names never start with @
+const ALL_OUTPUTS: &str = "@all-outputs";
+
+#[derive(Type, Debug, Default, Deserialize, Serialize, Clone, PartialEq)]
+/// A cast source, remembered by output name or by toplevel app_id and title
+pub struct RestoreSource {
+    pub source_type: SourceType,
+    pub name: String,
+    pub title: String,
+    /// x, y, width and height of an area inside the output, all zero for the whole output
+    pub region: (i32, i32, i32, i32),
+}
+
+impl RestoreSource {
+    fn monitor(output: &OutputInfo) -> Self {
+        Self {
+            source_type: SourceType::Monitor,
+            name: output.name.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn area(output: &OutputInfo, region: Region) -> Self {
+        Self {
+            source_type: SourceType::Monitor,
+            name: output.name.clone(),
+            region: (
+                region.position.x,
+                region.position.y,
+                region.size.width as i32,
+                region.size.height as i32,
+            ),
+            ..Default::default()
+        }
+    }
+
+    /// Restoring it creates a new virtual output, the name is only for the user
+    fn virtual_output(output: &OutputInfo) -> Self {
+        Self {
+            source_type: SourceType::Virtual,
+            name: output.name.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn window(top_level: &TopLevel) -> Self {
+        Self {
+            source_type: SourceType::Window,
+            name: top_level.app_id.clone(),
+            title: top_level.title.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn all_outputs() -> Self {
+        Self {
+            source_type: SourceType::Monitor,
+            name: ALL_OUTPUTS.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn covers_all_outputs(&self) -> bool {
+        self.source_type == SourceType::Monitor && self.name == ALL_OUTPUTS
+    }
+
+    fn active_window() -> Self {
+        Self {
+            source_type: SourceType::Window,
+            name: ACTIVE_WINDOW.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn follows_focus(&self) -> bool {
+        self.source_type == SourceType::Window && self.name == ACTIVE_WINDOW
+    }
+
+    /// A short human readable name of the source
+    fn description(&self) -> String {
+        let (x, y, width, height) = self.region;
+        match self.source_type {
+            SourceType::Window if self.follows_focus() => "Active window".to_owned(),
+            SourceType::Monitor if self.covers_all_outputs() => "All outputs".to_owned(),
+            SourceType::Window if !self.title.is_empty() => {
+                format!("{}: {}", self.name, self.title)
+            }
+            SourceType::Monitor if width > 0 && height > 0 => {
+                format!("{} ({width}x{height} at {x},{y})", self.name)
+            }
+            _ => self.name.clone(),
+        }
+    }
+
+    /// How a running stream finds the source again, an area or a new virtual output
+    /// cannot be switched to
+    fn cast_source(&self) -> Option<CastSource> {
+        match self.source_type {
+            SourceType::Window if self.follows_focus() => None,
+            SourceType::Monitor if self.covers_all_outputs() => None,
+            SourceType::Monitor if self.region == (0, 0, 0, 0) => {
+                Some(CastSource::Output(self.name.clone()))
+            }
+            SourceType::Window => Some(CastSource::Toplevel {
+                app_id: self.name.clone(),
+                title: self.title.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// A window of an excluded app is never found
+    fn find_target(
+        &self,
+        connection: &WayshotConnection,
+        exclusion: &Exclusion,
+    ) -> Option<CastTarget> {
+        match self.source_type {
+            SourceType::Monitor if self.covers_all_outputs() => Some(CastTarget::AllOutputs),
+            SourceType::Monitor => connection
+                .get_all_outputs()
+                .iter()
+                .find(|output| output.name == self.name)
+                .map(|output| {
+                    let (x, y, width, height) = self.region;
+                    if width <= 0 || height <= 0 {
+                        return CastTarget::Screen(output.wl_output.clone());
+                    }
+                    area_target(
+                        output,
+                        Region {
+                            position: Position { x, y },
+                            size: libwayshot::Size {
+                                width: width as u32,
+                                height: height as u32,
+                            },
+                        },
+                    )
+                }),
+            SourceType::Window if self.follows_focus() => {
+                active_window_target(connection, exclusion)
+            }
+            SourceType::Window if exclusion.excludes(&self.name) => None,
+            SourceType::Window => {
+                let top_levels = connection.get_all_toplevels();
+                // The title may change between runs, so fall back to the app_id alone
+                top_levels
+                    .iter()
+                    .find(|top_level| {
+                        top_level.app_id == self.name && top_level.title == self.title
+                    })
+                    .or_else(|| {
+                        top_levels
+                            .iter()
+                            .find(|top_level| top_level.app_id == self.name)
+                    })
+                    .map(|top_level| CastTarget::TopLevel(top_level.handle.clone()))
+            }
+            SourceType::Virtual => None,
+        }
+    }
+}
+
+/// The window a stream following the focus starts with
+fn active_window_target(
+    connection: &WayshotConnection,
+    exclusion: &Exclusion,
+) -> Option<CastTarget> {
+    let source = active_window(connection)
+        .inspect_err(|e| tracing::warn!("Cannot find the active window: {e}"))
+        .ok()??;
+    let CastSource::Toplevel { app_id, title } = source else {
+        return None;
+    };
+    RestoreSource {
+        source_type: SourceType::Window,
+        name: app_id,
+        title,
+        ..Default::default()
+    }
+    .find_target(connection, exclusion)
+}
+
+fn area_target(output: &OutputInfo, region: Region) -> CastTarget {
+    CastTarget::Area {
+        output: output.wl_output.clone(),
+        region,
+        output_size: output.logical_region.inner.size,
+    }
+}
+
+/// Let the user drag out an area, returning the output it was drawn on and the area
+/// relative to that output, clamped to its bounds
+fn select_area(connection: &WayshotConnection, outputs: &[OutputInfo]) -> Option<(usize, Region)> {
+    let info = WaySip::new()
+        .with_connection(connection.conn.clone())
+        .with_selection_type(SelectionType::Area)
+        .get()
+        .inspect_err(|e| tracing::warn!("Cannot select the area: {e}"))
+        .ok()??;
+    let index = outputs
+        .iter()
+        .position(|output| output.name == info.selected_screen_info().get_name())?;
+    let output = outputs[index].logical_region.inner;
+    let left_top = info.left_top_point();
+    let x = (left_top.x - output.position.x).clamp(0, output.size.width as i32 - 1);
+    let y = (left_top.y - output.position.y).clamp(0, output.size.height as i32 - 1);
+    let width = info.width().clamp(1, output.size.width as i32 - x);
+    let height = info.height().clamp(1, output.size.height as i32 - y);
+    Some((
+        index,
+        Region {
+            position: Position { x, y },
+            size: libwayshot::Size {
+                width: width as u32,
+                height: height as u32,
+            },
+        },
+    ))
 }
 
 #[derive(Clone, Serialize, Deserialize, Type, Default, Debug)]
@@ -82,6 +421,46 @@ struct StreamProperties {
     source_type: SourceType,
 }
 
+impl StreamProperties {
+    /// The id is the index of the source, which stays the same when the session is restored
+    fn new(
+        index: usize,
+        target: &CastTarget,
+        source_type: SourceType,
+        outputs: &[OutputInfo],
+        cast_thread: &ScreencastThread,
+    ) -> Self {
+        let output_region = |wl_output: &WlOutput| {
+            outputs
+                .iter()
+                .find(|output| &output.wl_output == wl_output)
+                .map(|output| output.logical_region.inner)
+        };
+        // A window has no place in the compositor space, only its frame size is known
+        let region = match target {
+            CastTarget::Screen(output) => output_region(output),
+            CastTarget::Area { output, region, .. } => {
+                output_region(output).map(|output_region| Region {
+                    position: Position {
+                        x: output_region.position.x + region.position.x,
+                        y: output_region.position.y + region.position.y,
+                    },
+                    size: region.size,
+                })
+            }
+            CastTarget::TopLevel(_) => None,
+            CastTarget::AllOutputs => Some(logical_extent(outputs)),
+        };
+        let size = region.map_or(cast_thread.size(), |region| region.size);
+        Self {
+            id: Some(index.to_string()),
+            position: region.map(|region| (region.position.x, region.position.y)),
+            size: (size.width as i32, size.height as i32),
+            source_type,
+        }
+    }
+}
+
 // TODO: this is copy from ashpd, but the dict is a little different from xdg_desktop_portal
 #[derive(Clone, Default, Debug, Type, Serialize, Deserialize)]
 #[zvariant(signature = "dict")]
@@ -92,16 +471,28 @@ struct StartReturnValue {
     persist_mode: u32,
     #[serde(with = "optional", skip_serializing_if = "Option::is_none", default)]
     restore_token: Option<String>,
+    #[serde(with = "optional", skip_serializing_if = "Option::is_none", default)]
+    restore_data: Option<CastRestoreData>,
 }
 
 pub struct CastSessionData {
     session_handle: String,
-    cast_thread: ScreencastThread,
+    app_id: String,
+    cast_threads: Vec<(ScreencastThread, StreamProperties)>,
+    /// Removed together with the session
+    virtual_output: Option<VirtualOutput>,
+    persist_mode: PersistMode,
+    restore_data: Option<CastRestoreData>,
 }
 pub static CAST_SESSIONS: LazyLock<Arc<Mutex<Vec<CastSessionData>>>> =
     LazyLock::new(|| Arc::new(Mutex::new(Vec::new())));
 
 pub async fn append_cast_session(session: CastSessionData) {
+    add_sharing_session(SharingSession {
+        handle: session.session_handle.clone(),
+        app_id: session.app_id.clone(),
+        kind: SharingKind::ScreenCast,
+    });
     let mut sessions = CAST_SESSIONS.lock().await;
     sessions.push(session)
 }
@@ -114,15 +505,36 @@ pub async fn remove_cast_session(path: &str) {
     else {
         return;
     };
-    sessions[index].cast_thread.stop();
+    for (cast_thread, _) in sessions[index].cast_threads.iter() {
+        cast_thread.stop();
+    }
+    remove_sharing_session(path);
     tracing::info!("session {} is stopped", sessions[index].session_handle);
     sessions.remove(index);
 }
 
+/// What the user picked in the chooser
+enum SourceChoice {
+    Sources(Vec<(CastTarget, SourceType, RestoreSource)>),
+    /// A new headless output, created once the chooser is closed so it is not captured in it
+    VirtualOutput,
+}
+
 pub struct ScreenCastBackend {
     pub toplevel_capture_support: bool,
+    pub cursor_metadata_support: bool,
+    pub virtual_outputs: Option<Arc<dyn VirtualOutputs>>,
     pub sender: Sender<Message>,
-    pub receiver: Receiver<CopySelect>,
+    /// Shared with the chooser, which runs without holding the backend
+    pub receiver: Arc<Mutex<Receiver<CopySelect>>>,
+}
+
+/// What the chooser needs from the backend, so it can be opened without holding the backend
+#[derive(Clone)]
+pub struct SourceChooser {
+    toplevel_capture_support: bool,
+    sender: Sender<Message>,
+    receiver: Arc<Mutex<Receiver<CopySelect>>>,
 }
 
 #[interface(name = "org.freedesktop.impl.portal.ScreenCast")]
@@ -134,16 +546,23 @@ impl ScreenCastBackend {
 
     #[zbus(property)]
     fn available_cursor_modes(&self) -> u32 {
-        (CursorMode::Hidden | CursorMode::Embedded).bits()
+        if self.cursor_metadata_support {
+            (CursorMode::Hidden | CursorMode::Embedded | CursorMode::Metadata).bits()
+        } else {
+            (CursorMode::Hidden | CursorMode::Embedded).bits()
+        }
     }
 
     #[zbus(property)]
     fn available_source_types(&self) -> u32 {
+        let mut source_types = BitFlags::from_flag(SourceType::Monitor);
         if self.toplevel_capture_support {
-            (SourceType::Monitor | SourceType::Window).bits()
-        } else {
-            BitFlags::from_flag(SourceType::Monitor).bits()
+            source_types |= SourceType::Window;
+        }
+        if self.virtual_outputs.is_some() {
+            source_types |= SourceType::Virtual;
         }
+        source_types.bits()
     }
 
     async fn create_session(
@@ -168,7 +587,7 @@ impl ScreenCastBackend {
                 },
             )
             .await?;
-        let current_session = Session::new(session_handle.clone(), SessionType::ScreenCast);
+        let current_session = Session::new(session_handle.clone(), SessionType::ScreenCast, app_id);
         append_session(current_session.clone()).await;
         server.at(session_handle.clone(), current_session).await?;
         Ok(PortalResponse::Success(SessionCreateResult {
@@ -195,24 +614,35 @@ impl ScreenCastBackend {
         Ok(PortalResponse::Success(HashMap::new()))
     }
 
+    // The backend is only borrowed shared, so other requests and switch_source go on while
+    // the chooser is open
     async fn start(
-        &mut self,
+        &self,
         _request_handle: ObjectPath<'_>,
         session_handle: ObjectPath<'_>,
-        _app_id: String,
+        app_id: String,
         _parent_window: String,
         _options: HashMap<String, Value<'_>>,
+        #[zbus(connection)] dbus_connection: &zbus::Connection,
     ) -> zbus::fdo::Result<PortalResponse<StartReturnValue>> {
+        if privacy_mode() {
+            return Ok(PortalResponse::Cancelled);
+        }
         let cast_sessions = CAST_SESSIONS.lock().await;
         if let Some(session) = cast_sessions
             .iter()
             .find(|session| session.session_handle == session_handle.to_string())
         {
             return Ok(PortalResponse::Success(StartReturnValue {
-                streams: vec![Stream(
-                    session.cast_thread.node_id(),
-                    StreamProperties::default(),
-                )],
+                streams: session
+                    .cast_threads
+                    .iter()
+                    .map(|(cast_thread, properties)| {
+                        Stream(cast_thread.node_id(), properties.clone())
+                    })
+                    .collect(),
+                persist_mode: session.persist_mode as u32,
+                restore_data: session.restore_data.clone(),
                 ..Default::default()
             }));
         }
@@ -233,11 +663,323 @@ impl ScreenCastBackend {
         }
         drop(locked_sessions);
 
-        let show_cursor = current_session.cursor_mode.show_cursor();
-        let connection = libwayshot::WayshotConnection::new().unwrap();
+        let offer_virtual = self.virtual_outputs.is_some()
+            && current_session.source_type.contains(SourceType::Virtual);
+        // With only virtual sources allowed there is nothing to choose from
+        let only_virtual = offer_virtual
+            && !current_session
+                .source_type
+                .intersects(SourceType::Monitor | SourceType::Window);
+
+        let cursor_mode = current_session.cursor_mode;
+        let show_cursor = cursor_mode.show_cursor();
+        let mut connection = libwayshot::WayshotConnection::new().unwrap();
+        let exclusion = Exclusion::from_config().await;
 
+        let restored = current_session
+            .cast_restore_data
+            .as_ref()
+            .and_then(|restore_data| {
+                restore_data.restore_targets(&connection, current_session.multiple, &exclusion)
+            })
+            .filter(|targets| {
+                targets.iter().all(|(_, source_type, _)| {
+                    current_session.source_type.contains(*source_type)
+                        && (*source_type != SourceType::Window || self.toplevel_capture_support)
+                })
+            });
+
+        let choice = if only_virtual {
+            SourceChoice::VirtualOutput
+        } else if let Some(restored) = restored {
+            tracing::info!(
+                "Restore the cast sources: {:?}",
+                restored
+                    .iter()
+                    .map(|(_, _, source)| source)
+                    .collect::<Vec<_>>()
+            );
+            SourceChoice::Sources(restored)
+        } else if SERVER_SOCK.exists() {
+            let outputs = connection.get_all_outputs();
+            let monitors: Vec<String> = outputs.iter().map(|output| output.name.clone()).collect();
+            let index = get_selection_from_socket(monitors)?;
+            let output = &outputs[index as usize];
+            SourceChoice::Sources(vec![(
+                CastTarget::Screen(output.wl_output.clone()),
+                SourceType::Monitor,
+                RestoreSource::monitor(output),
+            )])
+        } else {
+            let mut chooser = self.source_chooser();
+            let Some(choice) = chooser
+                .choose_sources(
+                    &connection,
+                    show_cursor,
+                    current_session.multiple,
+                    offer_virtual,
+                )
+                .await
+            else {
+                return Ok(PortalResponse::Cancelled);
+            };
+            choice
+        };
+
+        // Privacy mode may have been turned on while the chooser was open
+        if privacy_mode() {
+            return Ok(PortalResponse::Cancelled);
+        }
+
+        let (virtual_output, sources) = match choice {
+            SourceChoice::Sources(sources) => (None, sources),
+            SourceChoice::VirtualOutput => {
+                let virtual_output = self.create_virtual_output().await?;
+                connection.refresh_outputs().map_err(|e| {
+                    zbus::Error::Failure(format!("cannot list the outputs, error: {e}"))
+                })?;
+                let Some(output) = connection
+                    .get_all_outputs()
+                    .iter()
+                    .find(|output| output.name == virtual_output.name())
+                else {
+                    return Err(zbus::Error::Failure(format!(
+                        "cannot find the virtual output {}",
+                        virtual_output.name()
+                    ))
+                    .into());
+                };
+                let source = (
+                    CastTarget::Screen(output.wl_output.clone()),
+                    SourceType::Virtual,
+                    RestoreSource::virtual_output(output),
+                );
+                (Some(virtual_output), vec![source])
+            }
+        };
+        let outputs = connection.get_all_outputs();
+
+        // Every source gets its own pipewire stream, so the client can place them separately
+        let mut cast_threads = Vec::with_capacity(sources.len());
+        let mut restore_sources = Vec::with_capacity(sources.len());
+        for (target, source_type, restore_source) in sources {
+            let cast_connection = connection.try_clone().map_err(|e| {
+                zbus::Error::Failure(format!("cannot connect to wayland, error: {e}"))
+            })?;
+            let cast_thread = match ScreencastThread::start_cast(
+                cursor_mode,
+                target.clone(),
+                cast_connection,
+                restore_source.follows_focus(),
+            )
+            .await
+            {
+                Ok(cast_thread) => cast_thread,
+                Err(e) => {
+                    for (cast_thread, _) in cast_threads.iter() {
+                        cast_thread.stop();
+                    }
+                    return Err(zbus::Error::Failure(format!(
+                        "cannot start pipewire stream, error: {e}"
+                    ))
+                    .into());
+                }
+            };
+            let properties = StreamProperties::new(
+                cast_threads.len(),
+                &target,
+                source_type,
+                &outputs,
+                &cast_thread,
+            );
+            cast_threads.push((cast_thread, properties));
+            restore_sources.push(restore_source);
+        }
+
+        let streams = cast_threads
+            .iter()
+            .map(|(cast_thread, properties)| Stream(cast_thread.node_id(), properties.clone()))
+            .collect();
+        close_on_target_lost(
+            dbus_connection.clone(),
+            current_session.handle_path.clone(),
+            cast_threads
+                .iter_mut()
+                .filter_map(|(cast_thread, _)| cast_thread.take_target_lost())
+                .collect(),
+        );
+        let targets = restore_sources
+            .iter()
+            .map(RestoreSource::description)
+            .collect();
+        let persist_mode = current_session.persist_mode;
+        let restore_data = persist_mode.is_persist().then(|| {
+            CastRestoreData::new(LuminousCastData {
+                sources: restore_sources,
+            })
+        });
+
+        append_cast_session(CastSessionData {
+            session_handle: session_handle.to_string(),
+            app_id,
+            cast_threads,
+            virtual_output,
+            persist_mode,
+            restore_data: restore_data.clone(),
+        })
+        .await;
+        mark_session_started(&current_session.handle_path, targets).await;
+
+        Ok(PortalResponse::Success(StartReturnValue {
+            streams,
+            persist_mode: persist_mode as u32,
+            restore_data,
+            ..Default::default()
+        }))
+    }
+}
+
+/// Open the chooser again and move one stream of a running session to the chosen output or
+/// window, the client keeps the same PipeWire node
+pub async fn switch_source(
+    mut chooser: SourceChooser,
+    session_handle: &str,
+    stream: usize,
+) -> fdo::Result<()> {
+    let handle_path = OwnedObjectPath::try_from(session_handle)
+        .map_err(|e| fdo::Error::InvalidArgs(format!("Invalid session handle: {e}")))?;
+    {
+        let cast_sessions = CAST_SESSIONS.lock().await;
+        let Some(session) = cast_sessions
+            .iter()
+            .find(|session| session.session_handle == session_handle)
+        else {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "No cast session at {session_handle}"
+            )));
+        };
+        if stream >= session.cast_threads.len() {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "No stream {stream} in {session_handle}"
+            )));
+        }
+    }
+    let show_cursor = SESSIONS
+        .lock()
+        .await
+        .iter()
+        .find(|session| session.handle_path == handle_path)
+        .is_some_and(|session| session.cursor_mode.show_cursor());
+
+    let connection = WayshotConnection::new()
+        .map_err(|e| fdo::Error::Failed(format!("cannot connect to wayland, error: {e}")))?;
+    // No virtual output is offered, a stream cannot be moved onto a new one
+    let Some((target, source_type, restore_source)) = chooser
+        .choose_sources(&connection, show_cursor, false, false)
+        .await
+        .and_then(|choice| match choice {
+            SourceChoice::Sources(sources) => sources.into_iter().next(),
+            SourceChoice::VirtualOutput => None,
+        })
+    else {
+        return Err(fdo::Error::Failed(
+            "The user cancelled the switch".to_owned(),
+        ));
+    };
+    let Some(cast_source) = restore_source.cast_source() else {
+        return Err(fdo::Error::NotSupported(
+            "A stream cannot be switched to an area, all outputs or the active window".to_owned(),
+        ));
+    };
+
+    // The session may be gone while the chooser was open
+    let mut cast_sessions = CAST_SESSIONS.lock().await;
+    let Some(session) = cast_sessions
+        .iter_mut()
+        .find(|session| session.session_handle == session_handle)
+    else {
+        return Err(fdo::Error::Failed(format!(
+            "The session {session_handle} is closed"
+        )));
+    };
+    let Some((cast_thread, properties)) = session.cast_threads.get_mut(stream) else {
+        return Err(fdo::Error::Failed(format!("No stream {stream}")));
+    };
+    cast_thread
+        .retarget(cast_source)
+        .await
+        .map_err(|e| fdo::Error::Failed(format!("cannot switch the source, error: {e}")))?;
+    *properties = StreamProperties::new(
+        stream,
+        &target,
+        source_type,
+        connection.get_all_outputs(),
+        cast_thread,
+    );
+    if let Some(old_source) = session
+        .restore_data
+        .as_mut()
+        .and_then(|restore_data| restore_data.data.sources.get_mut(stream))
+    {
+        *old_source = restore_source.clone();
+    }
+    drop(cast_sessions);
+    update_session_target(&handle_path, stream, restore_source.description()).await;
+    tracing::info!(
+        "Stream {stream} of {session_handle} is switched to {}",
+        restore_source.description()
+    );
+    Ok(())
+}
+
+impl ScreenCastBackend {
+    pub fn source_chooser(&self) -> SourceChooser {
+        SourceChooser {
+            toplevel_capture_support: self.toplevel_capture_support,
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+        }
+    }
+
+    /// The IPC of the compositor blocks, so it runs off the executor
+    async fn create_virtual_output(&self) -> zbus::fdo::Result<VirtualOutput> {
+        let Some(virtual_outputs) = self.virtual_outputs.clone() else {
+            return Err(
+                zbus::Error::Failure("virtual outputs are not supported".to_owned()).into(),
+            );
+        };
+        tokio::task::spawn_blocking(move || VirtualOutput::create(virtual_outputs))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|created| created)
+            .map_err(|e| {
+                zbus::Error::Failure(format!("cannot create a virtual output, error: {e}")).into()
+            })
+    }
+}
+
+impl SourceChooser {
+    /// Show the chooser with previews of the outputs and windows, None when the user
+    /// cancels it
+    async fn choose_sources(
+        &mut self,
+        connection: &WayshotConnection,
+        show_cursor: bool,
+        multiple: bool,
+        offer_virtual: bool,
+    ) -> Option<SourceChoice> {
         use iced::widget::image;
-        let top_levels = connection.get_all_toplevels();
+        // One chooser at a time, so each answer goes back to the request which opened it
+        let mut receiver = self.receiver.lock().await;
+        let exclusion = Exclusion::from_config().await;
+        let top_levels: Vec<TopLevel> = connection
+            .get_all_toplevels()
+            .iter()
+            .filter(|top_level| !exclusion.excludes(&top_level.app_id))
+            .cloned()
+            .collect();
+        let outputs = connection.get_all_outputs();
+
         let mut top_levels_iced = vec![];
         use std::sync::mpsc;
         // NOTE: seems that when we shot the screen first time, it will influence the status later
@@ -282,102 +1024,177 @@ impl ScreenCastBackend {
                     .collect()
             };
         }
-        let outputs = connection.get_all_outputs();
-
-        let (target, source_type) = if SERVER_SOCK.exists() {
-            let monitors: Vec<String> = outputs.iter().map(|output| output.name.clone()).collect();
-            let index = get_selection_from_socket(monitors)?;
-            (
-                CastTarget::Screen(outputs[index as usize].wl_output.clone()),
-                SourceType::Monitor,
-            )
-        } else {
-            // NOTE: seems that when we shot the screen first time, it will influence the status later
-            let outputs_iced: Vec<WlOutputInfo> = std::thread::scope(|scope| {
-                let tasks: Vec<(OutputInfo, ScopedJoinHandle<Option<image::Handle>>)> = outputs
-                    .iter()
-                    .map(|output| {
-                        let way_conn = connection.try_clone();
-                        (
-                            output.clone(),
-                            scope.spawn(move || {
-                                let Ok(way_conn) = way_conn else {
-                                    return None;
-                                };
-
-                                way_conn
-                                    .screenshot_single_output(output, show_cursor)
-                                    .map(|data| {
-                                        let rgba_data = data.to_rgba8();
-                                        image::Handle::from_rgba(
-                                            rgba_data.width(),
-                                            rgba_data.height(),
-                                            rgba_data.into_raw(),
-                                        )
-                                    })
-                                    .ok()
-                            }),
-                        )
-                    })
-                    .collect();
-                tasks
-                    .into_iter()
-                    .map(|(output, task)| WlOutputInfo {
-                        output,
-                        image: task.join().unwrap_or(None),
-                    })
-                    .collect()
-            });
+        // NOTE: seems that when we shot the screen first time, it will influence the status later
+        let outputs_iced: Vec<WlOutputInfo> = std::thread::scope(|scope| {
+            let tasks: Vec<(OutputInfo, ScopedJoinHandle<Option<image::Handle>>)> = outputs
+                .iter()
+                .map(|output| {
+                    let way_conn = connection.try_clone();
+                    let exclusion = &exclusion;
+                    (
+                        output.clone(),
+                        scope.spawn(move || {
+                            let Ok(way_conn) = way_conn else {
+                                return None;
+                            };
 
-            let _ = self
-                .sender
-                .send(Message::ScreenCastOpen {
-                    top_levels: top_levels_iced,
-                    screens: outputs_iced,
-                    show_cursor,
+                            let mut rgba_data = way_conn
+                                .screenshot_single_output(output, show_cursor)
+                                .ok()?
+                                .to_rgba8();
+                            let (width, height) = rgba_data.dimensions();
+                            // No preview is better than one with the excluded windows
+                            exclusion
+                                .apply(&mut rgba_data, width, height, output.logical_region.inner)
+                                .inspect_err(|e| {
+                                    tracing::warn!("Cannot hide the excluded windows: {e}")
+                                })
+                                .ok()?;
+                            Some(image::Handle::from_rgba(
+                                width,
+                                height,
+                                rgba_data.into_raw(),
+                            ))
+                        }),
+                    )
                 })
-                .await;
-            let Some(select) = self.receiver.next().await else {
-                return Ok(PortalResponse::Cancelled);
-            };
-            match select {
-                CopySelect::Screen { index, .. } => (
+                .collect();
+            tasks
+                .into_iter()
+                .map(|(output, task)| WlOutputInfo {
+                    output,
+                    image: task.join().unwrap_or(None),
+                })
+                .collect()
+        });
+
+        let _ = self
+            .sender
+            .send(Message::ScreenCastOpen {
+                top_levels: top_levels_iced,
+                screens: outputs_iced,
+                show_cursor,
+                multiple,
+                virtual_output: offer_virtual,
+            })
+            .await;
+        let Some(select) = receiver.next().await else {
+            return None;
+        };
+        if select == CopySelect::VirtualOutput {
+            return Some(SourceChoice::VirtualOutput);
+        }
+        let selects = match select {
+            CopySelect::Multiple(selects) => selects,
+            select => vec![select],
+        };
+        if selects.contains(&CopySelect::Slurp) {
+            // reserve time to let dialog disappear
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        }
+        let sources: Vec<_> = selects
+            .into_iter()
+            .filter_map(|select| match select {
+                CopySelect::Screen { index, .. } => Some((
                     CastTarget::Screen(outputs[index].wl_output.clone()),
                     SourceType::Monitor,
-                ),
-                CopySelect::Window { index, .. } => (
+                    RestoreSource::monitor(&outputs[index]),
+                )),
+                CopySelect::Window { index, .. } => Some((
                     CastTarget::TopLevel(top_levels[index].handle.clone()),
                     SourceType::Window,
-                ),
-                _ => {
-                    return Ok(PortalResponse::Cancelled);
+                    RestoreSource::window(&top_levels[index]),
+                )),
+                CopySelect::All => Some((
+                    CastTarget::AllOutputs,
+                    SourceType::Monitor,
+                    RestoreSource::all_outputs(),
+                )),
+                CopySelect::ActiveWindow => Some((
+                    active_window_target(connection, &exclusion)?,
+                    SourceType::Window,
+                    RestoreSource::active_window(),
+                )),
+                CopySelect::Slurp => {
+                    let (index, region) = select_area(connection, outputs)?;
+                    Some((
+                        area_target(&outputs[index], region),
+                        SourceType::Monitor,
+                        RestoreSource::area(&outputs[index], region),
+                    ))
                 }
-            }
-        };
+                _ => None,
+            })
+            .collect();
+        if sources.is_empty() {
+            return None;
+        }
+        Some(SourceChoice::Sources(sources))
+    }
+}
 
-        let cast_thread = ScreencastThread::start_cast(show_cursor, target, connection)
-            .await
-            .map_err(|e| {
-                zbus::Error::Failure(format!("cannot start pipewire stream, error: {e}"))
-            })?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let node_id = cast_thread.node_id();
+    #[derive(Type, Serialize)]
+    #[zvariant(signature = "(suv)")]
+    struct RestoreDataOf<T: Type + Serialize> {
+        vendor_name: String,
+        version: u32,
+        #[serde(with = "as_value")]
+        data: T,
+    }
 
-        append_cast_session(CastSessionData {
-            session_handle: session_handle.to_string(),
-            cast_thread,
-        })
-        .await;
+    fn round_trip<T: Type + Serialize>(version: u32, data: T) -> CastRestoreData {
+        let restore_data = RestoreDataOf {
+            vendor_name: VENDOR_NAME.to_owned(),
+            version,
+            data,
+        };
+        let encoded = zbus::zvariant::to_bytes(Context::new_dbus(LE, 0), &restore_data).unwrap();
+        encoded.deserialize().unwrap().0
+    }
 
-        Ok(PortalResponse::Success(StartReturnValue {
-            streams: vec![Stream(
-                node_id,
-                StreamProperties {
-                    source_type,
-                    ..Default::default()
-                },
-            )],
-            ..Default::default()
-        }))
+    #[test]
+    fn restore_data_keeps_the_area() {
+        let data = LuminousCastData {
+            sources: vec![RestoreSource {
+                name: "DP-1".to_owned(),
+                region: (10, 20, 300, 200),
+                ..Default::default()
+            }],
+        };
+        let restore_data = round_trip(CAST_RESTORE_DATA_VERSION, data.clone());
+        assert_eq!(restore_data.version, CAST_RESTORE_DATA_VERSION);
+        assert_eq!(restore_data.data.sources, data.sources);
+    }
+
+    #[test]
+    fn restore_data_of_version_1_is_migrated() {
+        // LuminousCastDataV1 is a struct with one field
+        let data = (vec![(
+            SourceType::Window,
+            "foot".to_owned(),
+            "fish".to_owned(),
+        )],);
+        let restore_data = round_trip(1, data);
+        assert_eq!(restore_data.version, CAST_RESTORE_DATA_VERSION);
+        assert_eq!(
+            restore_data.data.sources,
+            vec![RestoreSource {
+                source_type: SourceType::Window,
+                name: "foot".to_owned(),
+                title: "fish".to_owned(),
+                region: (0, 0, 0, 0),
+            }]
+        );
+    }
+
+    #[test]
+    fn restore_data_of_an_unknown_version_is_empty() {
+        let restore_data = round_trip(CAST_RESTORE_DATA_VERSION + 1, "something else");
+        assert_eq!(restore_data.version, CAST_RESTORE_DATA_VERSION + 1);
+        assert!(restore_data.data.sources.is_empty());
     }
 }
@@ -1,10 +1,11 @@
 use libwayshot::{
-    WayshotConnection,
+    TopLevel, WayshotConnection,
     region::{LogicalRegion, Region, Size},
 };
 use serde::Deserialize;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use zbus::zvariant::{Type, Value};
 use zbus::{
     fdo, interface,
@@ -15,10 +16,13 @@ use zbus::{
 };
 
 use crate::dialog::{
-    CopySelect, Message, PermissionMode, PermissionResult, TopLevelInfo, WlOutputInfo,
+    CopySelect, DialogParent, Message, PermissionMode, PermissionResult, ScreenshotImage,
+    ShotOptions, TopLevelInfo, WlOutputInfo,
 };
+use crate::exclusion::{Exclusion, black_out};
+use crate::logind::session_locked;
+use crate::privacy::privacy_mode;
 use crate::settings::SETTING_CONFIG;
-use crate::utils::USER_RUNNING_DIR;
 use crate::{PortalResponse, settings::WHITE_LIST_MAINTAINER};
 use futures::{
     SinkExt, StreamExt,
@@ -27,6 +31,16 @@ use futures::{
 
 use libwaysip::WaySip;
 
+mod storage;
+mod target;
+
+use storage::ScreenshotStorage;
+use target::{ScreenshotTarget, window_output};
+
+// The countdown overlay closes when it reaches zero and the dialog tells once it is gone. A
+// dialog which never does cancels the shot, rather than capturing the overlay
+const COUNTDOWN_CLOSE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
 #[derive(Type, Serialize, Deserialize)]
 #[zvariant(signature = "dict")]
 struct Screenshot {
@@ -80,19 +94,66 @@ pub fn waysip_to_region(
     })
 }
 
+/// The clipboard gets a png, whatever format the file is saved in
+fn encode_png(image: &image::RgbaImage) -> image::ImageResult<Vec<u8>> {
+    let mut png = std::io::Cursor::new(Vec::new());
+    image.write_to(&mut png, image::ImageOutputFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+/// Modal dialogs go on the output of the window of the app. The parent window handle is
+/// exported through xdg-foreign, which tells nothing about outputs, so the window is found
+/// by its app id
+fn dialog_parent(
+    connection: &WayshotConnection,
+    app_id: &str,
+    parent_window: &str,
+    modal: bool,
+) -> DialogParent {
+    // Without a parent there is nothing to be modal to
+    let modal = modal && !parent_window.is_empty();
+    let output = if modal && !app_id.is_empty() {
+        window_output(connection, Some(app_id))
+            .inspect_err(|e| tracing::warn!("Cannot find the window of {app_id}: {e}"))
+            .ok()
+            .flatten()
+    } else {
+        None
+    };
+    DialogParent { modal, output }
+}
+
+/// The logical region a screenshot of every output covers
+fn all_outputs_region(connection: &WayshotConnection) -> Option<Region> {
+    LogicalRegion::try_from(connection.get_all_outputs())
+        .ok()
+        .map(|region| region.inner)
+}
+
 #[interface(name = "org.freedesktop.impl.portal.Screenshot")]
 impl ScreenShotBackend {
     #[zbus(property, name = "version")]
     fn version(&self) -> u32 {
-        1
+        2
     }
     async fn screenshot(
         &mut self,
         handle: ObjectPath<'_>,
         app_id: String,
-        _parent_window: String,
+        parent_window: String,
         options: ScreenshotOption,
     ) -> fdo::Result<PortalResponse<Screenshot>> {
+        if privacy_mode() || session_locked() {
+            return Ok(PortalResponse::Cancelled);
+        }
+        let wayshot_connection = WayshotConnection::new()
+            .map_err(|_| zbus::Error::Failure("Cannot update outputInfos".to_string()))?;
+        let parent = dialog_parent(
+            &wayshot_connection,
+            &app_id,
+            &parent_window,
+            options.modal.unwrap_or(true),
+        );
         if SETTING_CONFIG.lock().await.screenshot_permission_check
             // If it is failed
             && !WHITE_LIST_MAINTAINER.check_shot(&app_id).await
@@ -103,6 +164,7 @@ impl ScreenShotBackend {
                     message: format!("Allow '{}' to take a screenshot?", app_id),
                     mode: PermissionMode::ScreenShot,
                     id_valid: !app_id.is_empty(),
+                    parent: parent.clone(),
                 })
                 .await
                 .map_err(|e| zbus::Error::Failure(e.to_string()))?;
@@ -121,11 +183,21 @@ impl ScreenShotBackend {
             // reserve time to let dialog disappear
             tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
-        let wayshot_connection = WayshotConnection::new()
-            .map_err(|_| zbus::Error::Failure("Cannot update outputInfos".to_string()))?;
+        let exclusion = Exclusion::from_config().await;
         tracing::info!("Start shot: path :{}, appid: {}", handle.as_str(), app_id);
-        let image_buffer = if options.interactive {
-            let top_levels = wayshot_connection.get_all_toplevels();
+        // The chooser starts with the configured clipboard switch, and the user has the last word
+        let mut shot_options = ShotOptions {
+            clipboard: SETTING_CONFIG.lock().await.screenshot_clipboard,
+            ..ShotOptions::default()
+        };
+        // The logical region the image shows, excluded windows are blacked out in it
+        let (image_buffer, captured) = if options.interactive {
+            let top_levels: Vec<TopLevel> = wayshot_connection
+                .get_all_toplevels()
+                .iter()
+                .filter(|level| !exclusion.excludes(&level.app_id))
+                .cloned()
+                .collect();
             let screens = wayshot_connection.get_all_outputs();
             let top_levels_iced = top_levels
                 .iter()
@@ -146,56 +218,184 @@ impl ScreenShotBackend {
                 .send(Message::ImageCopyOpen {
                     top_levels: top_levels_iced,
                     screens: screens_iced,
+                    copy_to_clipboard: shot_options.clipboard,
+                    parent,
                 })
                 .await;
             let Some(select) = self.receiver.next().await else {
                 return Ok(PortalResponse::Cancelled);
             };
+            let select = match select {
+                CopySelect::Shot(select, options) => {
+                    shot_options = options;
+                    *select
+                }
+                select => select,
+            };
             tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            if shot_options.delay > 0 {
+                let _ = self
+                    .sender
+                    .send(Message::Countdown(shot_options.delay))
+                    .await;
+                let closed = tokio::time::timeout(
+                    std::time::Duration::from_secs(shot_options.delay) + COUNTDOWN_CLOSE_TIMEOUT,
+                    self.receiver.next(),
+                )
+                .await;
+                if !matches!(closed, Ok(Some(CopySelect::CountdownClosed))) {
+                    tracing::warn!("The countdown did not close, cancelling the screenshot");
+                    return Ok(PortalResponse::Cancelled);
+                }
+            }
+            // Privacy mode may have been turned on, or the session locked, while the chooser or
+            // the countdown was shown
+            if privacy_mode() || session_locked() {
+                return Ok(PortalResponse::Cancelled);
+            }
             match select {
-                CopySelect::Screen { index, show_cursor } => wayshot_connection
-                    .screenshot_single_output(&screens[index], show_cursor)
-                    .map_err(|e| zbus::Error::Failure(format!("Wayland screencopy failed, {e}")))?,
-                CopySelect::Window { index, show_cursor } => wayshot_connection
-                    .screenshot_toplevel(&top_levels[index], show_cursor)
-                    .map_err(|e| zbus::Error::Failure(format!("Wayland screencopy failed, {e}")))?,
-                CopySelect::All => wayshot_connection
-                    .screenshot_all(false)
-                    .map_err(|e| zbus::Error::Failure(format!("Wayland screencopy failed, {e}")))?,
-                CopySelect::Slurp => wayshot_connection
-                    .screenshot_freeze(
-                        |w_conn| {
-                            let info = WaySip::new()
-                                .with_connection(w_conn.conn.clone())
-                                .with_selection_type(libwaysip::SelectionType::Area)
-                                .get()
-                                .map_err(|e| libwayshot::Error::FreezeCallbackError(e.to_string()))?
-                                .ok_or(libwayshot::Error::FreezeCallbackError(
-                                    "Failed to capture the area".to_string(),
-                                ))?;
-                            waysip_to_region(info.size(), info.left_top_point())
-                        },
-                        false,
-                    )
-                    .map_err(|e| zbus::Error::Failure(format!("Wayland screencopy failed, {e}")))?,
+                CopySelect::Screen { index, show_cursor } => (
+                    wayshot_connection
+                        .screenshot_single_output(&screens[index], show_cursor)
+                        .map_err(|e| {
+                            zbus::Error::Failure(format!("Wayland screencopy failed, {e}"))
+                        })?,
+                    Some(screens[index].logical_region.inner),
+                ),
+                // Only the window itself is in the image, and excluded ones are not offered
+                CopySelect::Window { index, show_cursor } => (
+                    wayshot_connection
+                        .screenshot_toplevel(&top_levels[index], show_cursor)
+                        .map_err(|e| {
+                            zbus::Error::Failure(format!("Wayland screencopy failed, {e}"))
+                        })?,
+                    None,
+                ),
+                CopySelect::All => (
+                    wayshot_connection
+                        .screenshot_all(shot_options.show_cursor)
+                        .map_err(|e| {
+                            zbus::Error::Failure(format!("Wayland screencopy failed, {e}"))
+                        })?,
+                    all_outputs_region(&wayshot_connection),
+                ),
+                CopySelect::Slurp => {
+                    let selected = Arc::new(Mutex::new(None));
+                    let selected_in_callback = selected.clone();
+                    let image = wayshot_connection
+                        .screenshot_freeze(
+                            move |w_conn| {
+                                let info = WaySip::new()
+                                    .with_connection(w_conn.conn.clone())
+                                    .with_selection_type(libwaysip::SelectionType::Area)
+                                    .get()
+                                    .map_err(|e| {
+                                        libwayshot::Error::FreezeCallbackError(e.to_string())
+                                    })?
+                                    .ok_or(libwayshot::Error::FreezeCallbackError(
+                                        "Failed to capture the area".to_string(),
+                                    ))?;
+                                let region = waysip_to_region(info.size(), info.left_top_point())?;
+                                *selected_in_callback.lock().unwrap() = Some(region.inner);
+                                Ok(region)
+                            },
+                            shot_options.show_cursor,
+                        )
+                        .map_err(|e| {
+                            zbus::Error::Failure(format!("Wayland screencopy failed, {e}"))
+                        })?;
+                    let captured = *selected.lock().unwrap();
+                    (image, captured)
+                }
                 CopySelect::Cancel => {
                     return Ok(PortalResponse::Cancelled);
                 }
-                CopySelect::Permission(_) | CopySelect::BackgroundPermission { .. } => {
+                CopySelect::Permission(_)
+                | CopySelect::BackgroundPermission { .. }
+                | CopySelect::ActiveWindow
+                | CopySelect::VirtualOutput
+                | CopySelect::Multiple(_)
+                | CopySelect::Annotated(_)
+                | CopySelect::CountdownClosed
+                | CopySelect::Shot(..) => {
                     unreachable!()
                 }
             }
         } else {
-            wayshot_connection
-                .screenshot_all(false)
-                .map_err(|e| zbus::Error::Failure(format!("Wayland screencopy failed, {e}")))?
+            let target =
+                ScreenshotTarget::from_setting(&SETTING_CONFIG.lock().await.screenshot_target);
+            let compositor_output = target.compositor_output().await;
+            if privacy_mode() || session_locked() {
+                return Ok(PortalResponse::Cancelled);
+            }
+            match target.output(&wayshot_connection, compositor_output) {
+                Some(output) => (
+                    wayshot_connection
+                        .screenshot_single_output(&output, false)
+                        .map_err(|e| {
+                            zbus::Error::Failure(format!("Wayland screencopy failed, {e}"))
+                        })?,
+                    Some(output.logical_region.inner),
+                ),
+                None => (
+                    wayshot_connection.screenshot_all(false).map_err(|e| {
+                        zbus::Error::Failure(format!("Wayland screencopy failed, {e}"))
+                    })?,
+                    all_outputs_region(&wayshot_connection),
+                ),
+            }
+        };
+        let mut image = image_buffer.to_rgba8();
+        let (width, height) = image.dimensions();
+        if let Some(captured) = captured.filter(|_| !exclusion.is_empty()) {
+            // Like a cast, the whole screenshot is black when the windows cannot be found
+            let regions = exclusion.window_regions().await.unwrap_or_else(|e| {
+                tracing::error!(
+                    "Cannot find the excluded windows, blacking out the screenshot: {e}"
+                );
+                vec![captured]
+            });
+            black_out(&mut image, width, height, captured, &regions);
+        }
+        // libwayshot hands out images of another version of the image crate
+        let image = image::RgbaImage::from_raw(width, height, image.into_raw())
+            .ok_or_else(|| zbus::Error::Failure("The screenshot has no pixels".to_string()))?;
+
+        // The user may crop and draw on what they picked before the app gets it
+        let editor = SETTING_CONFIG.lock().await.screenshot_editor;
+        let image = if options.interactive && editor {
+            let _ = self
+                .sender
+                .send(Message::AnnotateOpen(ScreenshotImage::new(image)))
+                .await;
+            match self.receiver.next().await {
+                Some(CopySelect::Annotated(edited)) => edited.into_image(),
+                _ => return Ok(PortalResponse::Cancelled),
+            }
+        } else {
+            image
         };
+        // Nothing leaves the editor once privacy mode is on or the session is locked
+        if privacy_mode() || session_locked() {
+            return Ok(PortalResponse::Cancelled);
+        }
 
-        let savepath = USER_RUNNING_DIR.join("wayshot.png");
-        image_buffer.save(&savepath).map_err(|e| {
-            zbus::Error::Failure(format!("Cannot save to {}, e: {e}", savepath.display()))
-        })?;
-        tracing::info!("Shot Finished");
+        let clipboard_png = shot_options.clipboard.then(|| encode_png(&image));
+        let storage = ScreenshotStorage::from_config(&*SETTING_CONFIG.lock().await);
+        let savepath = storage
+            .save(image, &app_id)
+            .map_err(|e| zbus::Error::Failure(format!("Cannot save the screenshot, e: {e}")))?;
+        tracing::info!("Shot Finished, saved to {}", savepath.display());
+        // The file is there either way, a failed copy does not fail the request
+        if let Some(png) = clipboard_png {
+            let copied = match png {
+                Ok(png) => crate::clipboard::offer_screenshot(png).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = copied {
+                tracing::warn!("Cannot copy the screenshot to the clipboard: {e}");
+            }
+        }
         Ok(PortalResponse::Success(Screenshot {
             uri: url::Url::from_file_path(savepath).unwrap(),
         }))
@@ -208,6 +408,9 @@ impl ScreenShotBackend {
         _parent_window: String,
         _options: HashMap<String, Value<'_>>,
     ) -> fdo::Result<PortalResponse<Color>> {
+        if privacy_mode() || session_locked() {
+            return Ok(PortalResponse::Cancelled);
+        }
         let wayshot_connection = WayshotConnection::new()
             .map_err(|_| zbus::Error::Failure("Cannot update outputInfos".to_string()))?;
 
@@ -234,6 +437,10 @@ impl ScreenShotBackend {
             )
             .map_err(|e| zbus::Error::Failure(format!("Wayland screencopy failed, {e}")))?
             .to_rgba8();
+        // The screen stays frozen while the user picks, the session may be locked meanwhile
+        if privacy_mode() || session_locked() {
+            return Ok(PortalResponse::Cancelled);
+        }
         let pixel = image.get_pixel(0, 0);
         Ok(PortalResponse::Success(Color {
             color: [
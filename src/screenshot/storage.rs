@@ -0,0 +1,228 @@
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use image::codecs::pnm::{PnmSubtype, SampleEncoding};
+use image::{DynamicImage, ImageOutputFormat, RgbaImage};
+
+use crate::settings::SettingsConfig;
+use crate::utils::XDG_PICTURES_DIR;
+
+const JPEG_QUALITY: u8 = 90;
+// Past this many screenshots with the same name something is wrong with the template
+const MAX_COUNTER: u32 = 10000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScreenshotFormat {
+    Png,
+    Jpeg,
+    Qoi,
+    Pnm,
+}
+
+impl ScreenshotFormat {
+    fn from_setting(setting: &str) -> Self {
+        match setting {
+            "jpeg" => Self::Jpeg,
+            "qoi" => Self::Qoi,
+            "pnm" => Self::Pnm,
+            _ => Self::Png,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Qoi => "qoi",
+            Self::Pnm => "ppm",
+        }
+    }
+}
+
+/// Where and how screenshots are saved, every screenshot gets a file of its own
+pub struct ScreenshotStorage {
+    dir: PathBuf,
+    filename: String,
+    format: ScreenshotFormat,
+}
+
+impl ScreenshotStorage {
+    pub fn from_config(config: &SettingsConfig) -> Self {
+        Self {
+            dir: config
+                .screenshot_dir
+                .as_deref()
+                .map_or_else(|| XDG_PICTURES_DIR.clone(), expand_home),
+            filename: config.screenshot_filename.clone(),
+            format: ScreenshotFormat::from_setting(&config.screenshot_format),
+        }
+    }
+
+    /// Save the screenshot under a name no other file has, and return its path
+    pub fn save(&self, image: RgbaImage, app_id: &str) -> anyhow::Result<PathBuf> {
+        let (file, path) = self.create_file(app_id)?;
+        let mut writer = BufWriter::new(file);
+        // jpeg and ppm have no alpha channel, and screenshots are opaque anyway
+        let written = match self.format {
+            ScreenshotFormat::Png => {
+                DynamicImage::ImageRgba8(image).write_to(&mut writer, ImageOutputFormat::Png)
+            }
+            ScreenshotFormat::Qoi => {
+                DynamicImage::ImageRgba8(image).write_to(&mut writer, ImageOutputFormat::Qoi)
+            }
+            ScreenshotFormat::Jpeg => DynamicImage::ImageRgba8(image)
+                .to_rgb8()
+                .write_to(&mut writer, ImageOutputFormat::Jpeg(JPEG_QUALITY)),
+            ScreenshotFormat::Pnm => DynamicImage::ImageRgba8(image).to_rgb8().write_to(
+                &mut writer,
+                ImageOutputFormat::Pnm(PnmSubtype::Pixmap(SampleEncoding::Binary)),
+            ),
+        }
+        .map_err(anyhow::Error::from)
+        .and_then(|()| writer.flush().map_err(anyhow::Error::from));
+        if let Err(e) = written {
+            // A broken file would take the name for nothing
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+        Ok(path)
+    }
+
+    // The file is created exclusively, so concurrent requests cannot end up with one file.
+    // Without a counter in the template one is appended on a clash
+    fn create_file(&self, app_id: &str) -> io::Result<(File, PathBuf)> {
+        let app_id = if app_id.is_empty() {
+            "unknown".to_string()
+        } else {
+            app_id.replace('/', "_")
+        };
+        let stem = format_time(&self.filename).replace("{app_id}", &app_id);
+        let extension = self.format.extension();
+        for counter in 1..=MAX_COUNTER {
+            let name = if stem.contains("{counter}") {
+                stem.replace("{counter}", &counter.to_string())
+            } else if counter == 1 {
+                stem.clone()
+            } else {
+                format!("{stem}-{counter}")
+            };
+            let path = self.dir.join(format!("{name}.{extension}"));
+            // The template may put screenshots into subdirectories, like "%Y/%m/shot"
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((file, path)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Every name for {stem} is taken"),
+        ))
+    }
+}
+
+fn expand_home(dir: &str) -> PathBuf {
+    match (dir.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) if rest.is_empty() || rest.starts_with('/') => {
+            PathBuf::from(home).join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(dir),
+    }
+}
+
+/// Expand the strftime conversions of the template with the local time
+fn format_time(template: &str) -> String {
+    let Ok(c_template) = CString::new(template) else {
+        return template.to_string();
+    };
+    let mut buffer = vec![0u8; template.len() * 8 + 256];
+    let written = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut local_time: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut local_time).is_null() {
+            return template.to_string();
+        }
+        libc::strftime(
+            buffer.as_mut_ptr().cast(),
+            buffer.len(),
+            c_template.as_ptr(),
+            &local_time,
+        )
+    };
+    if written == 0 {
+        return template.to_string();
+    }
+    buffer.truncate(written);
+    String::from_utf8_lossy(&buffer).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage(name: &str, filename: &str) -> ScreenshotStorage {
+        let dir =
+            std::env::temp_dir().join(format!("luminous-storage-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        ScreenshotStorage {
+            dir,
+            filename: filename.to_string(),
+            format: ScreenshotFormat::Png,
+        }
+    }
+
+    fn file_name(path: &std::path::Path) -> &str {
+        path.file_name().unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn create_file_appends_a_counter_on_a_clash() {
+        let storage = storage("clash", "shot_{app_id}");
+        let (_, first) = storage.create_file("org/app").unwrap();
+        let (_, second) = storage.create_file("org/app").unwrap();
+        assert_eq!(file_name(&first), "shot_org_app.png");
+        assert_eq!(file_name(&second), "shot_org_app-2.png");
+        std::fs::remove_dir_all(&storage.dir).unwrap();
+    }
+
+    #[test]
+    fn create_file_fills_the_counter_of_the_template() {
+        let storage = storage("counter", "sub/{counter}_{app_id}");
+        let (_, first) = storage.create_file("").unwrap();
+        let (_, second) = storage.create_file("").unwrap();
+        assert_eq!(first, storage.dir.join("sub/1_unknown.png"));
+        assert_eq!(second, storage.dir.join("sub/2_unknown.png"));
+        std::fs::remove_dir_all(&storage.dir).unwrap();
+    }
+
+    #[test]
+    fn expand_home_only_expands_the_own_home() {
+        if let Ok(home) = std::env::var("HOME") {
+            assert_eq!(expand_home("~"), PathBuf::from(&home));
+            assert_eq!(
+                expand_home("~/Pictures"),
+                PathBuf::from(&home).join("Pictures")
+            );
+        }
+        assert_eq!(
+            expand_home("~other/Pictures"),
+            PathBuf::from("~other/Pictures")
+        );
+        assert_eq!(expand_home("/srv/shots"), PathBuf::from("/srv/shots"));
+    }
+
+    #[test]
+    fn format_time_expands_conversions_only() {
+        let year = format_time("%Y");
+        assert_eq!(year.len(), 4);
+        assert!(year.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(format_time("shot_{app_id}_%%"), "shot_{app_id}_%");
+        assert_eq!(format_time(""), "");
+        assert_eq!(format_time("a\0b"), "a\0b");
+    }
+}
@@ -0,0 +1,127 @@
+use libwayshot::WayshotConnection;
+use libwayshot::output::OutputInfo;
+use serde::Deserialize;
+
+use crate::pipewirethread::FocusTracker;
+use crate::sway::{SWAY_GET_OUTPUTS, SwayIpc};
+
+#[derive(Debug, Deserialize)]
+struct SwayOutput {
+    name: String,
+    #[serde(default)]
+    focused: bool,
+}
+
+/// What a screenshot without the chooser shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenshotTarget {
+    All,
+    FocusedOutput,
+    ActiveOutput,
+}
+
+impl ScreenshotTarget {
+    pub fn from_setting(setting: &str) -> Self {
+        match setting {
+            "focused-output" => Self::FocusedOutput,
+            "active-output" => Self::ActiveOutput,
+            _ => Self::All,
+        }
+    }
+
+    /// The output sway has focused, asked for active-output only. It may have no window, and
+    /// with `focus_follows_mouse` it is usually the output under the pointer, which neither
+    /// Wayland nor the sway ipc tell about
+    pub async fn compositor_output(self) -> Option<String> {
+        if self != Self::ActiveOutput {
+            return None;
+        }
+        let ipc = SwayIpc::from_env()?;
+        tokio::task::spawn_blocking(move || sway_focused_output(&ipc))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|output| output)
+            .inspect_err(|e| tracing::warn!("Cannot ask sway for the focused output: {e}"))
+            .ok()
+            .flatten()
+    }
+
+    /// The output to capture, `None` for all of them. `compositor_output` is what
+    /// [`Self::compositor_output`] found. When the output cannot be found every output is
+    /// captured, rather than failing the request
+    pub fn output(
+        self,
+        connection: &WayshotConnection,
+        compositor_output: Option<String>,
+    ) -> Option<OutputInfo> {
+        let name = match self {
+            Self::All => return None,
+            Self::FocusedOutput => focused_output(connection),
+            // Without sway, the focused window is the best guess where the user is
+            Self::ActiveOutput => compositor_output.or_else(|| focused_output(connection)),
+        };
+        let Some(name) = name else {
+            tracing::warn!("Cannot find the output for {self:?}, capturing all outputs");
+            return None;
+        };
+        connection
+            .get_all_outputs()
+            .iter()
+            .find(|output| output.name == name)
+            .cloned()
+    }
+}
+
+/// The name of the output a window of the app is on, or the focused window is on without an
+/// app id. Only wlr-foreign-toplevel-management tells where windows are
+pub fn window_output(
+    connection: &WayshotConnection,
+    app_id: Option<&str>,
+) -> anyhow::Result<Option<String>> {
+    let Some(output) = FocusTracker::new(connection)?.output_of(app_id) else {
+        return Ok(None);
+    };
+    Ok(connection
+        .get_all_outputs()
+        .iter()
+        .find(|info| info.wl_output == output)
+        .map(|info| info.name.clone()))
+}
+
+fn focused_output(connection: &WayshotConnection) -> Option<String> {
+    window_output(connection, None)
+        .inspect_err(|e| tracing::warn!("Cannot find the focused window: {e}"))
+        .ok()
+        .flatten()
+}
+
+fn sway_focused_output(ipc: &SwayIpc) -> anyhow::Result<Option<String>> {
+    let outputs: Vec<SwayOutput> = ipc.request(SWAY_GET_OUTPUTS, "")?;
+    Ok(outputs
+        .into_iter()
+        .find(|output| output.focused)
+        .map(|output| output.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_setting_falls_back_to_all() {
+        assert_eq!(
+            ScreenshotTarget::from_setting("focused-output"),
+            ScreenshotTarget::FocusedOutput
+        );
+        assert_eq!(
+            ScreenshotTarget::from_setting("active-output"),
+            ScreenshotTarget::ActiveOutput
+        );
+        assert_eq!(ScreenshotTarget::from_setting("all"), ScreenshotTarget::All);
+        assert_eq!(ScreenshotTarget::from_setting(""), ScreenshotTarget::All);
+        assert_eq!(
+            ScreenshotTarget::from_setting("pointer-output"),
+            ScreenshotTarget::All
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use enumflags2::{BitFlags, bitflags};
+use futures::{StreamExt, stream::FuturesUnordered};
 use zbus::{interface, object_server::SignalEmitter, zvariant::OwnedObjectPath};
 
 use serde::{Deserialize, Serialize};
@@ -6,12 +7,13 @@ use serde_repr::{Deserialize_repr, Serialize_repr};
 use zbus::zvariant::Type;
 
 use std::sync::{Arc, LazyLock};
-use tokio::sync::Mutex;
+use std::time::SystemTime;
+use tokio::sync::{Mutex, oneshot};
 
 use crate::{
     clipboard::remove_clipboard_session,
     remotedesktop::{RestoreData, SelectDevicesOptions, remove_remote_session},
-    screencast::{SelectSourcesOptions, remove_cast_session},
+    screencast::{CastRestoreData, SelectSourcesOptions, remove_cast_session},
 };
 
 pub static SESSIONS: LazyLock<Arc<Mutex<Vec<Session>>>> =
@@ -22,6 +24,31 @@ pub async fn append_session(session: Session) {
     sessions.push(session)
 }
 
+/// Remember what a started session captures, for the sessions interface
+pub async fn mark_session_started(handle_path: &OwnedObjectPath, targets: Vec<String>) {
+    let mut sessions = SESSIONS.lock().await;
+    let Some(session) = sessions
+        .iter_mut()
+        .find(|the_session| the_session.handle_path == *handle_path)
+    else {
+        return;
+    };
+    session.targets = targets;
+    session.started = Some(SystemTime::now());
+}
+
+/// A stream of a running session shows another output or window now
+pub async fn update_session_target(handle_path: &OwnedObjectPath, index: usize, target: String) {
+    let mut sessions = SESSIONS.lock().await;
+    if let Some(old_target) = sessions
+        .iter_mut()
+        .find(|the_session| the_session.handle_path == *handle_path)
+        .and_then(|session| session.targets.get_mut(index))
+    {
+        *old_target = target;
+    }
+}
+
 #[must_use]
 pub async fn remove_session(session: &Session) -> bool {
     // It will always alive
@@ -42,6 +69,69 @@ pub async fn remove_session(session: &Session) -> bool {
     true
 }
 
+/// Close the session once one of its streams lost its target, like a closed window or an
+/// unplugged output
+pub fn close_on_target_lost(
+    connection: zbus::Connection,
+    handle_path: OwnedObjectPath,
+    target_lost: Vec<oneshot::Receiver<()>>,
+) {
+    if target_lost.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut target_lost: FuturesUnordered<_> = target_lost.into_iter().collect();
+        // An error means that stream was stopped, the others may still lose their target
+        loop {
+            match target_lost.next().await {
+                Some(Ok(())) => break,
+                Some(Err(_)) => continue,
+                None => return,
+            }
+        }
+        tracing::info!("The target of session {handle_path} is gone");
+        let _ = close_session(&connection, handle_path, "Target lost").await;
+    });
+}
+
+/// Close the session from the backend side and emit Closed. Unlike [`remove_session`], this
+/// ignores the persist mode. Returns false when there is no such session
+pub async fn close_session(
+    connection: &zbus::Connection,
+    handle_path: OwnedObjectPath,
+    reason: &str,
+) -> bool {
+    let mut sessions = SESSIONS.lock().await;
+    let Some(index) = sessions
+        .iter()
+        .position(|the_session| the_session.handle_path == handle_path)
+    else {
+        return false;
+    };
+    sessions.remove(index);
+    drop(sessions);
+    remove_cast_session(handle_path.as_str()).await;
+    remove_remote_session(handle_path.as_str()).await;
+    remove_clipboard_session(handle_path.as_ref()).await;
+
+    if let Err(e) = connection
+        .object_server()
+        .remove::<Session, _>(&handle_path)
+        .await
+    {
+        tracing::warn!("Cannot remove the session {handle_path}: {e}");
+    }
+    match SignalEmitter::new(connection, handle_path.as_ref()) {
+        Ok(emitter) => {
+            if let Err(e) = Session::closed(&emitter, reason).await {
+                tracing::warn!("Cannot emit Closed for {handle_path}: {e}");
+            }
+        }
+        Err(e) => tracing::warn!("Cannot emit Closed for {handle_path}: {e}"),
+    }
+    true
+}
+
 #[bitflags]
 #[derive(Serialize_repr, Default, Deserialize_repr, PartialEq, Eq, Copy, Clone, Debug, Type)]
 #[repr(u32)]
@@ -88,8 +178,9 @@ pub enum DeviceType {
 }
 
 impl CursorMode {
+    /// Whether the cursor is painted into the frames
     pub fn show_cursor(&self) -> bool {
-        !matches!(self, CursorMode::Hidden)
+        matches!(self, CursorMode::Embedded)
     }
 }
 
@@ -100,6 +191,16 @@ pub enum SessionType {
     InputCapture,
 }
 
+impl SessionType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ScreenCast => "screencast",
+            Self::Remote => "remote-desktop",
+            Self::InputCapture => "input-capture",
+        }
+    }
+}
+
 #[derive(Default, Serialize, Deserialize, PartialEq, Eq, Debug, Copy, Clone, Type)]
 #[repr(u32)]
 /// Persistence mode for a screencast session.
@@ -124,6 +225,10 @@ impl PersistMode {
 pub struct Session {
     pub session_type: SessionType,
     pub handle_path: OwnedObjectPath,
+    pub app_id: String,
+    /// What the session captures, filled in once it is started
+    pub targets: Vec<String>,
+    pub started: Option<SystemTime>,
     pub source_type: BitFlags<SourceType>,
     pub multiple: bool,
     pub cursor_mode: CursorMode,
@@ -133,13 +238,21 @@ pub struct Session {
     pub screen_share_enabled: bool,
     pub clipboard_requested: bool,
     pub restore_data: Option<RestoreData>,
+    pub cast_restore_data: Option<CastRestoreData>,
 }
 
 impl Session {
-    pub fn new<P: Into<OwnedObjectPath>>(path: P, session_type: SessionType) -> Self {
+    pub fn new<P: Into<OwnedObjectPath>>(
+        path: P,
+        session_type: SessionType,
+        app_id: impl Into<String>,
+    ) -> Self {
         Self {
             session_type,
             handle_path: path.into(),
+            app_id: app_id.into(),
+            targets: Vec::new(),
+            started: None,
             source_type: SourceType::Monitor.into(),
             multiple: false,
             cursor_mode: CursorMode::Hidden,
@@ -148,6 +261,7 @@ impl Session {
             screen_share_enabled: false,
             clipboard_requested: false,
             restore_data: None,
+            cast_restore_data: None,
         }
     }
     pub fn set_screencast_options(&mut self, options: SelectSourcesOptions) {
@@ -162,6 +276,7 @@ impl Session {
         if let Some(persist_mode) = options.persist_mode {
             self.persist_mode = persist_mode;
         }
+        self.cast_restore_data = options.restore_data;
     }
 
     pub fn set_remote_options(&mut self, options: SelectDevicesOptions) {
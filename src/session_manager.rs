@@ -0,0 +1,94 @@
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+use zbus::{
+    fdo, interface,
+    zvariant::{
+        ObjectPath, OwnedObjectPath, Type,
+        as_value::{self, optional},
+    },
+};
+
+use crate::screencast::{ScreenCastBackend, switch_source};
+use crate::session::{SESSIONS, close_session};
+
+#[derive(Clone, Debug, Type, Serialize, Deserialize)]
+#[zvariant(signature = "dict")]
+/// A portal session as seen by status bars and scripts
+pub struct SessionInfo {
+    #[serde(with = "as_value")]
+    handle: OwnedObjectPath,
+    #[serde(with = "as_value")]
+    app_id: String,
+    /// One of "screencast", "remote-desktop" or "input-capture"
+    #[serde(with = "as_value")]
+    session_type: String,
+    /// Outputs and windows, empty until the session is started
+    #[serde(with = "as_value")]
+    targets: Vec<String>,
+    /// Seconds since the unix epoch, missing until the session is started
+    #[serde(with = "optional", skip_serializing_if = "Option::is_none", default)]
+    start_time: Option<u64>,
+}
+
+/// Lets the user see and revoke the sessions apps hold, without going through the apps
+pub struct SessionManager;
+
+#[interface(name = "org.waycrate.Luminous.Sessions")]
+impl SessionManager {
+    #[zbus(property, name = "version")]
+    fn version(&self) -> u32 {
+        2
+    }
+
+    async fn list_sessions(&self) -> Vec<SessionInfo> {
+        SESSIONS
+            .lock()
+            .await
+            .iter()
+            .map(|session| SessionInfo {
+                handle: session.handle_path.clone(),
+                app_id: session.app_id.clone(),
+                session_type: session.session_type.name().to_owned(),
+                targets: session.targets.clone(),
+                start_time: session.started.map(|started| {
+                    started
+                        .duration_since(UNIX_EPOCH)
+                        .map(|time| time.as_secs())
+                        .unwrap_or_default()
+                }),
+            })
+            .collect()
+    }
+
+    /// Close any session, the app is told through the Closed signal of the session
+    async fn close_session(
+        &self,
+        handle: ObjectPath<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> fdo::Result<()> {
+        if !close_session(connection, handle.clone().into(), "Revoked").await {
+            return Err(fdo::Error::InvalidArgs(format!("No session at {handle}")));
+        }
+        tracing::info!("Session {handle} is revoked");
+        Ok(())
+    }
+
+    /// Let the user choose another output or window for a stream of a screencast, the app
+    /// keeps receiving the same PipeWire node
+    async fn switch_source(
+        &self,
+        handle: ObjectPath<'_>,
+        stream: u32,
+        #[zbus(object_server)] object_server: &zbus::ObjectServer,
+    ) -> fdo::Result<()> {
+        // The backend is not held while the chooser is open, it keeps serving other requests
+        let chooser = object_server
+            .interface::<_, ScreenCastBackend>("/org/freedesktop/portal/desktop")
+            .await?
+            .get()
+            .await
+            .source_chooser();
+        switch_source(chooser, handle.as_str(), stream as usize).await
+    }
+}
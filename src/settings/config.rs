@@ -17,6 +17,11 @@ const HIGHER_CONTRAST: &str = "higher";
 const DEFAULT_REDUCED_MOTION: &str = "default";
 const REDUCED_REDUCED_MOTION: &str = "reduced";
 const DEFAULT_BACKGROUND_PERMISSION: &str = "ask";
+const DEFAULT_SCREENCAST_MAX_FPS: u32 = 60;
+const DEFAULT_SCREENCAST_HIDDEN_WINDOW: &str = "last-frame";
+const DEFAULT_SCREENSHOT_FILENAME: &str = "Screenshot_%Y-%m-%d_%H-%M-%S";
+const DEFAULT_SCREENSHOT_FORMAT: &str = "png";
+const DEFAULT_SCREENSHOT_TARGET: &str = "all";
 
 #[derive(Deserialize, PartialEq, Eq, Debug)]
 pub struct SettingsConfig {
@@ -27,6 +32,17 @@ pub struct SettingsConfig {
     pub screenshot_permission_check: bool,
     pub remote_permission_check: bool,
     pub background_permission_default: String,
+    pub screencast_max_fps: u32,
+    pub screencast_hidden_window: String,
+    pub screencast_max_resolution: Option<(u32, u32)>,
+    pub excluded_apps: Vec<String>,
+    pub privacy_mode: bool,
+    pub screenshot_dir: Option<String>,
+    pub screenshot_filename: String,
+    pub screenshot_format: String,
+    pub screenshot_editor: bool,
+    pub screenshot_clipboard: bool,
+    pub screenshot_target: String,
 }
 
 #[derive(Deserialize, PartialEq, Eq, Debug)]
@@ -40,6 +56,38 @@ struct SettingsConfigRead {
     // This means only one screen
     pub remote_permission_check: Option<bool>,
     pub background_permission_default: Option<String>,
+    // upper bound of the framerate offered to screencast clients
+    pub screencast_max_fps: Option<u32>,
+    // what a window cast shows while the window cannot be captured, "last-frame" or "card"
+    pub screencast_hidden_window: Option<String>,
+    // largest size of a screencast stream like "1920x1080", larger sources are scaled down
+    pub screencast_max_resolution: Option<String>,
+    // app ids whose windows are blacked out in screenshots and casts. Finding the windows
+    // needs the sway ipc, on other compositors a non empty list makes screenshots fail and
+    // casts fully black
+    pub excluded_apps: Option<Vec<String>>,
+    // deny every screenshot, screencast and remote desktop request
+    pub privacy_mode: Option<bool>,
+    // where screenshots are saved, the xdg pictures dir by default
+    pub screenshot_dir: Option<String>,
+    // strftime template of the screenshot file name, "{app_id}" and "{counter}" are replaced
+    pub screenshot_filename: Option<String>,
+    // "png", "jpeg", "qoi" or "pnm"
+    pub screenshot_format: Option<String>,
+    // open the annotation editor after an interactive screenshot
+    pub screenshot_editor: Option<bool>,
+    // also put every screenshot on the clipboard as a png
+    pub screenshot_clipboard: Option<bool>,
+    // what a screenshot without the chooser shows, "all", "focused-output" or "active-output",
+    // the output sway has focused, which falls back to "focused-output" without the sway ipc
+    pub screenshot_target: Option<String>,
+}
+
+fn parse_resolution(resolution: &str) -> Option<(u32, u32)> {
+    let (width, height) = resolution.split_once('x')?;
+    let width: u32 = width.trim().parse().ok()?;
+    let height: u32 = height.trim().parse().ok()?;
+    (width > 0 && height > 0).then_some((width, height))
 }
 
 impl From<SettingsConfigRead> for SettingsConfig {
@@ -64,6 +112,50 @@ impl From<SettingsConfigRead> for SettingsConfig {
                 "deny" => "deny".to_string(),
                 _ => DEFAULT_BACKGROUND_PERMISSION.to_string(),
             },
+            screencast_max_fps: value
+                .screencast_max_fps
+                .unwrap_or(DEFAULT_SCREENCAST_MAX_FPS)
+                .max(1),
+            screencast_hidden_window: match value
+                .screencast_hidden_window
+                .unwrap_or(DEFAULT_SCREENCAST_HIDDEN_WINDOW.to_string())
+                .as_str()
+            {
+                "card" => "card".to_string(),
+                _ => DEFAULT_SCREENCAST_HIDDEN_WINDOW.to_string(),
+            },
+            screencast_max_resolution: value
+                .screencast_max_resolution
+                .as_deref()
+                .and_then(parse_resolution),
+            excluded_apps: value.excluded_apps.unwrap_or_default(),
+            privacy_mode: value.privacy_mode.unwrap_or(false),
+            screenshot_dir: value.screenshot_dir.filter(|dir| !dir.is_empty()),
+            screenshot_filename: value
+                .screenshot_filename
+                .filter(|filename| !filename.is_empty())
+                .unwrap_or(DEFAULT_SCREENSHOT_FILENAME.to_string()),
+            screenshot_format: match value
+                .screenshot_format
+                .unwrap_or(DEFAULT_SCREENSHOT_FORMAT.to_string())
+                .as_str()
+            {
+                "jpeg" | "jpg" => "jpeg".to_string(),
+                "qoi" => "qoi".to_string(),
+                "pnm" | "ppm" => "pnm".to_string(),
+                _ => DEFAULT_SCREENSHOT_FORMAT.to_string(),
+            },
+            screenshot_editor: value.screenshot_editor.unwrap_or(false),
+            screenshot_clipboard: value.screenshot_clipboard.unwrap_or(false),
+            screenshot_target: match value
+                .screenshot_target
+                .unwrap_or(DEFAULT_SCREENSHOT_TARGET.to_string())
+                .as_str()
+            {
+                "focused-output" => "focused-output".to_string(),
+                "active-output" => "active-output".to_string(),
+                _ => DEFAULT_SCREENSHOT_TARGET.to_string(),
+            },
         }
     }
 }
@@ -120,6 +212,17 @@ impl Default for SettingsConfig {
             screenshot_permission_check: true,
             remote_permission_check: true,
             background_permission_default: DEFAULT_BACKGROUND_PERMISSION.to_string(),
+            screencast_max_fps: DEFAULT_SCREENCAST_MAX_FPS,
+            screencast_hidden_window: DEFAULT_SCREENCAST_HIDDEN_WINDOW.to_string(),
+            screencast_max_resolution: None,
+            excluded_apps: Vec::new(),
+            privacy_mode: false,
+            screenshot_dir: None,
+            screenshot_filename: DEFAULT_SCREENSHOT_FILENAME.to_string(),
+            screenshot_format: DEFAULT_SCREENSHOT_FORMAT.to_string(),
+            screenshot_editor: false,
+            screenshot_clipboard: false,
+            screenshot_target: DEFAULT_SCREENSHOT_TARGET.to_string(),
         }
     }
 }
@@ -167,3 +270,30 @@ impl SettingsConfig {
         file_config.into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_resolution_reads_width_x_height() {
+        assert_eq!(parse_resolution("1920x1080"), Some((1920, 1080)));
+        assert_eq!(parse_resolution(" 1280 x 720 "), Some((1280, 720)));
+    }
+
+    #[test]
+    fn parse_resolution_rejects_invalid_sizes() {
+        for resolution in [
+            "",
+            "1920",
+            "1920x",
+            "x1080",
+            "0x1080",
+            "1920x0",
+            "-1x1",
+            "1920*1080",
+        ] {
+            assert_eq!(parse_resolution(resolution), None, "{resolution}");
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::LazyLock;
+
+use futures::{
+    SinkExt, StreamExt,
+    channel::mpsc::{Sender, UnboundedReceiver},
+};
+use tokio::sync::watch;
+use zbus::zvariant::OwnedObjectPath;
+
+use crate::dialog::Message;
+use crate::session::close_session;
+
+/// What a session does with the screen or the input devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharingKind {
+    ScreenCast,
+    RemoteDesktop,
+    Input,
+}
+
+impl SharingKind {
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::ScreenCast => "Sharing your screen",
+            Self::RemoteDesktop => "Controlling your desktop",
+            Self::Input => "Using your input devices",
+        }
+    }
+}
+
+/// A session the sharing indicator lists
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharingSession {
+    pub handle: String,
+    pub app_id: String,
+    pub kind: SharingKind,
+}
+
+static SHARING_SESSIONS: LazyLock<watch::Sender<Vec<SharingSession>>> =
+    LazyLock::new(|| watch::Sender::new(Vec::new()));
+
+pub fn add_sharing_session(session: SharingSession) {
+    SHARING_SESSIONS.send_modify(|sessions| sessions.push(session));
+}
+
+pub fn remove_sharing_session(handle: &str) {
+    SHARING_SESSIONS.send_if_modified(|sessions| {
+        let previous_len = sessions.len();
+        sessions.retain(|session| session.handle != handle);
+        sessions.len() != previous_len
+    });
+}
+
+/// Keep the indicator of the dialog in sync with the sharing sessions
+pub async fn forward_sharing_sessions(mut sender: Sender<Message>) {
+    let mut receiver = SHARING_SESSIONS.subscribe();
+    loop {
+        let sessions = receiver.borrow_and_update().clone();
+        if sender
+            .send(Message::SharingChanged(sessions))
+            .await
+            .is_err()
+        {
+            return;
+        }
+        if receiver.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+pub async fn route_stop_sharing_requests(
+    mut receiver: UnboundedReceiver<String>,
+    connection: zbus::Connection,
+) {
+    while let Some(handle) = receiver.next().await {
+        let handle_path = match OwnedObjectPath::try_from(handle) {
+            Ok(handle_path) => handle_path,
+            Err(e) => {
+                tracing::warn!("Cannot stop sharing, invalid session handle: {e}");
+                continue;
+            }
+        };
+        tracing::info!("The user stopped sharing for {handle_path}");
+        let _ = close_session(&connection, handle_path, "Stopped by the user").await;
+    }
+}
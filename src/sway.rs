@@ -0,0 +1,125 @@
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+pub(crate) const SWAY_RUN_COMMAND: u32 = 0;
+pub(crate) const SWAY_GET_OUTPUTS: u32 = 3;
+pub(crate) const SWAY_GET_TREE: u32 = 4;
+
+const SWAY_IPC_MAGIC: &[u8; 6] = b"i3-ipc";
+const SWAY_IPC_HEADER_SIZE: usize = 14;
+
+#[derive(Debug, Deserialize)]
+struct SwayCommandReply {
+    success: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// sway and other compositors speaking its IPC, the socket is taken as is so it can be faked.
+/// Every request blocks until sway answers, so async code calls it from a blocking task
+#[derive(Debug)]
+pub struct SwayIpc {
+    socket: PathBuf,
+}
+
+impl SwayIpc {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Self {
+            socket: socket.into(),
+        }
+    }
+
+    /// The IPC of the running sway, if there is one
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os("SWAYSOCK").map(Self::new)
+    }
+
+    pub(crate) fn request<T: DeserializeOwned>(
+        &self,
+        message_type: u32,
+        payload: &str,
+    ) -> anyhow::Result<T> {
+        let mut stream = UnixStream::connect(&self.socket)?;
+        let mut message = Vec::with_capacity(SWAY_IPC_HEADER_SIZE + payload.len());
+        message.extend_from_slice(SWAY_IPC_MAGIC);
+        message.extend_from_slice(&u32::try_from(payload.len())?.to_ne_bytes());
+        message.extend_from_slice(&message_type.to_ne_bytes());
+        message.extend_from_slice(payload.as_bytes());
+        stream.write_all(&message)?;
+
+        let mut header = [0; SWAY_IPC_HEADER_SIZE];
+        stream.read_exact(&mut header)?;
+        if &header[..6] != SWAY_IPC_MAGIC {
+            anyhow::bail!("Invalid sway ipc reply from {}", self.socket.display());
+        }
+        let length = u32::from_ne_bytes(header[6..10].try_into()?) as usize;
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    pub(crate) fn run_command(&self, command: &str) -> anyhow::Result<()> {
+        let replies: Vec<SwayCommandReply> = self.request(SWAY_RUN_COMMAND, command)?;
+        if let Some(reply) = replies.into_iter().find(|reply| !reply.success) {
+            anyhow::bail!(
+                "sway cannot run `{command}`: {}",
+                reply.error.unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    /// A socket speaking the sway IPC framing, `reply` answers each message by its type and
+    /// payload
+    pub(crate) fn fake_sway(
+        name: &str,
+        mut reply: impl FnMut(u32, String) -> String + Send + 'static,
+    ) -> PathBuf {
+        let socket =
+            std::env::temp_dir().join(format!("luminous-sway-{}-{name}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut header = [0; SWAY_IPC_HEADER_SIZE];
+                stream.read_exact(&mut header).unwrap();
+                assert_eq!(&header[..6], SWAY_IPC_MAGIC);
+                let length = u32::from_ne_bytes(header[6..10].try_into().unwrap()) as usize;
+                let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+                let mut payload = vec![0; length];
+                stream.read_exact(&mut payload).unwrap();
+
+                let body = reply(message_type, String::from_utf8(payload).unwrap());
+                let mut message = SWAY_IPC_MAGIC.to_vec();
+                message.extend_from_slice(&(body.len() as u32).to_ne_bytes());
+                message.extend_from_slice(&message_type.to_ne_bytes());
+                message.extend_from_slice(body.as_bytes());
+                stream.write_all(&message).unwrap();
+            }
+        });
+        socket
+    }
+
+    #[test]
+    fn failed_command_is_an_error() {
+        let socket = fake_sway("failed", |_, _| {
+            r#"[{"success":false,"error":"Unknown command"}]"#.to_owned()
+        });
+        let error = SwayIpc::new(&socket)
+            .run_command("output \"HEADLESS-1\" unplug")
+            .unwrap_err();
+        assert!(error.to_string().contains("Unknown command"));
+        let _ = std::fs::remove_file(socket);
+    }
+}
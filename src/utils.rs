@@ -10,6 +10,32 @@ pub static USER_RUNNING_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
     PathBuf::from(cache_dir)
 });
 
+/// The PICTURES entry of the xdg user dirs, `~/Pictures` when it is not set
+pub static XDG_PICTURES_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    let home = std::env::var("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| USER_RUNNING_DIR.clone());
+    if let Ok(pictures) = std::env::var("XDG_PICTURES_DIR")
+        && !pictures.is_empty()
+    {
+        return PathBuf::from(pictures);
+    }
+    XDG_CONFIG_HOME
+        .clone()
+        .and_then(|config_home| std::fs::read_to_string(config_home.join("user-dirs.dirs")).ok())
+        .and_then(|user_dirs| {
+            user_dirs.lines().find_map(|line| {
+                let pictures = line.trim().strip_prefix("XDG_PICTURES_DIR=")?;
+                let pictures = pictures.trim().trim_matches('"');
+                Some(match pictures.strip_prefix("$HOME") {
+                    Some(rest) => home.join(rest.trim_start_matches('/')),
+                    None => PathBuf::from(pictures),
+                })
+            })
+        })
+        .unwrap_or_else(|| home.join("Pictures"))
+});
+
 pub static HEADLESS_START: LazyLock<bool> = LazyLock::new(|| {
     if std::env::var("WLR_BACKENDS").is_ok_and(|v| v == "headless") {
         return true;
@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::sway::{SWAY_GET_OUTPUTS, SwayIpc};
+
+/// Creates and removes headless outputs through the IPC of a compositor
+pub trait VirtualOutputs: Send + Sync {
+    /// Returns the name of the new output, the same one its wl_output reports
+    fn create_output(&self) -> anyhow::Result<String>;
+    fn destroy_output(&self, name: &str) -> anyhow::Result<()>;
+}
+
+/// The IPC of the running compositor, if we know how to create outputs with it
+pub fn backend() -> Option<Arc<dyn VirtualOutputs>> {
+    if let Some(ipc) = SwayIpc::from_env() {
+        return Some(Arc::new(ipc));
+    }
+    None
+}
+
+/// A headless output which lives as long as this value
+pub struct VirtualOutput {
+    name: String,
+    backend: Arc<dyn VirtualOutputs>,
+}
+
+impl VirtualOutput {
+    pub fn create(backend: Arc<dyn VirtualOutputs>) -> anyhow::Result<Self> {
+        let name = backend.create_output()?;
+        tracing::info!("Created the virtual output {name}");
+        Ok(Self { name, backend })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// The IPC blocks, so on the executor the output is removed from a blocking task. Sessions
+/// drop it while holding their lock
+impl Drop for VirtualOutput {
+    fn drop(&mut self) {
+        let name = std::mem::take(&mut self.name);
+        let backend = self.backend.clone();
+        let destroy = move || match backend.destroy_output(&name) {
+            Ok(()) => tracing::info!("Removed the virtual output {name}"),
+            Err(e) => tracing::warn!("Cannot remove the virtual output {name}: {e}"),
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(destroy)),
+            Err(_) => destroy(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SwayOutput {
+    name: String,
+}
+
+fn output_names(ipc: &SwayIpc) -> anyhow::Result<HashSet<String>> {
+    let outputs: Vec<SwayOutput> = ipc.request(SWAY_GET_OUTPUTS, "")?;
+    Ok(outputs.into_iter().map(|output| output.name).collect())
+}
+
+impl VirtualOutputs for SwayIpc {
+    fn create_output(&self) -> anyhow::Result<String> {
+        // create_output does not tell the name, so look for the output which is new
+        let previous_outputs = output_names(self)?;
+        self.run_command("create_output")?;
+        output_names(self)?
+            .into_iter()
+            .find(|name| !previous_outputs.contains(name))
+            .ok_or_else(|| anyhow::anyhow!("sway created no new output"))
+    }
+
+    fn destroy_output(&self, name: &str) -> anyhow::Result<()> {
+        self.run_command(&format!("output \"{name}\" unplug"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    use crate::sway::SWAY_RUN_COMMAND;
+    use crate::sway::tests::fake_sway;
+
+    /// Keeps the outputs like sway and records the commands it runs
+    fn fake_outputs(name: &str) -> (PathBuf, Arc<Mutex<Vec<String>>>) {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let sent = commands.clone();
+        let mut outputs = vec!["eDP-1".to_owned()];
+        let socket = fake_sway(name, move |message_type, payload| match message_type {
+            SWAY_GET_OUTPUTS => serde_json::to_string(
+                &outputs
+                    .iter()
+                    .map(|name| serde_json::json!({ "name": name }))
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap(),
+            SWAY_RUN_COMMAND => {
+                if payload == "create_output" {
+                    outputs.push("HEADLESS-1".to_owned());
+                } else if payload == "output \"HEADLESS-1\" unplug" {
+                    outputs.retain(|name| name != "HEADLESS-1");
+                }
+                sent.lock().unwrap().push(payload);
+                r#"[{"success":true}]"#.to_owned()
+            }
+            _ => panic!("unexpected message type {message_type}"),
+        });
+        (socket, commands)
+    }
+
+    #[test]
+    fn create_output_finds_the_new_output() {
+        let (socket, commands) = fake_outputs("create");
+        let ipc = SwayIpc::new(&socket);
+        assert_eq!(ipc.create_output().unwrap(), "HEADLESS-1");
+        assert_eq!(*commands.lock().unwrap(), ["create_output"]);
+        let _ = std::fs::remove_file(socket);
+    }
+
+    #[test]
+    fn dropping_the_output_unplugs_it() {
+        let (socket, commands) = fake_outputs("unplug");
+        let output = VirtualOutput::create(Arc::new(SwayIpc::new(&socket))).unwrap();
+        assert_eq!(output.name(), "HEADLESS-1");
+        drop(output);
+        assert_eq!(
+            *commands.lock().unwrap(),
+            ["create_output", "output \"HEADLESS-1\" unplug"]
+        );
+        let _ = std::fs::remove_file(socket);
+    }
+
+    #[tokio::test]
+    async fn dropping_on_the_executor_unplugs_it_from_a_blocking_task() {
+        let (socket, commands) = fake_outputs("unplug-blocking");
+        let output = VirtualOutput::create(Arc::new(SwayIpc::new(&socket))).unwrap();
+        drop(output);
+        for _ in 0..100 {
+            if commands.lock().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            *commands.lock().unwrap(),
+            ["create_output", "output \"HEADLESS-1\" unplug"]
+        );
+        let _ = std::fs::remove_file(socket);
+    }
+}